@@ -0,0 +1,140 @@
+use std::{fmt, str::FromStr};
+
+use crate::VcvarsError;
+
+/// An architecture as named by vcvars, like in the `VSCMD_ARG_TGT_ARCH` variable. Used for both host and target architectures, like vcvars' names are. `Arch::Arm64Ec` is only a target architecture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Arch {
+    X86,
+    X64,
+    Arm,
+    Arm64,
+    /// ARM64EC, which vcvars doesn't distinguish from ARM64. The ARM64 environment is used, with the compiler option `/arm64EC`.
+    Arm64Ec,
+}
+
+impl Arch {
+    pub fn from_rust_arch(rust_arch: &str) -> Option<Self> {
+        #![must_use]
+        //! Maps an architecture name as used by Rust, like in [`std::env::consts::ARCH`] or the `CARGO_CFG_TARGET_ARCH` environment variable, to the vcvars architecture.
+
+        match rust_arch {
+            "x86" => Some(Self::X86),
+            "x86_64" => Some(Self::X64),
+            "arm" => Some(Self::Arm),
+            "aarch64" => Some(Self::Arm64),
+            "arm64ec" => Some(Self::Arm64Ec),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        #![must_use]
+        //! Returns the name as used by vcvars, like `x64`. `Arch::Arm64Ec` is named `arm64ec`.
+
+        match self {
+            Self::X86 => "x86",
+            Self::X64 => "x64",
+            Self::Arm => "arm",
+            Self::Arm64 => "arm64",
+            Self::Arm64Ec => "arm64ec",
+        }
+    }
+}
+
+impl FromStr for Arch {
+    type Err = VcvarsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        //! Parses a name as used by vcvars, like `x64`, case-insensitively. Surrounding whitespace is ignored.
+
+        match s.trim().to_lowercase().as_str() {
+            "x86" => Ok(Self::X86),
+            "x64" | "amd64" => Ok(Self::X64),
+            "arm" => Ok(Self::Arm),
+            "arm64" => Ok(Self::Arm64),
+            "arm64ec" => Ok(Self::Arm64Ec),
+            _ => Err(VcvarsError::InvalidArch(s.to_owned())),
+        }
+    }
+}
+
+impl fmt::Display for Arch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// An application platform as named by vcvars, like in the `VSCMD_ARG_app_plat` variable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AppPlatform {
+    Desktop,
+    Uwp,
+    OneCore,
+}
+
+impl AppPlatform {
+    pub fn as_str(self) -> &'static str {
+        #![must_use]
+        //! Returns the name as used by vcvars in the `VSCMD_ARG_app_plat` variable, like `UWP`.
+
+        match self {
+            Self::Desktop => "Desktop",
+            Self::Uwp => "UWP",
+            Self::OneCore => "OneCore",
+        }
+    }
+}
+
+impl FromStr for AppPlatform {
+    type Err = VcvarsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        //! Parses a name as used by vcvars, like `Desktop`, case-insensitively. Surrounding whitespace is ignored.
+
+        match s.trim().to_lowercase().as_str() {
+            "desktop" => Ok(Self::Desktop),
+            "uwp" => Ok(Self::Uwp),
+            "onecore" => Ok(Self::OneCore),
+            _ => Err(VcvarsError::InvalidAppPlatform(s.to_owned())),
+        }
+    }
+}
+
+impl fmt::Display for AppPlatform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AppPlatform, Arch};
+
+    #[test]
+    fn arch() {
+        assert_eq!("X64\r".parse::<Arch>().unwrap(), Arch::X64);
+        assert_eq!("arm64".parse::<Arch>().unwrap(), Arch::Arm64);
+        assert!("mips".parse::<Arch>().is_err());
+
+        assert_eq!(Arch::from_rust_arch("x86_64"), Some(Arch::X64));
+        assert_eq!(Arch::from_rust_arch("aarch64"), Some(Arch::Arm64));
+        assert_eq!(Arch::from_rust_arch("arm64ec"), Some(Arch::Arm64Ec));
+        assert_eq!(Arch::from_rust_arch("mips"), None);
+
+        assert_eq!(Arch::Arm64.to_string(), "arm64");
+    }
+
+    #[test]
+    fn app_platform() {
+        assert_eq!("UWP".parse::<AppPlatform>().unwrap(), AppPlatform::Uwp);
+        assert_eq!(
+            "Desktop".parse::<AppPlatform>().unwrap(),
+            AppPlatform::Desktop
+        );
+        assert!("Xbox".parse::<AppPlatform>().is_err());
+        assert_eq!(AppPlatform::Uwp.to_string(), "UWP");
+    }
+}
@@ -0,0 +1,183 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use itertools::Itertools;
+
+use crate::{split_path_list, vars, Arch, EnvMap, VcToolsVersion, VcvarsError};
+
+/// An immutable snapshot of the environment resolved by vcvars, as returned by `Vcvars::resolve()`. Cloning is cheap, since the variables are shared. Unlike `Vcvars`, it doesn't involve `OUT_DIR`.
+#[derive(Debug, Clone)]
+pub struct VcvarsEnvironment {
+    env_map: Arc<EnvMap>,
+    visual_studio_dir: Option<PathBuf>,
+    host_arch: Option<Arch>,
+    target_arch: Option<Arch>,
+    vc_tools_version: Option<VcToolsVersion>,
+}
+
+impl VcvarsEnvironment {
+    pub(crate) fn new(env_map: EnvMap, visual_studio_dir: Option<PathBuf>) -> Self {
+        //! Derives the metadata from the variables, where not given.
+
+        let parse_arch = |var_name: &str| env_map.get(&var_name.to_uppercase())?.parse().ok();
+        let host_arch = parse_arch(vars::VSCMD_ARG_HOST_ARCH);
+        let target_arch = parse_arch(vars::VSCMD_ARG_TGT_ARCH);
+
+        let vc_tools_version = match env_map.get(&vars::VC_TOOLS_VERSION.to_uppercase()) {
+            Some(version) => version.parse().ok(),
+            None => env_map
+                .get(&vars::VC_TOOLS_INSTALL_DIR.to_uppercase())
+                .and_then(|dir| Path::new(dir).file_name()?.to_str()?.parse().ok()),
+        };
+
+        let visual_studio_dir = visual_studio_dir.or_else(|| {
+            env_map
+                .get(vars::VSINSTALLDIR)
+                .map(|dir| PathBuf::from(dir.trim_end_matches('\\')))
+        });
+
+        Self {
+            env_map: Arc::new(env_map),
+            visual_studio_dir,
+            host_arch,
+            target_arch,
+            vc_tools_version,
+        }
+    }
+
+    pub fn get(&self, var_name: impl AsRef<str>) -> Result<&str, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Returns `var_name`'s value. The name is matched case-insensitively, as with `Vcvars::get()`.
+
+        let var_name = var_name.as_ref();
+
+        match self.get_opt(var_name) {
+            Some(value) => Ok(value),
+            None => Err(VcvarsError::VarNotFound(var_name.to_owned())),
+        }
+    }
+
+    pub fn get_opt(&self, var_name: impl AsRef<str>) -> Option<&str> {
+        #![must_use]
+        //! Like `get()`, but returns `None` if the variable isn't set.
+
+        self.env_map
+            .get(&var_name.as_ref().to_uppercase())
+            .map(String::as_str)
+    }
+
+    pub fn contains(&self, var_name: impl AsRef<str>) -> bool {
+        #![must_use]
+        //! Returns whether the variable is set.
+
+        self.get_opt(var_name).is_some()
+    }
+
+    pub fn get_paths(&self, var_name: impl AsRef<str>) -> Result<Vec<PathBuf>, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Like `get()`, but splits the value of a list-style variable like `INCLUDE`, `LIB`, `LIBPATH` or `PATH` into its paths, like `Vcvars::get_paths()`.
+
+        Ok(split_path_list(self.get(var_name)?))
+    }
+
+    pub fn include_dirs(&self) -> Result<Vec<PathBuf>, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Returns the paths from `INCLUDE` that represent existing directories, like `Vcvars::include_dirs()`.
+
+        let mut dirs = self.get_paths(vars::INCLUDE)?;
+        dirs.retain(|dir| dir.is_dir());
+
+        Ok(dirs)
+    }
+
+    pub fn lib_dirs(&self) -> Result<Vec<PathBuf>, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Returns the absolute paths from `LIB`, like `Vcvars::lib_dirs()`.
+
+        let mut dirs = self.get_paths(vars::LIB)?;
+        dirs.retain(|dir| dir.is_absolute());
+
+        Ok(dirs)
+    }
+
+    pub fn vars(&self) -> impl Iterator<Item = (&str, &str)> {
+        //! Returns an iterator over the key-value pairs, sorted by key, like `Vcvars::vars()`. The keys are uppercased.
+
+        self.env_map
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .sorted_unstable_by_key(|&(key, _)| key)
+    }
+
+    pub fn visual_studio_dir(&self) -> Option<&Path> {
+        #![must_use]
+        //! Returns the Visual Studio installation directory vcvars was run from.
+
+        self.visual_studio_dir.as_deref()
+    }
+
+    pub fn host_arch(&self) -> Option<Arch> {
+        #![must_use]
+        //! Returns the host architecture from `VSCMD_ARG_HOST_ARCH`, if set and known.
+
+        self.host_arch
+    }
+
+    pub fn target_arch(&self) -> Option<Arch> {
+        #![must_use]
+        //! Returns the target architecture from `VSCMD_ARG_TGT_ARCH`, if set and known.
+
+        self.target_arch
+    }
+
+    pub fn vc_tools_version(&self) -> Option<VcToolsVersion> {
+        #![must_use]
+        //! Returns the MSVC toolset version, determined like with `Vcvars::vc_tools_version()`, if available.
+
+        self.vc_tools_version
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::VcvarsEnvironment;
+    use crate::{Arch, VcToolsVersion};
+
+    #[test]
+    fn vcvars_environment() {
+        let environment = VcvarsEnvironment::new(
+            [
+                ("INCLUDE", r"C:\a;;C:\b;C:\a"),
+                ("VSCMD_ARG_HOST_ARCH", "x64"),
+                ("VSCMD_ARG_TGT_ARCH", "arm64"),
+                ("VCTOOLSINSTALLDIR", r"C:\VS\VC\Tools\MSVC\14.38.33130\"),
+                ("VSINSTALLDIR", r"C:\VS\"),
+            ]
+            .map(|(key, value)| (key.to_owned(), value.to_owned()))
+            .into(),
+            None,
+        );
+        let clone = environment.clone();
+
+        assert_eq!(clone.get("vscmd_arg_tgt_arch").unwrap(), "arm64");
+        assert!(clone.get("MISSING").is_err());
+        assert!(!clone.contains("MISSING"));
+        assert_eq!(
+            clone.get_paths("INCLUDE").unwrap(),
+            [PathBuf::from(r"C:\a"), PathBuf::from(r"C:\b")]
+        );
+        assert_eq!(clone.vars().next(), Some(("INCLUDE", r"C:\a;;C:\b;C:\a")));
+
+        assert_eq!(clone.visual_studio_dir(), Some(Path::new(r"C:\VS")));
+        assert_eq!(clone.host_arch(), Some(Arch::X64));
+        assert_eq!(clone.target_arch(), Some(Arch::Arm64));
+        assert_eq!(
+            clone.vc_tools_version(),
+            Some(VcToolsVersion::new(14, 38, 33130))
+        );
+    }
+}
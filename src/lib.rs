@@ -1,34 +1,199 @@
 #![cfg(target_os = "windows")]
 #![warn(clippy::pedantic)]
 
-use std::{borrow::Cow, collections::HashMap, env, fs, io, mem, path::PathBuf, process::Command};
+use std::{
+    borrow::Cow,
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+    env,
+    ffi::OsString,
+    fmt, fs, io,
+    os::windows::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    process::{self, Command},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant, UNIX_EPOCH},
+};
 
 use filenamify::filenamify;
 use itertools::Itertools;
 use thiserror::Error;
 
+pub mod vars;
+
+mod arch;
+mod environment;
+mod registry;
+mod version;
+mod vswhere;
+
+pub use arch::{AppPlatform, Arch};
+pub use environment::VcvarsEnvironment;
+pub use vars::WellKnownVar;
+pub use version::{SdkVersion, VcToolsVersion, VsCmdVersion, VsInstallationVersion, VsVersion};
+pub use vswhere::{InstallationInfo, VswhereOptions, VswhereQuery};
+
+/// The name of the environment variable that overrides the path of `vcvarsall.bat`, or of `VsDevCmd.bat` with `Vcvars::use_vsdevcmd()`, for machines with unusual layouts. If set, the file is run directly, without finding Visual Studio with `vswhere.exe`, and without reusing the environment of a Developer Command Prompt (see `Vcvars::reuse_ambient_env()`). It must be an existing `.bat` or `.cmd` file, or else running vcvars fails with `VcvarsError::InvalidVcvarsallOverride`. Doesn't apply with `Vcvars::from_ewdk()`.
+///
+/// Build scripts should make Cargo rerun them if it changes:
+///
+/// ```ignore
+/// println!("cargo:rerun-if-env-changed={}", vcvars::VCVARSALL_ENV_VAR);
+/// ```
+pub const VCVARSALL_ENV_VAR: &str = "VCVARS_RS_VCVARSALL";
+
 type EnvMap = HashMap<String, String>;
 
+/// The maximum length of the cache directory name of a configuration, to stay within path length limits.
+const MAX_CACHE_KEY_LEN: usize = 64;
+/// How long to wait for another process to finish writing the cache files, before running vcvars anyway.
+const CACHE_LOCK_TIMEOUT: Duration = Duration::from_secs(30);
+type InstallationSelector<'a> = Arc<dyn Fn(&InstallationInfo) -> bool + Send + Sync + 'a>;
+
+pub fn include_paths() -> Result<Vec<PathBuf>, VcvarsError> {
+    #![allow(clippy::missing_errors_doc)]
+    //! Quick way to obtain the paths from vcvars' `INCLUDE` with a default [`Vcvars`], using its `get_cached_paths()`. Use `Vcvars` directly for configurability.
+    //!
+    //! ```ignore
+    //! cc::Build::new()
+    //!     .file("src/demo.c")
+    //!     .includes(vcvars::include_paths().unwrap())
+    //!     .compile("demo");
+    //! ```
+    //!
+    //! # Panics
+    //!
+    //! See [`Vcvars::get_cached()`].
+
+    Vcvars::new().get_cached_paths(vars::INCLUDE)
+}
+
+pub fn lib_paths() -> Result<Vec<PathBuf>, VcvarsError> {
+    #![allow(clippy::missing_errors_doc)]
+    //! Quick way to obtain the paths from vcvars' `LIB` with a default [`Vcvars`], using its `get_cached_paths()`. Use `Vcvars` directly for configurability.
+    //!
+    //! ```ignore
+    //! for path in vcvars::lib_paths().unwrap() {
+    //!     println!("cargo:rustc-link-search=native={}", path.display());
+    //! }
+    //! ```
+    //!
+    //! # Panics
+    //!
+    //! See [`Vcvars::get_cached()`].
+
+    Vcvars::new().get_cached_paths(vars::LIB)
+}
+
+#[derive(Clone)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct Vcvars<'a> {
     env_map: Option<EnvMap>,
+    /// The vcvars env vars read from or written to the cache file by `get_cached()`.
+    cached_env_map: Option<EnvMap>,
+    /// The env vars of the current process at the time vcvars was run, with uppercased keys.
+    parent_env_map: Option<EnvMap>,
+    /// The Visual Studio installation directory, once found.
+    visual_studio_dir: Option<PathBuf>,
+    /// The path of `vcvarsall.bat`, once found.
+    vcvarsall_path: Option<PathBuf>,
+    /// The `cmd.exe` command line used to run vcvars, once determined.
+    command_line: Option<String>,
+    /// The architecture argument vcvars was successfully run with.
+    arch_arg: Option<String>,
+    /// The host architecture among the candidates whose tools vcvars was successfully run for, to be cached by `get_cached()`.
+    run_host_arch: Option<Arch>,
+    /// Whether to retain the `cmd.exe` stdout output even if vcvars succeeded.
+    keep_raw_output: bool,
+    /// The `cmd.exe` stdout output, if retained.
+    raw_output: Option<String>,
+    /// Memory cache of `msbuild_path()`.
+    msbuild_path: Option<PathBuf>,
+    /// Memory cache of `vswhere_find()`, by pattern.
+    vswhere_find_results: HashMap<String, Vec<PathBuf>>,
+    /// Memory cache of `installation()`.
+    installation: Option<InstallationInfo>,
+    /// Memory cache of `installed_sdks()`.
+    installed_sdks: Option<Vec<SdkVersion>>,
     /// Arguments to `vswhere.exe` that substitute the regular argument `-latest`.
     vswhere_latest_substitute_args: Option<&'a [&'a str]>,
+    /// The Visual Studio installation directory to use instead of running `vswhere.exe`.
+    installation_dir: Option<PathBuf>,
+    /// The root directory of an Enterprise WDK, whose `SetupBuildEnv.cmd` to run instead of vcvars.
+    ewdk_root: Option<PathBuf>,
+    /// The path of `vswhere.exe` to use instead of the one in the Visual Studio Installer directory.
+    vswhere_path: Option<PathBuf>,
+    /// The path of the `vswhere.exe` that was run.
+    used_vswhere_path: Option<PathBuf>,
+    /// Whether to pass `-prerelease` to `vswhere.exe`.
+    include_prereleases: bool,
+    /// Whether to pass `-legacy` to `vswhere.exe`.
+    include_legacy: bool,
+    /// Whether to fall back to Visual Studio 2015 in its default directory, if no newer installation is found.
+    allow_vs2015: bool,
+    /// The product IDs to pass to `vswhere.exe` with `-products` instead of `*`.
+    products: Option<&'a [&'a str]>,
+    /// The component IDs to pass to `vswhere.exe` with `-requires`.
+    requires: Option<&'a [&'a str]>,
+    /// Whether to pass `-requiresAny` to `vswhere.exe`.
+    requires_any: bool,
+    /// The version range to pass to `vswhere.exe` with `-version`.
+    version_range: Option<&'a str>,
+    /// The predicate set with `select_installation()`.
+    installation_selector: Option<InstallationSelector<'a>>,
+    /// How to select the installation.
+    selection_strategy: SelectionStrategy,
+    /// Whether to fail if `vswhere.exe` returns several installations without this crate selecting one.
+    fail_on_ambiguous_installation: bool,
+    /// How the installation was selected, for `selection_log()`.
+    selection_log: Option<String>,
+    /// The Windows SDK version to pass to vcvars.
+    sdk_version: Option<SdkVersion>,
+    /// The MSVC toolset version to pass to vcvars.
+    toolset_version: Option<&'a str>,
+    /// Whether to make vcvars use the Spectre-mitigated libraries.
+    spectre_libs: bool,
+    /// The application platform to pass to vcvars.
+    app_platform: Option<AppPlatform>,
+    /// Arguments to `vcvarsall.bat` that are appended to the ones determined by `Vcvars`.
+    extra_vcvarsall_args: Option<&'a [&'a str]>,
+    /// The target architecture to use instead of the one from `CARGO_CFG_TARGET_ARCH`.
+    target_arch: Option<Arch>,
+    /// The host architecture of the tools to use instead of the one of the current process.
+    host_arch: Option<Arch>,
+    /// The options for running `VsDevCmd.bat` instead of `vcvarsall.bat`, if it should be.
+    vsdevcmd_options: Option<VsDevCmdOptions>,
+    /// Env vars to set for the `cmd.exe` child process.
+    child_env: Vec<(String, String)>,
+    /// Whether to let vcvars send telemetry data.
+    telemetry: bool,
+    /// Whether to run vcvars with a minimal set of env vars instead of the ones of the current process.
+    clean_env: bool,
+    /// Whether to use the env vars of the current process instead of running vcvars, if they're already from a matching vcvars run.
+    reuse_ambient_env: bool,
+    /// Whether to prefer the installation named by the `VSINSTALLDIR` env var of the current process.
+    prefer_ambient_installation: bool,
+    /// Whether to prefer native or cross host tools.
+    toolchain_preference: ToolchainPreference,
 }
 
 impl<'a> Vcvars<'a> {
     //! Runs vcvars in a `cmd.exe` child process (at most once) and makes available the set of environment variables the child process inherited, mutated by vcvars. The `cmd.exe` stdout output is converted with [`std::string::String::from_utf8_lossy()`].
     //!
-    //! Use [`std::env::split_paths()`] to split a variable like `INCLUDE`, which could then, e.g., be passed to [`cc::Build::includes()`].
+    //! Use `get_cached_paths()` to split a variable like `INCLUDE`, or `include_dirs()`, whose return value could then, e.g., be passed to [`cc::Build::includes()`].
     //!
     //! # Example
     //!
     //! ```ignore
     //! let mut vcvars = Vcvars::new();
-    //! let vcvars_include = vcvars.get_cached("INCLUDE").unwrap();
     //!
     //! cxx_build::bridge("src/demo.rs")
     //!     .file("src/demo.cc")
-    //!     .includes(env::split_paths(&*vcvars_include))
+    //!     .includes(vcvars.include_dirs().unwrap())
     //!     .compile("demo");
     //! ```
 
@@ -38,311 +203,6727 @@ impl<'a> Vcvars<'a> {
 
         Self {
             env_map: None,
+            cached_env_map: None,
+            parent_env_map: None,
+            visual_studio_dir: None,
+            vcvarsall_path: None,
+            command_line: None,
+            arch_arg: None,
+            run_host_arch: None,
+            keep_raw_output: false,
+            raw_output: None,
+            msbuild_path: None,
+            vswhere_find_results: HashMap::new(),
+            installation: None,
+            installed_sdks: None,
             vswhere_latest_substitute_args: None,
+            installation_dir: None,
+            ewdk_root: None,
+            vswhere_path: None,
+            used_vswhere_path: None,
+            include_prereleases: true,
+            include_legacy: false,
+            allow_vs2015: false,
+            products: None,
+            requires: None,
+            requires_any: false,
+            version_range: None,
+            installation_selector: None,
+            selection_strategy: SelectionStrategy::NewestInstallation,
+            fail_on_ambiguous_installation: false,
+            selection_log: None,
+            sdk_version: None,
+            toolset_version: None,
+            spectre_libs: false,
+            app_platform: None,
+            extra_vcvarsall_args: None,
+            target_arch: None,
+            host_arch: None,
+            vsdevcmd_options: None,
+            child_env: Vec::new(),
+            telemetry: false,
+            clean_env: false,
+            reuse_ambient_env: true,
+            prefer_ambient_installation: true,
+            toolchain_preference: ToolchainPreference::PreferNative,
         }
     }
 
-    pub fn not_vswhere_latest_but(mut self, substitute_args: &'a [&'a str]) -> Self {
+    pub fn from_ewdk(root: impl Into<PathBuf>) -> Self {
         #![must_use]
-        //! Microsoft's [`vswhere.exe`](https://github.com/microsoft/vswhere) that locates your Visual Studio installation is normally called with the argument `-latest`. If you need different arguments *instead of it*, you can pass them here. It may well be that there can be a better solution than calling this function that would involve the Rust `Vcvars` type to be adapted. The method is provided as a means to be able to quickly solve problems regarding `vswhere`.
+        //! Creates an instance that obtains the environment from an Enterprise WDK (EWDK) instead of a Visual Studio installation, like from a mounted EWDK ISO at `root`, by running `BuildEnv\SetupBuildEnv.cmd` in it the same way vcvars is run. Neither `vswhere.exe` nor an installed Visual Studio is needed then. The target architecture is passed as `SetupBuildEnv.cmd` expects it, like `amd64`, and only the native host tools are used.
         //!
-        //! ```
-        //! let mut vcvars = Vcvars::new()
-        //!     .not_vswhere_latest_but(["-version", "[15.0,16.0)"]);
-        //! ```
+        //! Options that are passed to `vcvarsall.bat`, like `sdk_version()` or `toolset_version()`, can't be combined with this, nor can `use_vsdevcmd()` and `with_installation_dir()`. Methods that rely on `vswhere.exe`, like `installation()`, still run it.
         //!
-        //! Run `vswhere -help` on the command line for more information.
+        //! ```ignore
+        //! println!("cargo:rerun-if-env-changed=EWDK_ROOT");
+        //!
+        //! let mut vcvars = match std::env::var_os("EWDK_ROOT") {
+        //!     Some(root) => Vcvars::from_ewdk(root),
+        //!     None => Vcvars::new(),
+        //! };
+        //! ```
 
-        self.vswhere_latest_substitute_args = Some(substitute_args);
+        Self {
+            ewdk_root: Some(root.into()),
+            ..Self::new()
+        }
+    }
 
-        self
+    pub fn needed() -> bool {
+        #![must_use]
+        //! Returns whether the Cargo target uses the MSVC toolchain, as opposed to, e.g., `x86_64-pc-windows-gnu`, for which the vcvars environment is irrelevant. Determined from the environment variable `CARGO_CFG_TARGET_ENV` set by Cargo for build scripts. Returns `true` if it isn't set.
+        //!
+        //! ```ignore
+        //! if Vcvars::needed() {
+        //!     let mut vcvars = Vcvars::new();
+        //!     // ...
+        //! }
+        //! ```
+
+        match env::var("CARGO_CFG_TARGET_ENV") {
+            Ok(target_env) => target_env == "msvc",
+            Err(_) => true,
+        }
     }
 
-    pub fn get_cached(&mut self, var_name: &str) -> Result<Cow<str>, VcvarsError> {
+    pub fn list_installations(
+        options: &VswhereOptions,
+    ) -> Result<Vec<InstallationInfo>, VcvarsError> {
         #![allow(clippy::missing_errors_doc)]
-        //! Reads the `OUT_DIR` environment variable that Cargo sets and obtains `var_name`'s value from a cache file. If the file isn't present, runs vcvars and creates a memory cache of its variables, if not done previously, to source the value from and creates the cache file. Then returns the value.
-        //!
-        //! The cache files are named after the variables. The filenames are sanitized to be legal on all platforms. Should this result in two variables getting the same filename, there will be incorrect behavior. (See <https://github.com/chawyehsu/filenamify-rs/blob/main/src/lib.rs>.)
-        //!
-        //! # Panics
+        //! Runs `vswhere.exe` with `-all` to list all Visual Studio installations matching `options`, sorted by ascending version, without running vcvars. Unlike with `installation()`, incomplete installations are included, which are flagged by `InstallationInfo::is_complete`. If there are no installations, the returned `Vec` is empty.
         //!
-        //! Panics if the `OUT_DIR` environment variable isn't set or doesn't represent an existing directory.
+        //! ```ignore
+        //! for installation in Vcvars::list_installations(&VswhereOptions::default()).unwrap() {
+        //!     println!("{} ({})", installation.display_name, installation.installation_version);
+        //! }
+        //! ```
 
-        // Find Cargo output directory.
-        let cargo_out_dir = PathBuf::from(
-            &env::var("OUT_DIR").expect("env var `OUT_DIR` should've been set by Cargo"),
-        );
-        assert!(
-            cargo_out_dir.is_dir(),
-            "env var `OUT_DIR` should be a valid directory path"
-        );
+        if let Some(range) = &options.version_range {
+            if !is_version_range(range) {
+                return Err(VcvarsError::InvalidConfiguration(format!(
+                    "version range `{range}` isn't a version like `17.0` or a range like `[16.0,18.0)`"
+                )));
+            }
+        }
 
-        // Create cache directory.
-        let mut cache_dir = cargo_out_dir;
-        cache_dir.push("vcvars-cache");
-        if let Err(err) = fs::create_dir_all(&cache_dir) {
-            return Err(VcvarsError::CacheFailed(
-                cache_dir.to_string_lossy().into_owned(),
-                err,
+        if options.requires_any && options.requires.is_empty() {
+            return Err(VcvarsError::InvalidConfiguration(
+                "`requires_any` was enabled without components in `requires`".to_owned(),
             ));
         }
 
-        // Read, or prepare and write cache file.
-        let mut cache_file = cache_dir;
-        cache_file.push(filenamify(format!("{var_name}.txt")));
+        if options.include_legacy && !(options.products.is_empty() && options.requires.is_empty()) {
+            return Err(VcvarsError::InvalidConfiguration(
+                "`include_legacy` can't be combined with `products` or `requires`".to_owned(),
+            ));
+        }
 
-        if cache_file.exists() {
-            match fs::read_to_string(&cache_file) {
-                Ok(value) => Ok(Cow::Owned(value)),
-                Err(err) => Err(VcvarsError::CacheFailed(
-                    cache_file.to_string_lossy().into_owned(),
-                    err,
-                )),
-            }
+        let vswhere_path = vswhere::vswhere_path(options.vswhere_path.as_deref())?;
+        vswhere::check_version(&vswhere_path)?;
+
+        let mut command = Command::new(&vswhere_path);
+        command.arg("-all");
+        if options.include_prereleases {
+            command.arg("-prerelease");
+        }
+        if options.include_legacy {
+            command.arg("-legacy");
+        } else if options.products.is_empty() {
+            command.args(["-products", "*"]);
         } else {
-            match self.ensure_env_map()?.get(&var_name.to_uppercase()) {
-                Some(value) => match fs::write(&cache_file, value) {
-                    Ok(()) => Ok(Cow::Borrowed(value)),
-                    Err(err) => Err(VcvarsError::CacheFailed(
-                        cache_file.to_string_lossy().into_owned(),
-                        err,
-                    )),
-                },
-                None => Err(VcvarsError::VarNotFound(var_name.to_owned())),
-            }
+            command.arg("-products").args(&options.products);
+        }
+        if !options.requires.is_empty() {
+            command.arg("-requires").args(&options.requires);
+        }
+        if options.requires_any {
+            command.arg("-requiresAny");
         }
+        if let Some(range) = &options.version_range {
+            command.args(["-version", range]);
+        }
+
+        let mut installations = match vswhere::query_installations(&vswhere_path, command) {
+            Ok(installations) => installations,
+            Err(VcvarsError::NoVisualStudioFound(..)) => Vec::new(),
+            Err(err) => return Err(err),
+        };
+        installations.sort_by_cached_key(|installation| {
+            vswhere::version_parts(&installation.installation_version)
+        });
+
+        Ok(installations)
     }
 
-    pub fn get(&mut self, var_name: &str) -> Result<&str, VcvarsError> {
-        #![allow(clippy::missing_errors_doc)]
-        //! Runs vcvars and creates a memory cache of its variables, if not done previously, and returns `var_name`'s value.
+    pub fn not_vswhere_latest_but(mut self, substitute_args: &'a [&'a str]) -> Self {
+        #![must_use]
+        //! Microsoft's [`vswhere.exe`](https://github.com/microsoft/vswhere) that locates your Visual Studio installation is normally called with the argument `-latest`. If you need different arguments *instead of it*, you can pass them here. It may well be that there can be a better solution than calling this function that would involve the Rust `Vcvars` type to be adapted. The method is provided as a means to be able to quickly solve problems regarding `vswhere`. Prefer `vswhere_query()` for the arguments it supports.
         //!
-        //! For productive use, it's recommended to use `get_cached()` instead, so follow-up build script runs are significantly sped up.
+        //! ```
+        //! let mut vcvars = Vcvars::new()
+        //!     .not_vswhere_latest_but(["-version", "[15.0,16.0)"]);
+        //! ```
+        //!
+        //! Run `vswhere -help` on the command line for more information.
+        //!
+        //! If `vswhere.exe` returns several installations due to the arguments, the first one is used, which is noted in `selection_log()`, unless `fail_on_ambiguous_installation()` is set.
 
-        match self.ensure_env_map()?.get(&var_name.to_uppercase()) {
-            Some(value) => Ok(value),
-            None => Err(VcvarsError::VarNotFound(var_name.to_owned())),
-        }
+        self.vswhere_latest_substitute_args = Some(substitute_args);
+
+        self
     }
 
-    fn ensure_env_map(&mut self) -> Result<&EnvMap, VcvarsError> {
-        if self.env_map.is_none() {
-            self.env_map = Some(Self::make_env_map(self)?);
-        };
+    pub fn vswhere_query(mut self, query: VswhereQuery<'a>) -> Self {
+        #![must_use]
+        //! Sets the arguments `vswhere.exe` is run with to select the Visual Studio installation in a typed way. The options set in `query` override the ones set with `include_prereleases()`, `products()`, `requires()` and `version_range()`, which have the same effect, and `VswhereQuery::all()` is the same as `not_vswhere_latest_but(&["-all"])`.
+        //!
+        //! ```ignore
+        //! let mut vcvars = Vcvars::new().vswhere_query(VswhereQuery::new().version_range("[15.0,16.0)"));
+        //! ```
 
-        Ok(self.env_map.as_ref().unwrap())
+        if let Some(enabled) = query.prerelease {
+            self.include_prereleases = enabled;
+        }
+        if query.products.is_some() {
+            self.products = query.products;
+        }
+        if query.requires.is_some() {
+            self.requires = query.requires;
+        }
+        if query.version_range.is_some() {
+            self.version_range = query.version_range;
+        }
+        if let Some(all) = query.all {
+            self.vswhere_latest_substitute_args = all.then_some(&["-all"]);
+        }
+
+        self
     }
 
-    fn make_env_map(&mut self) -> Result<EnvMap, VcvarsError> {
-        #![allow(clippy::too_many_lines)] //TODO
+    pub fn with_installation_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        #![must_use]
+        //! Makes vcvars be run from the Visual Studio installation in `dir`, like `C:\Program Files\Microsoft Visual Studio\2022\BuildTools`, without running `vswhere.exe` to find one. This way, neither `vswhere.exe` nor the `PROGRAMFILES(X86)` environment variable is needed. The options selecting the installation, like `products()` or `select_installation()`, are then ignored, while methods that rely on `vswhere.exe` anyway, like `installation()` and `msbuild_path()`, still run it.
+        //!
+        //! If `dir` doesn't exist, running vcvars fails with `VcvarsError::FileNotFound`.
+        //!
+        //! ```ignore
+        //! println!("cargo:rerun-if-env-changed=VS_INSTALL_DIR");
+        //!
+        //! let mut vcvars = Vcvars::new();
+        //! if let Some(dir) = std::env::var_os("VS_INSTALL_DIR") {
+        //!     vcvars = vcvars.with_installation_dir(dir);
+        //! }
+        //! ```
 
-        // Read env var dependencies.
-        let Ok(program_files_x86_dir) = env::var("PROGRAMFILES(X86)") else {
-            return Err(VcvarsError::MissingEnvVarDependency(
-                "PROGRAMFILES(X86)".to_owned(),
-            ));
-        };
+        self.installation_dir = Some(dir.into());
 
-        let Ok(win_dir) = env::var("WINDIR") else {
-            return Err(VcvarsError::MissingEnvVarDependency("WINDIR".to_owned()));
-        };
+        self
+    }
 
-        let Ok(target_arch) = env::var("CARGO_CFG_TARGET_ARCH") else {
-            return Err(VcvarsError::MissingEnvVarDependency("CARGO_CFG_TARGET_ARCH".to_owned()));
-        };
+    pub fn vswhere_path(mut self, path: impl Into<PathBuf>) -> Self {
+        #![must_use]
+        //! Makes `path` be used as the path of `vswhere.exe`. Otherwise, the one in the Visual Studio Installer directory in the x86 program files directory is used, if it exists, or else the one in the per-user Installer directory `%LOCALAPPDATA%\Microsoft\VisualStudio\Installer` of per-user installations, or else a standalone copy, like from Chocolatey or winget, found in the `PATH` directories or the Chocolatey shim directory, with the extensions from `PATHEXT`. If none is found, running `vswhere.exe` fails with `VcvarsError::VswhereNotFound`, listing the probed locations. Running vcvars then falls back to the Visual Studio 2017 and earlier installations registered in the registry key `HKEY_LOCAL_MACHINE\SOFTWARE\Microsoft\VisualStudio\SxS\VS7`, unless options selecting the installation, like `products()`, are set, and fails with `VcvarsError::VisualStudioNotFound` if there is none either.
+        //!
+        //! The x86 program files directory is named by the `PROGRAMFILES(X86)` environment variable. If it isn't set, it's derived from `ProgramW6432` or `ProgramFiles`, and `C:\Program Files (x86)` is probed last.
+        //!
+        //! If `path` doesn't exist, running `vswhere.exe` fails with `VcvarsError::FileNotFound`.
 
-        // Find `vswhere`.
-        let mut vswhere_path = PathBuf::from(program_files_x86_dir);
-        vswhere_path.push("Microsoft Visual Studio");
-        vswhere_path.push("Installer");
-        vswhere_path.push("vswhere.exe");
+        self.vswhere_path = Some(path.into());
 
-        // Note: Microsoft says about the `vswhere` path: "This is a fixed location that will be maintained." (https://github.com/Microsoft/vswhere/wiki/Installing)
+        self
+    }
 
-        if !vswhere_path.is_file() {
-            return Err(VcvarsError::FileNotFound(
-                vswhere_path.to_string_lossy().into_owned(),
-            ));
-        }
+    pub fn include_prereleases(mut self, enabled: bool) -> Self {
+        #![must_use]
+        //! Sets whether `vswhere.exe` considers prerelease installations like Visual Studio Preview, which it does by default. Since `-latest` selects the installation with the highest version, a Preview installation is normally selected over a stable installation of the same or a lower major version. Disable this for builds that should reproducibly use the stable installation.
+        //!
+        //! The option is independent of `not_vswhere_latest_but()`, so prerelease installations are also considered or excluded with the substitute arguments.
 
-        // Find Visual Studio.
-        let visual_studio_dir = match Command::new(&vswhere_path)
-            .arg("-prerelease") // Allow Visual Studio Preview.
-            .args(mem::take(&mut self.vswhere_latest_substitute_args).unwrap_or(&["-latest"]))
-            .args(["-property", "installationPath", "-utf8"])
-            .output()
-        {
-            Ok(output) => {
-                let dir = String::from_utf8(output.stdout)
-                    .expect("`vswhere.exe` with `-utf8` switch should've returned valid UTF-8");
+        self.include_prereleases = enabled;
 
-                dir.trim().to_owned()
-            }
-            Err(err) => {
-                return Err(VcvarsError::CouldntRun(
-                    vswhere_path.to_string_lossy().into_owned(),
-                    err,
-                ));
-            }
-        };
+        self
+    }
 
-        // Find vcvars and determine its args.
-        let mut vcvars_path = PathBuf::from(visual_studio_dir);
-        vcvars_path.push("VC");
-        vcvars_path.push("Auxiliary");
-        vcvars_path.push("Build");
-        vcvars_path.push("vcvarsall.bat");
+    pub fn include_legacy(mut self, enabled: bool) -> Self {
+        #![must_use]
+        //! Makes `vswhere.exe` also consider Visual Studio 2015 and earlier installations, which are registered in the registry instead of with the Visual Studio Installer, by passing `-legacy`. For such installations, `vcvarsall.bat` is run from the `VC` directory instead of `VC\Auxiliary\Build`, and some fields of `InstallationInfo` are empty. Options of newer vcvars versions, like `toolset_version()`, aren't supported by their `vcvarsall.bat`.
+        //!
+        //! Since `vswhere.exe` doesn't support `-legacy` together with `-products` and `-requires`, `-products *` isn't passed in this mode, and setting `products()` or `requires()` makes `validate()` fail with `VcvarsError::InvalidConfiguration`.
 
-        if !vcvars_path.is_file() {
-            return Err(VcvarsError::FileNotFound(
-                vcvars_path.to_string_lossy().into_owned(),
-            ));
-        }
+        self.include_legacy = enabled;
 
-        let vcvars_path = vcvars_path.to_str().unwrap(); // Built from valid UTF-8.
+        self
+    }
 
-        // Note: Usage documented here: https://learn.microsoft.com/en-us/cpp/build/building-on-the-command-line?view=msvc-170#vcvarsall-syntax.
+    pub fn allow_vs2015(mut self, enabled: bool) -> Self {
+        #![must_use]
+        //! Makes running vcvars fall back to Visual Studio 2015 if no newer installation is found, without relying on `vswhere.exe`. The installation directory is derived from the `VS140COMNTOOLS` environment variable, if set, or else `Microsoft Visual Studio 14.0` in the x86 program files directory is probed. Its `VC\vcvarsall.bat` is then run with the architecture arguments of that version, like `amd64` and `x86_amd64` instead of `x64` and `x86_x64`. ARM64 isn't supported by Visual Studio 2015.
+        //!
+        //! Since the `vcvarsall.bat` of Visual Studio 2015 doesn't support `-vcvars_ver`, `toolset_version()` and `sdk_version()` aren't supported for such an installation. Use `include_legacy()` to find Visual Studio 2015 with `vswhere.exe` instead.
 
-        let arch_arg = match env::consts::ARCH /* host architecture */ {
-            "x86" => match target_arch.as_str() {
-                "x86" => Some("x86"),
-                "x86_64" => Some("x86_x64"),
-                "arm" => Some("x86_arm"),
-                "aarch64" => Some("x86_arm64"),
-                _ => None,
-            },
-            "x86_64" => match target_arch.as_str() {
-                "x86" => Some("x64_x86"),       // Or `Some("x86")`? Usage table not clear.
-                "x86_64" => Some("x64"),        // Or `Some("x86_x64")`? Usage table not clear.
-                "arm" => Some("x64_arm"),       // Or `Some("x86_arm")`? Usage table not clear.
-                "aarch64" => Some("x64_arm64"), // Or `Some("x86_arm64")`? Usage table not clear.
-                _ => None,
-            },
-            _ => None,
-        }
-        .ok_or(VcvarsError::UnsupportedArch)?;
+        self.allow_vs2015 = enabled;
 
-        // Find `cmd.exe`.
-        let mut cmd_exe_path = PathBuf::from(win_dir);
-        cmd_exe_path.push("System32");
-        cmd_exe_path.push("cmd.exe");
+        self
+    }
 
-        // Run `cmd.exe` with vcvars.
-        let vcvars_path = vcvars_path.replace('^', "^^").replace('&', "^&"); // Try to follow `cmd.exe`'s erratic escaping rules (tested).
+    pub fn fail_on_ambiguous_installation(mut self, enabled: bool) -> Self {
+        #![must_use]
+        //! Makes running `vswhere.exe` fail with `VcvarsError::AmbiguousInstallation`, listing the installation paths, if it returns several installations, which can happen with `not_vswhere_latest_but()`. Otherwise, the first one is used. Has no effect if the installation is selected by this crate due to `select_installation()` or `selection_strategy()`.
 
-        // Note: Escaping `%` by writing `%%` doesn't work, and a path containing two `%`s and the name of an existing env var in between breaks the command.
+        self.fail_on_ambiguous_installation = enabled;
 
-        let separator_line =
-            "=".repeat(20) + "_unique_separator_by_rust_crate_that_utilizes_vcvars";
+        self
+    }
 
-        let output = Command::new(&cmd_exe_path)
-            .arg("/C")
-            // Note: On the regular, interactive command line, `chcp 65001` to change the active code page to UTF-8 doesn't seem to make a difference regarding the content.
-            .args([&vcvars_path, arch_arg, "&&"])
-            .args([&format!("echo.{separator_line}"), "&&"])
-            .arg("set") // Lists env vars.
-            .output();
+    pub fn products(mut self, product_ids: &'a [&'a str]) -> Self {
+        #![must_use]
+        //! Makes `vswhere.exe` only consider installations of the given products, which are passed with `-products`. By default, `*` is passed, so that, besides the full Visual Studio products, also Build Tools for Visual Studio installations are found, which `vswhere.exe` wouldn't consider otherwise.
+        //!
+        //! ```ignore
+        //! let mut vcvars = Vcvars::new().products(&["Microsoft.VisualStudio.Product.BuildTools"]);
+        //! ```
 
-        // Note: vcvars always returns exit code 0, even if it failed (as of Dec. 2022).
+        self.products = Some(product_ids);
 
-        let stdout = match output {
-            Ok(ref output) => String::from_utf8_lossy(&output.stdout),
-            Err(err) => {
-                return Err(VcvarsError::CouldntRun(
-                    cmd_exe_path.to_string_lossy().into_owned(),
-                    err,
-                ));
-            }
-        };
+        self
+    }
 
-        if stdout.starts_with("[ERROR:") {
-            return Err(VcvarsError::VcvarsFailed(
-                Itertools::intersperse(stdout.lines(), r"\n").collect(),
-            ));
-        }
+    pub fn requires(mut self, component_ids: &'a [&'a str]) -> Self {
+        #![must_use]
+        //! Makes `vswhere.exe` only consider installations that have all of the given components, which are passed with `-requires`, like `Microsoft.VisualStudio.Component.VC.Tools.x86.x64` for the MSVC build tools for x86 and x64.
+        //!
+        //! If no installation has the components, running `vswhere.exe` fails with `VcvarsError::RequiredComponentsNotFound`.
 
-        // Transform output lines to key-value pairs.
-        let mut env = HashMap::new();
-        let mut may_collect = false;
+        self.requires = Some(component_ids);
 
-        // Note: The format in stdout that we get is basically identical to that of the Windows API function `GetEnvironmentStrings()`, which is only for the current process.
+        self
+    }
 
-        for line in stdout.lines() {
-            if may_collect {
-                if let Some((key, value)) = line.split_once('=') {
-                    env.insert(key.to_uppercase(), value.to_owned());
-                }
-            } else if line.starts_with(&separator_line) {
-                // Note: The notoriously erratic `cmd.exe` adds a space. Hence not `==`.
+    pub fn requires_any(mut self, enabled: bool) -> Self {
+        #![must_use]
+        //! Makes `vswhere.exe` consider installations that have any instead of all of the components set with `requires()`, by passing `-requiresAny`. This is useful with alternative components, like the MSVC build tools for different host architectures.
+        //!
+        //! Enabling this without components makes `validate()` fail with `VcvarsError::InvalidConfiguration`.
 
-                may_collect = true;
-            }
-        }
+        self.requires_any = enabled;
 
-        Ok(env)
+        self
     }
-}
 
-#[derive(Error, Debug)]
-pub enum VcvarsError {
-    #[error("env var `{0}` isn't set, which is a dependency to run vcvars")]
-    MissingEnvVarDependency(String),
-    #[error("couldn't find file `{0}`")]
-    FileNotFound(String),
-    #[error("unsupported host or target architecture")]
-    UnsupportedArch,
-    #[error("couldn't run `{0}`: {1}")]
-    CouldntRun(String, io::Error),
-    #[error("`vcvarsall.bat` failed: {0}")]
-    VcvarsFailed(String),
-    #[error("I/O operation regarding cache path `{0}` failed: {1}")]
-    CacheFailed(String, io::Error),
-    #[error("variable `{0}` not found in vcvars environment")]
-    VarNotFound(String),
-}
+    pub fn version_range(mut self, range: &'a str) -> Self {
+        #![must_use]
+        //! Makes `vswhere.exe` only consider Visual Studio installations whose version is in `range`, which is passed with `-version`. `-latest` then selects the installation with the highest version in the range. The range can be a single version like `17.0` as the inclusive minimum, or an interval like `[16.0,18.0)`, where square brackets include the bound and parentheses exclude it, and a bound can be omitted.
+        //!
+        //! ```ignore
+        //! let mut vcvars = Vcvars::new().version_range("[16.0,17.0)"); // Visual Studio 2019.
+        //! ```
+        //!
+        //! A malformed range makes `validate()` and running `vswhere.exe` fail with `VcvarsError::InvalidConfiguration`. If no installation is in the range, running `vswhere.exe` fails with `VcvarsError::NoVisualStudioFound`.
 
-#[cfg(test)]
-mod tests {
-    use crate::Vcvars;
-    use regex::Regex;
-    use serial_test::serial;
-    use std::{env, fs, io, path::PathBuf, time::Instant};
+        self.version_range = Some(range);
 
-    fn prepare() {
-        // Normally set by Cargo.
-        env::set_var("CARGO_CFG_TARGET_ARCH", env::consts::ARCH);
+        self
     }
 
-    fn version_number_regex() -> Regex {
-        Regex::new(r"^(\d+\.)+\d+$").unwrap()
-    }
+    pub fn select_installation(
+        mut self,
+        predicate: impl Fn(&InstallationInfo) -> bool + Send + Sync + 'a,
+    ) -> Self {
+        #![must_use]
+        //! Makes the Visual Studio installation to run vcvars from be selected with `predicate`. Instead of `-latest`, `-all` is passed to `vswhere.exe`, unless `not_vswhere_latest_but()` is used, and the newest complete installation for which `predicate` returns `true` is selected. Because of this, `vswhere.exe` is also run when the values are read from cache files.
+        //!
+        //! ```ignore
+        //! let mut vcvars = Vcvars::new().select_installation(|installation| {
+        //!     !installation.is_prerelease
+        //!         && installation.product_line_version == "2022"
+        //!         && installation.installation_path.join(r"VC\Tools\MSVC").is_dir()
+        //! });
+        //! ```
+        //!
+        //! If no installation matches, running `vswhere.exe` fails with `VcvarsError::NoInstallationSelected`.
 
-    #[test]
-    #[serial]
-    fn get() {
-        prepare();
+        self.installation_selector = Some(Arc::new(predicate));
 
-        let mut vcvars = Vcvars::new();
+        self
+    }
+
+    pub fn selection_strategy(mut self, strategy: SelectionStrategy) -> Self {
+        #![must_use]
+        //! Sets how the Visual Studio installation to run vcvars from is selected, as described with `SelectionStrategy`. With a strategy other than the default, `-all` is passed to `vswhere.exe` like with `select_installation()`, which it combines with, and `vswhere.exe` is also run when the values are read from cache files. See `selection_log()` for how the installation was selected.
+
+        self.selection_strategy = strategy;
+
+        self
+    }
+
+    pub fn target(mut self, arch: Arch) -> Self {
+        #![must_use]
+        //! Makes vcvars set up the environment for the target architecture `arch`, instead of the one from the `CARGO_CFG_TARGET_ARCH` environment variable. Build scripts don't need this, since Cargo sets the variable for them, but standalone tools like `xtask` binaries do.
+
+        self.target_arch = Some(arch);
+
+        self
+    }
+
+    pub fn is_arm64ec(&self) -> bool {
+        #![must_use]
+        //! Returns whether ARM64EC is the target architecture, as set with `target()` or in the `CARGO_CFG_TARGET_ARCH` environment variable. vcvars sets up the ARM64 environment for it, so the compiler option `/arm64EC` and the linker option `/MACHINE:ARM64EC` need to be added, e.g., with [`cc::Build::flag()`].
+
+        match self.target_arch {
+            Some(arch) => arch == Arch::Arm64Ec,
+            None => env::var("CARGO_CFG_TARGET_ARCH").is_ok_and(|rust_arch| rust_arch == "arm64ec"),
+        }
+    }
+
+    pub fn host(mut self, arch: Arch) -> Self {
+        #![must_use]
+        //! Makes vcvars put the tools for the host architecture `arch` into `PATH`, instead of the ones for the architecture of the current process, like the x86-hosted tools for a lower memory footprint, or the x64-hosted tools from an x86 process.
+        //!
+        //! If vcvars doesn't support the combination of host and target architecture, running vcvars fails with `VcvarsError::UnsupportedArch`. This is always the case with `Arch::Arm64Ec`, which isn't a host architecture, as `validate()` reports.
+
+        self.host_arch = Some(arch);
+
+        self
+    }
+
+    pub fn toolchain_preference(mut self, preference: ToolchainPreference) -> Self {
+        #![must_use]
+        //! Sets whether the natively hosted tools or cross tools hosted on a different architecture are used, as described with `ToolchainPreference`. Ignored if the host architecture is set with `host()`, and if the tools directory layout of the script run as vcvars isn't known, like with `VCVARSALL_ENV_VAR`, in which case the native tools are used.
+
+        self.toolchain_preference = preference;
+
+        self
+    }
+
+    pub fn app_platform(mut self, platform: AppPlatform) -> Self {
+        #![must_use]
+        //! Makes vcvars set up the environment for the application `platform`, like `AppPlatform::Uwp` for UWP apps and Windows Runtime components, or `AppPlatform::OneCore` for the `onecore` libraries, which affects `LIB` and `LIBPATH`. `AppPlatform::Desktop` is vcvars' default.
+        //!
+        //! After running vcvars, `VSCMD_ARG_app_plat` is checked to match the requested platform. If it doesn't, running vcvars fails with `VcvarsError::AppPlatformNotApplied`. For `AppPlatform::OneCore`, `LIB` is additionally checked to contain an MSVC `onecore` directory, failing with `VcvarsError::OneCoreLibsNotFound` otherwise.
+
+        self.app_platform = Some(platform);
+
+        self
+    }
+
+    pub fn sdk_version(mut self, version: SdkVersion) -> Self {
+        #![must_use]
+        //! Makes vcvars select the Windows SDK `version`, like `10.0.19041.0` or `8.1`, instead of the newest installed one. See `installed_sdks()` for the available versions.
+        //!
+        //! If the SDK isn't installed, running vcvars fails with `VcvarsError::SdkNotFound`. For the Windows 8.1 SDK, this is checked with `installed_sdks()` before running vcvars. After running vcvars, `WindowsSDKVersion`, or `WindowsSdkDir` for the Windows 8.1 SDK, is checked to match the requested version.
+
+        self.sdk_version = Some(version);
+
+        self
+    }
+
+    pub fn toolset_version(mut self, version: &'a str) -> Self {
+        #![must_use]
+        //! Makes vcvars select the MSVC toolset `version`, like `14.29` or `14.29.30133`, with the argument `-vcvars_ver`, instead of the default toolset of the Visual Studio installation. See `installed_toolsets()` for the available versions.
+        //!
+        //! After running vcvars, `VCToolsVersion` is checked to start with the requested version. If it doesn't, or if vcvars can't find the toolset, running vcvars fails with `VcvarsError::ToolsetNotFound`.
+
+        self.toolset_version = Some(version);
+
+        self
+    }
+
+    pub fn spectre_libs(mut self, enabled: bool) -> Self {
+        #![must_use]
+        //! Makes vcvars put the Spectre-mitigated libraries into `LIB`, with the argument `-vcvars_spectre_libs=spectre`.
+        //!
+        //! After running vcvars, `LIB` is checked to contain a `spectre` directory. If it doesn't, because the Visual Studio component with the Spectre-mitigated libraries isn't installed, running vcvars fails with `VcvarsError::SpectreLibsNotInstalled`.
+
+        self.spectre_libs = enabled;
+
+        self
+    }
+
+    pub fn extra_vcvarsall_args(mut self, args: &'a [&'a str]) -> Self {
+        #![must_use]
+        //! Appends `args` to the arguments `vcvarsall.bat` is called with, after the ones determined by `Vcvars`. This is a means to use options of vcvars that `Vcvars` doesn't offer methods for yet, like with `not_vswhere_latest_but()`. `^` and `&` are escaped for `cmd.exe`.
+        //!
+        //! ```ignore
+        //! let mut vcvars = Vcvars::new()
+        //!     .extra_vcvarsall_args(&["-vcvars_ver=14.29"]);
+        //! ```
+
+        self.extra_vcvarsall_args = Some(args);
+
+        self
+    }
+
+    pub fn use_vsdevcmd(mut self, options: VsDevCmdOptions) -> Self {
+        #![must_use]
+        //! Makes `Vcvars` run `Common7\Tools\VsDevCmd.bat` with the arguments `-arch` and `-host_arch` instead of `vcvarsall.bat`. The options set with other methods, like `sdk_version()`, are passed in `VsDevCmd.bat`'s syntax, while `extra_vcvarsall_args()` are passed as is.
+        //!
+        //! `vcvarsall.bat` is a wrapper around `VsDevCmd.bat` that sets up the C++ environment. Running `VsDevCmd.bat` directly sets up the full developer command prompt environment instead, which, depending on the installed workloads, additionally contains variables like `DevEnvDir`, the directory of `MSBuild.exe` in `PATH` and extension SDK directories, but takes longer.
+
+        self.vsdevcmd_options = Some(options);
+
+        self
+    }
+
+    pub fn child_env(mut self, key: &str, value: &str) -> Self {
+        #![must_use]
+        //! Sets the environment variable `key` to `value` for the `cmd.exe` child process that runs vcvars, before vcvars runs, like `VSCMD_DEBUG` to make vcvars log diagnostic output. Can be called multiple times. The current process isn't affected.
+
+        self.child_env.push((key.to_owned(), value.to_owned()));
+
+        self
+    }
+
+    pub fn telemetry(mut self, enabled: bool) -> Self {
+        #![must_use]
+        //! Lets vcvars send telemetry data to Microsoft. By default, the environment variable `VSCMD_SKIP_SENDTELEMETRY` is set to `1` for the `cmd.exe` child process to prevent this, because vcvars would otherwise start a PowerShell process for it, which can make running vcvars take about a second longer.
+
+        self.telemetry = enabled;
+
+        self
+    }
+
+    pub fn clean_env(mut self, enabled: bool) -> Self {
+        #![must_use]
+        //! Makes vcvars run with only a minimal set of environment variables from the current process, so the resulting variables only reflect what vcvars sets, regardless of the environment of, e.g., individual developers. The set consists of the system directories, the program files directories, the temp directories and the processor architecture variables, with a `PATH` of just `System32`. Variables set with `child_env()` are added.
+        //!
+        //! As a tradeoff, `PATH` and other variables don't contain the entries of user-level tools anymore. `path_additions()`, `changed_vars()` and `origin()` compare against the minimal set.
+
+        self.clean_env = enabled;
+
+        self
+    }
+
+    pub fn reuse_ambient_env(mut self, enabled: bool) -> Self {
+        #![must_use]
+        //! Controls whether the environment variables of the current process are used instead of running vcvars, if Cargo was invoked from a Developer Command Prompt, like "x64 Native Tools Command Prompt", whose vcvars run matches the target architecture. Saves the seconds running vcvars takes. Enabled by default.
+        //!
+        //! The environment is recognized by the variables `VSCMD_VER`, `VSCMD_ARG_TGT_ARCH`, `VSCMD_ARG_HOST_ARCH` and `VCINSTALLDIR`. It isn't reused if the host architecture set with `host()` differs, if options that vcvars would have to apply, like `sdk_version()`, don't match it, or with options that can't be checked against it, like `products()`, `with_installation_dir()`, `use_vsdevcmd()`, `clean_env()`, `child_env()` or `extra_vcvarsall_args()`. If it's reused, `path_additions()`, `changed_vars()` and `origin()` consider all variables unchanged, and `command_line()` fails with `VcvarsError::AmbientEnvReused`.
+
+        self.reuse_ambient_env = enabled;
+
+        self
+    }
+
+    pub fn prefer_ambient_installation(mut self, enabled: bool) -> Self {
+        #![must_use]
+        //! Controls whether the Visual Studio installation named by the `VSINSTALLDIR` environment variable of the current process is used, if Cargo was invoked from a Developer Command Prompt whose environment can't be reused (see `reuse_ambient_env()`), like because it's for another architecture. This way, one build doesn't mix toolsets of different installations. `vswhere.exe` isn't run to find the installation then, which is noted in `selection_log()`. Enabled by default. The installation is only preferred if it contains `VC\Auxiliary\Build\vcvarsall.bat` and no options selecting the installation, like `products()` or `with_installation_dir()`, are set.
+
+        self.prefer_ambient_installation = enabled;
+
+        self
+    }
+
+    pub fn keep_raw_output(mut self, keep: bool) -> Self {
+        #![must_use]
+        //! Makes the `cmd.exe` stdout output of the vcvars run available via `raw_output()`, for debugging. If vcvars fails, the output is retained regardless.
+
+        self.keep_raw_output = keep;
+
+        self
+    }
+
+    pub fn validate(&self) -> Result<(), VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Checks the configuration for options that conflict with each other or are malformed, returning `VcvarsError::InvalidConfiguration` describing the problem, or `VcvarsError::UnsupportedArch` for `Arch::Arm64Ec` set with `host()`. Called before running vcvars, but can be called earlier to fail fast.
+
+        let invalid = |message: String| Err(VcvarsError::InvalidConfiguration(message));
+
+        if self.host_arch == Some(Arch::Arm64Ec) {
+            self.host_arch(self.resolved_target_arch().map_or("any", Arch::as_str))?;
+        }
+
+        if let Some(version) = self.sdk_version {
+            if version.major() < 10 {
+                if let Some(platform @ (AppPlatform::Uwp | AppPlatform::OneCore)) =
+                    self.app_platform
+                {
+                    return invalid(format!(
+                        "app platform `{platform}` requires a Windows 10+ SDK, but SDK `{version}` was requested"
+                    ));
+                }
+
+                if let Some(arch @ (Arch::Arm64 | Arch::Arm64Ec)) = self.target_arch {
+                    return invalid(format!(
+                        "target architecture `{arch}` requires a Windows 10+ SDK, but SDK `{version}` was requested"
+                    ));
+                }
+            }
+        }
+
+        if let Some(version) = self.toolset_version {
+            if !version
+                .split('.')
+                .all(|part| !part.is_empty() && part.bytes().all(|byte| byte.is_ascii_digit()))
+            {
+                return invalid(format!(
+                    "toolset version `{version}` isn't a version like `14.29` or `14.29.30133`"
+                ));
+            }
+        }
+
+        if self.include_legacy && (self.products.is_some() || self.requires.is_some()) {
+            return invalid(
+                "`include_legacy()` can't be combined with `products()` or `requires()`".to_owned(),
+            );
+        }
+
+        if self.requires_any && self.requires.is_none_or(<[_]>::is_empty) {
+            return invalid(
+                "`requires_any()` was enabled without components set with `requires()`".to_owned(),
+            );
+        }
+
+        if let Some(root) = &self.ewdk_root {
+            if root.to_str().is_none() {
+                return invalid(format!(
+                    "EWDK root `{}` isn't valid UTF-8, which is required to run `SetupBuildEnv.cmd` with `cmd.exe`",
+                    root.display()
+                ));
+            }
+
+            let conflicting_options = [
+                (self.vsdevcmd_options.is_some(), "use_vsdevcmd()"),
+                (self.installation_dir.is_some(), "with_installation_dir()"),
+                (self.sdk_version.is_some(), "sdk_version()"),
+                (self.toolset_version.is_some(), "toolset_version()"),
+                (self.app_platform.is_some(), "app_platform()"),
+                (self.spectre_libs, "spectre_libs()"),
+            ];
+            if let Some((_, option)) = conflicting_options.iter().find(|(is_set, _)| *is_set) {
+                return invalid(format!("`from_ewdk()` can't be combined with `{option}`"));
+            }
+        }
+
+        self.validate_version_range()?;
+
+        for (key, _) in &self.child_env {
+            if key.is_empty() || key.contains('=') {
+                return invalid(format!(
+                    "child env var name `{key}` is empty or contains `=`"
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn get_cached(&mut self, var_name: impl AsRef<str>) -> Result<Cow<str>, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Reads the `OUT_DIR` environment variable that Cargo sets and obtains `var_name`'s value from a cache file. If the file isn't present, runs vcvars and creates a memory cache of its variables, if not done previously, to source the value from and creates the cache file. Then returns the value.
+        //!
+        //! All variables are cached in one file, `=env.txt`, so vcvars is run at most once, regardless of how many variables are obtained in how many build script runs. It's kept in a subdirectory named after the host and target architecture, like `x64_arm64`, so values for another architecture are never served. Likewise, every configuration set with the builder methods, like `sdk_version()` or `products()`, has its own cache files. Cache files of earlier versions of this crate, which lacked these subdirectories, are removed when the file is created.
+        //!
+        //! Before cached values are served, the sizes and modification times of the vcvars script that was run and of `Microsoft.VCToolsVersion.default.txt`, which names the default toolset, are compared with the ones recorded in the cache, to check whether the installation changed, like by an update, since the cached paths may not exist anymore then. If it changed, vcvars is run again, and the cache files are refreshed. `vswhere.exe` isn't run for this, so a newly installed Visual Studio installation that would be found instead isn't detected (see `force_refresh()`).
+        //!
+        //! Build scripts running in parallel, like in a workspace, share one vcvars run: The cache files are created while holding the exclusive lock file `=lock`, so the other build scripts wait for them, and then read them. If the lock can't be acquired within 30 seconds, vcvars is run anyway.
+        //!
+        //! # Panics
+        //!
+        //! Panics if the `OUT_DIR` environment variable isn't set or doesn't represent an existing directory.
+
+        let var_name = var_name.as_ref();
+
+        match self.ensure_cached_env_map()?.get(&var_name.to_uppercase()) {
+            Some(value) => Ok(Cow::Borrowed(value)),
+            None => Err(VcvarsError::VarNotFound(var_name.to_owned())),
+        }
+    }
+
+    pub fn clear_cache(&mut self) -> Result<(), VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Removes the cache directory of this crate, `vcvars-cache` in Cargo's output directory, with the cache files of all configurations, and drops the memory cache of the vcvars env vars, so vcvars is run again when values are obtained next. Doesn't fail if the directory doesn't exist. Useful when cached values are suspected to be wrong.
+        //!
+        //! # Panics
+        //!
+        //! See `get_cached()`.
+
+        self.drop_env_maps();
+
+        Self::clear_cache_in(cache_dir("").parent().unwrap())
+    }
+
+    pub fn clear_cache_in(out_dir: impl AsRef<Path>) -> Result<(), VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Like `clear_cache()`, but removes `vcvars-cache` in the specified directory instead of the one in the `OUT_DIR` environment variable, like when not used in a build script. Only this subdirectory is removed, not `out_dir` itself.
+
+        let cache_dir = out_dir.as_ref().join("vcvars-cache");
+
+        match fs::remove_dir_all(&cache_dir) {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(VcvarsError::CacheFailed(
+                cache_dir.to_string_lossy().into_owned(),
+                err,
+            )),
+        }
+    }
+
+    pub fn force_refresh(&mut self) -> Result<(), VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Drops the memory cache of the vcvars env vars, runs vcvars again, and rewrites the cache files of the configuration and architecture, like `=env.txt`. Other cache files, like the one of `bundled_vcpkg()`, are removed, so their values are determined again when obtained next. Useful when the environment changed in a way this crate doesn't detect, like after installing a Windows SDK.
+        //!
+        //! The exclusive lock file of the cache directory is held while refreshing, so build scripts running in parallel wait, and then read the new values (see `get_cached()`).
+        //!
+        //! # Panics
+        //!
+        //! See `get_cached()`.
+
+        self.drop_env_maps();
+
+        let cache_dir = self.ensure_cache_dir()?;
+        let _lock = CacheLock::acquire(&cache_dir, CACHE_LOCK_TIMEOUT);
+
+        // Including the manifest file, which is rewritten with the env file.
+        remove_cache_files(&cache_dir);
+
+        let env_map = self.ensure_env_map()?.clone();
+        self.write_env_cache_file(&cache_dir, &env_map)?;
+        self.cached_env_map = Some(env_map);
+
+        Ok(())
+    }
+
+    fn drop_env_maps(&mut self) {
+        //! Drops the memory caches of the vcvars env vars and of what was retained when vcvars was run.
+
+        self.env_map = None;
+        self.cached_env_map = None;
+        self.parent_env_map = None;
+        self.raw_output = None;
+        self.run_host_arch = None;
+    }
+
+    pub fn get(&mut self, var_name: impl AsRef<str>) -> Result<&str, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Runs vcvars and creates a memory cache of its variables, if not done previously, and returns `var_name`'s value.
+        //!
+        //! For productive use, it's recommended to use `get_cached()` instead, so follow-up build script runs are significantly sped up.
+
+        let var_name = var_name.as_ref();
+
+        match self.ensure_env_map()?.get(&var_name.to_uppercase()) {
+            Some(value) => Ok(value),
+            None => Err(VcvarsError::VarNotFound(var_name.to_owned())),
+        }
+    }
+
+    pub fn get_cached_opt(
+        &mut self,
+        var_name: impl AsRef<str>,
+    ) -> Result<Option<Cow<'_, str>>, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Like `get_cached()`, but returns `None` if the variable isn't set in the vcvars environment, while still returning errors for real failures, like vcvars not being able to run.
+        //!
+        //! # Panics
+        //!
+        //! See `get_cached()`.
+
+        let var_name = var_name.as_ref();
+
+        match self.get_cached(var_name) {
+            Ok(value) => Ok(Some(value)),
+            Err(VcvarsError::VarNotFound(_)) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn get_cached_or<'b>(
+        &'b mut self,
+        var_name: impl AsRef<str>,
+        default: &'b str,
+    ) -> Result<Cow<'b, str>, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Like `get_cached_opt()`, but returns `default` if the variable isn't set.
+        //!
+        //! # Panics
+        //!
+        //! See `get_cached()`.
+
+        let var_name = var_name.as_ref();
+
+        Ok(self
+            .get_cached_opt(var_name)?
+            .unwrap_or(Cow::Borrowed(default)))
+    }
+
+    pub fn get_opt(&mut self, var_name: impl AsRef<str>) -> Result<Option<&str>, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Like `get()`, but returns `None` if the variable isn't set in the vcvars environment, while still returning errors for real failures, like vcvars not being able to run.
+
+        let var_name = var_name.as_ref();
+
+        Ok(self
+            .ensure_env_map()?
+            .get(&var_name.to_uppercase())
+            .map(String::as_str))
+    }
+
+    pub fn get_or<'b>(
+        &'b mut self,
+        var_name: impl AsRef<str>,
+        default: &'b str,
+    ) -> Result<&'b str, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Like `get_opt()`, but returns `default` if the variable isn't set.
+
+        let var_name = var_name.as_ref();
+
+        Ok(self.get_opt(var_name)?.unwrap_or(default))
+    }
+
+    pub fn contains(&mut self, var_name: impl AsRef<str>) -> Result<bool, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Returns whether `var_name` is set in the vcvars environment. If the `OUT_DIR` environment variable is set, the variables are obtained like with `get_cached()`, so vcvars isn't run if the cache file exists. Otherwise, runs vcvars and creates a memory cache of its variables, if not done previously.
+        //!
+        //! # Panics
+        //!
+        //! Panics if the `OUT_DIR` environment variable is set, but doesn't represent an existing directory.
+
+        let var_name = var_name.as_ref();
+
+        let env_map = if env::var_os("OUT_DIR").is_some() {
+            self.ensure_cached_env_map()?
+        } else {
+            self.ensure_env_map()?
+        };
+
+        Ok(env_map.contains_key(&var_name.to_uppercase()))
+    }
+
+    pub fn get_cached_many(
+        &mut self,
+        var_names: &[&str],
+    ) -> Result<HashMap<String, String>, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Like `get_cached()`, but for multiple variables. Values found in cache files don't require vcvars to be run. The returned map is keyed by the names as passed. If variables aren't found, all of them are reported in the error.
+        //!
+        //! # Panics
+        //!
+        //! See `get_cached()`.
+
+        let mut values = HashMap::new();
+        let mut missing_var_names = Vec::new();
+
+        for &var_name in var_names {
+            match self.get_cached(var_name) {
+                Ok(value) => {
+                    values.insert(var_name.to_owned(), value.into_owned());
+                }
+                Err(VcvarsError::VarNotFound(_)) => missing_var_names.push(var_name.to_owned()),
+                Err(err) => return Err(err),
+            }
+        }
+
+        if missing_var_names.is_empty() {
+            Ok(values)
+        } else {
+            Err(VcvarsError::VarsNotFound(missing_var_names))
+        }
+    }
+
+    pub fn get_many(&mut self, var_names: &[&str]) -> Result<HashMap<String, String>, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Like `get()`, but for multiple variables. The returned map is keyed by the names as passed. If variables aren't found, all of them are reported in the error.
+        //!
+        //! For productive use, it's recommended to use `get_cached_many()` instead.
+
+        let env_map = self.ensure_env_map()?;
+        let mut values = HashMap::new();
+        let mut missing_var_names = Vec::new();
+
+        for &var_name in var_names {
+            match env_map.get(&var_name.to_uppercase()) {
+                Some(value) => {
+                    values.insert(var_name.to_owned(), value.clone());
+                }
+                None => missing_var_names.push(var_name.to_owned()),
+            }
+        }
+
+        if missing_var_names.is_empty() {
+            Ok(values)
+        } else {
+            Err(VcvarsError::VarsNotFound(missing_var_names))
+        }
+    }
+
+    pub fn get_cached_paths(
+        &mut self,
+        var_name: impl AsRef<str>,
+    ) -> Result<Vec<PathBuf>, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Like `get_cached()`, but splits the value of a list-style variable like `INCLUDE`, `LIB`, `LIBPATH` or `PATH` into its paths. Empty and duplicate entries are dropped, while the order is otherwise preserved.
+        //!
+        //! # Panics
+        //!
+        //! See `get_cached()`.
+
+        let var_name = var_name.as_ref();
+
+        Ok(split_path_list(&self.get_cached(var_name)?))
+    }
+
+    pub fn get_paths(&mut self, var_name: impl AsRef<str>) -> Result<Vec<PathBuf>, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Like `get()`, but splits the value of a list-style variable like `INCLUDE`, `LIB`, `LIBPATH` or `PATH` into its paths. Empty and duplicate entries are dropped, while the order is otherwise preserved.
+        //!
+        //! For productive use, it's recommended to use `get_cached_paths()` instead.
+
+        let var_name = var_name.as_ref();
+
+        Ok(split_path_list(self.get(var_name)?))
+    }
+
+    pub fn include_dirs(&mut self) -> Result<Vec<PathBuf>, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Returns the paths from `INCLUDE` that represent existing directories, as obtained by `get_cached_paths()`. Can be passed to, e.g., [`cc::Build::includes()`].
+        //!
+        //! # Panics
+        //!
+        //! See `get_cached()`.
+
+        let mut dirs = self.include_dirs_unfiltered()?;
+        dirs.retain(|dir| dir.is_dir());
+
+        Ok(dirs)
+    }
+
+    pub fn include_dirs_unfiltered(&mut self) -> Result<Vec<PathBuf>, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Like `include_dirs()`, but also returns paths that don't represent existing directories.
+        //!
+        //! # Panics
+        //!
+        //! See `get_cached()`.
+
+        self.get_cached_paths("INCLUDE")
+    }
+
+    pub fn find_header(&mut self, name: &str) -> Result<Option<PathBuf>, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Returns the path of the header file `name` in the first directory from `include_dirs_unfiltered()` that contains it. `name` may contain subdirectories, like `winrt/base.h`. Returns `None` if no directory contains the file.
+        //!
+        //! # Panics
+        //!
+        //! See `get_cached()`.
+
+        Ok(self
+            .include_dirs_unfiltered()?
+            .into_iter()
+            .map(|dir| dir.join(name))
+            .find(|path| path.is_file()))
+    }
+
+    pub fn lib_dirs(&mut self) -> Result<Vec<PathBuf>, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Returns the absolute paths from `LIB`, as obtained by `get_cached_paths()`. The order is preserved, since the linker uses the first matching library.
+        //!
+        //! # Panics
+        //!
+        //! See `get_cached()`.
+
+        let mut dirs = self.get_cached_paths("LIB")?;
+        dirs.retain(|dir| dir.is_absolute());
+
+        Ok(dirs)
+    }
+
+    pub fn find_lib(&mut self, name: &str) -> Result<Option<PathBuf>, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Returns the path of the library file `name`, like `d3d12.lib`, in the first directory from `lib_dirs()` that contains it, which is where the linker would find it. The name is compared case-insensitively. Directories that don't exist are skipped. Returns `None` if no directory contains the file.
+        //!
+        //! # Panics
+        //!
+        //! See `get_cached()`.
+
+        Ok(self.lib_dirs()?.into_iter().find_map(|dir| {
+            fs::read_dir(dir)
+                .ok()?
+                .filter_map(Result::ok)
+                .map(|entry| entry.path())
+                .find(|path| {
+                    path.is_file()
+                        && path
+                            .file_name()
+                            .is_some_and(|file_name| file_name.eq_ignore_ascii_case(name))
+                })
+        }))
+    }
+
+    pub fn libpath_dirs(&mut self) -> Result<Vec<PathBuf>, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Like `lib_dirs()`, but for `LIBPATH`, which contains the directories to search for metadata files like `.winmd` and `.dll` files referenced by C++/WinRT and C++/CLI code.
+        //!
+        //! Since `LIBPATH` isn't set in every configuration, an empty vector is returned if it's missing, instead of an error.
+        //!
+        //! # Panics
+        //!
+        //! See `get_cached()`.
+
+        let mut dirs = match self.get_cached_paths("LIBPATH") {
+            Ok(dirs) => dirs,
+            Err(VcvarsError::VarNotFound(_)) => Vec::new(),
+            Err(err) => return Err(err),
+        };
+        dirs.retain(|dir| dir.is_absolute());
+
+        Ok(dirs)
+    }
+
+    pub fn path_additions(&mut self) -> Result<Vec<PathBuf>, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Returns the paths from vcvars' `PATH`, as obtained by `get_paths()`, that weren't already in the `PATH` of the current process when vcvars was run. Paths that vcvars just reordered aren't included. Paths are compared case-insensitively and irrespective of trailing backslashes.
+
+        let paths = self.get_paths("PATH")?;
+        let parent_paths: HashSet<_> = self
+            .parent_env_map
+            .as_ref()
+            .and_then(|parent_env_map| parent_env_map.get("PATH"))
+            .into_iter()
+            .flat_map(env::split_paths)
+            .map(|path| comparable_path(&path))
+            .collect();
+
+        Ok(paths
+            .into_iter()
+            .filter(|path| !parent_paths.contains(&comparable_path(path)))
+            .collect())
+    }
+
+    pub fn merged_path(&mut self) -> Result<OsString, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Returns the `PATH` of the current process with the paths from vcvars' `PATH`, as obtained by `get_paths()`, prepended in their original order, unless they're already contained. Paths are compared case-insensitively and irrespective of trailing backslashes. Suitable for [`std::process::Command::env()`].
+
+        let current_paths = env::var_os("PATH")
+            .map(|path| env::split_paths(&path).collect_vec())
+            .unwrap_or_default();
+
+        let mut seen_paths: HashSet<_> = current_paths
+            .iter()
+            .map(|path| comparable_path(path))
+            .collect();
+        let new_paths = self
+            .get_paths("PATH")?
+            .into_iter()
+            .filter(|path| seen_paths.insert(comparable_path(path)));
+
+        match env::join_paths(new_paths.chain(current_paths)) {
+            Ok(merged_path) => Ok(merged_path),
+            Err(err) => Err(VcvarsError::JoinPathsFailed(err)),
+        }
+    }
+
+    pub fn compiler_path(&mut self, name: &str) -> Result<PathBuf, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Returns the path of an MSVC tool like `cl`, `link` or `lib` for the host and target architecture vcvars was run for. `name` is specified without the `.exe` extension. The path is built from the variables `VCToolsInstallDir`, `VSCMD_ARG_HOST_ARCH` and `VSCMD_ARG_TGT_ARCH`, obtained with `get_cached()`.
+        //!
+        //! # Panics
+        //!
+        //! See `get_cached()`.
+
+        let mut path = PathBuf::from(self.get_cached("VCToolsInstallDir")?.into_owned());
+        let host_arch = self.get_cached("VSCMD_ARG_HOST_ARCH")?.into_owned();
+        let target_arch = self.get_cached("VSCMD_ARG_TGT_ARCH")?.into_owned();
+
+        path.push("bin");
+        path.push(format!("Host{host_arch}"));
+        path.push(target_arch);
+        path.push(format!("{name}.exe"));
+
+        if path.is_file() {
+            Ok(path)
+        } else {
+            Err(VcvarsError::MsvcToolNotFound(
+                path.to_string_lossy().into_owned(),
+            ))
+        }
+    }
+
+    pub fn msvc_tools(&mut self) -> Result<MsvcTools, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Returns the paths of the most important MSVC tools, as obtained by `compiler_path()`, along with the MSVC toolset version from `VCToolsVersion`.
+        //!
+        //! # Panics
+        //!
+        //! See `get_cached()`.
+
+        Ok(MsvcTools {
+            cl: self.compiler_path("cl")?,
+            link: self.compiler_path("link")?,
+            lib: self.compiler_path("lib")?,
+            nmake: self.compiler_path("nmake")?,
+            version: self.get_cached("VCToolsVersion")?.into_owned(),
+        })
+    }
+
+    pub fn rc_exe(&mut self) -> Result<PathBuf, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Returns the path of the Windows SDK's resource compiler `rc.exe` for the host architecture. See `windows_sdk_tool_path()`.
+        //!
+        //! # Panics
+        //!
+        //! See `get_cached()`.
+
+        self.windows_sdk_tool_path("rc")
+    }
+
+    pub fn mt_exe(&mut self) -> Result<PathBuf, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Returns the path of the Windows SDK's manifest tool `mt.exe` for the host architecture. See `windows_sdk_tool_path()`.
+        //!
+        //! # Panics
+        //!
+        //! See `get_cached()`.
+
+        self.windows_sdk_tool_path("mt")
+    }
+
+    pub fn midl_exe(&mut self) -> Result<PathBuf, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Returns the path of the Windows SDK's IDL compiler `midl.exe` for the host architecture. See `windows_sdk_tool_path()`.
+        //!
+        //! # Panics
+        //!
+        //! See `get_cached()`.
+
+        self.windows_sdk_feature_tool_path("midl", "the MIDL compiler")
+    }
+
+    pub fn cppwinrt_exe(&mut self) -> Result<PathBuf, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Returns the path of the Windows SDK's C++/WinRT tool `cppwinrt.exe` for the host architecture. See `windows_sdk_tool_path()`.
+        //!
+        //! # Panics
+        //!
+        //! See `get_cached()`.
+
+        self.windows_sdk_feature_tool_path("cppwinrt", "C++/WinRT (version 10.0.17134.0 or later)")
+    }
+
+    fn windows_sdk_feature_tool_path(
+        &mut self,
+        name: &str,
+        feature: &str,
+    ) -> Result<PathBuf, VcvarsError> {
+        match self.windows_sdk_tool_path(name) {
+            Err(VcvarsError::WindowsSdkToolNotFound(path)) => Err(
+                VcvarsError::WindowsSdkFeatureNotFound(path, feature.to_owned()),
+            ),
+            result => result,
+        }
+    }
+
+    pub fn windows_sdk_tool_path(&mut self, name: &str) -> Result<PathBuf, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Returns the path of a Windows SDK tool like `rc` or `mt` for the host architecture vcvars was run for. `name` is specified without the `.exe` extension. The variables are obtained with `get_cached()`.
+        //!
+        //! The tool is searched for in the directory named after `VSCMD_ARG_HOST_ARCH` (like `x64`) inside of `WindowsSdkVerBinPath`. If that variable isn't set, `WindowsSdkDir` is used with the layout of newer SDKs, `bin\<WindowsSDKVersion>\<host arch>`, and then with that of older SDKs, `bin\<host arch>`.
+        //!
+        //! # Panics
+        //!
+        //! See `get_cached()`.
+
+        let host_arch = self.get_cached("VSCMD_ARG_HOST_ARCH")?.into_owned();
+        let file_name = format!("{name}.exe");
+
+        let mut bin_dirs = Vec::new();
+        if let Some(ver_bin_dir) = self.get_cached_opt("WindowsSdkVerBinPath")? {
+            bin_dirs.push(PathBuf::from(ver_bin_dir.into_owned()));
+        } else {
+            let mut bin_dir = PathBuf::from(self.get_cached("WindowsSdkDir")?.into_owned());
+            bin_dir.push("bin");
+
+            if let Some(sdk_version) = self.get_cached_opt("WindowsSDKVersion")? {
+                bin_dirs.push(bin_dir.join(sdk_version.trim_end_matches('\\')));
+            }
+            bin_dirs.push(bin_dir);
+        }
+
+        let paths = bin_dirs
+            .into_iter()
+            .map(|bin_dir| bin_dir.join(&host_arch).join(&file_name))
+            .collect::<Vec<_>>();
+
+        match paths.iter().find(|path| path.is_file()) {
+            Some(path) => Ok(path.clone()),
+            None => Err(VcvarsError::WindowsSdkToolNotFound(
+                paths[0].to_string_lossy().into_owned(),
+            )),
+        }
+    }
+
+    pub fn windows_sdk(&mut self) -> Result<WindowsSdkInfo, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Returns information about the Windows SDK vcvars selected, built from the variables `WindowsSdkDir`, `WindowsSDKVersion`, `WindowsSdkVerBinPath` and `VSCMD_ARG_TGT_ARCH`, obtained with `get_cached()`.
+        //!
+        //! # Panics
+        //!
+        //! See `get_cached()`.
+
+        let root = PathBuf::from(self.get_cached("WindowsSdkDir")?.into_owned());
+        let version = self
+            .get_cached("WindowsSDKVersion")?
+            .trim_end_matches('\\')
+            .to_owned();
+        let target_arch = self.get_cached("VSCMD_ARG_TGT_ARCH")?.into_owned();
+
+        let bin = match self.get_cached_opt("WindowsSdkVerBinPath")? {
+            Some(bin) => PathBuf::from(bin.into_owned()),
+            None => root.join("bin").join(&version),
+        };
+
+        let include_dirs = ["ucrt", "shared", "um", "winrt", "cppwinrt"]
+            .into_iter()
+            .map(|name| root.join("Include").join(&version).join(name))
+            .filter(|dir| dir.is_dir())
+            .collect();
+
+        let lib_dirs = ["ucrt", "um"]
+            .into_iter()
+            .map(|name| {
+                root.join("Lib")
+                    .join(&version)
+                    .join(name)
+                    .join(&target_arch)
+            })
+            .filter(|dir| dir.is_dir())
+            .collect();
+
+        Ok(WindowsSdkInfo {
+            root,
+            version,
+            bin,
+            include_dirs,
+            lib_dirs,
+        })
+    }
+
+    pub fn vc_tools_version(&mut self) -> Result<VcToolsVersion, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Returns the parsed MSVC toolset version from `VCToolsVersion`, obtained with `get_cached()`. If the variable isn't set, as with older vcvars versions, the version is parsed from the name of the directory in `VCToolsInstallDir`, which is located in `VC\Tools\MSVC`.
+        //!
+        //! # Panics
+        //!
+        //! See `get_cached()`.
+
+        if let Some(version) = self.get_cached_opt("VCToolsVersion")? {
+            return version.parse();
+        }
+
+        let tools_dir = PathBuf::from(self.get_cached("VCToolsInstallDir")?.into_owned());
+        match tools_dir.file_name() {
+            Some(dir_name) => dir_name.to_string_lossy().parse(),
+            None => Err(VcvarsError::InvalidVersion(
+                tools_dir.to_string_lossy().into_owned(),
+            )),
+        }
+    }
+
+    pub fn installed_toolsets(&mut self) -> Result<Vec<VcToolsVersion>, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Returns the versions of the MSVC toolsets installed side by side in `VC\Tools\MSVC` of the installation directory found by `vswhere.exe`, sorted in ascending order. Directories whose names aren't versions are skipped. Doesn't run vcvars, so the result can inform which toolset to request.
+
+        let visual_studio_dir = self.ensure_visual_studio_dir()?;
+
+        match toolsets_in(&visual_studio_dir) {
+            Some(toolsets) => Ok(toolsets),
+            None => Err(VcvarsError::FileNotFound(
+                visual_studio_dir
+                    .join(r"VC\Tools\MSVC")
+                    .to_string_lossy()
+                    .into_owned(),
+            )),
+        }
+    }
+
+    pub fn available_targets(&mut self) -> Result<Vec<Arch>, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Returns the target architectures the MSVC toolset can build for from the current host (see `host()`), as installed in the installation directory found by `vswhere.exe`. An architecture is available if the toolset has its `lib` subdirectory and a `cl.exe` in the matching `bin\Host*` subdirectory. Doesn't run vcvars, so missing components can be reported before using the environment.
+        //!
+        //! The toolset is the newest one matching `toolset_version()`, if set, or else the default one named in `VC\Auxiliary\Build\Microsoft.VCToolsVersion.default.txt`, which vcvars uses, falling back to the newest from `installed_toolsets()`.
+
+        let host_arch = self.effective_host_arch("any")?;
+        let Some(tools_dir) = self.tools_dir()? else {
+            return Ok(Vec::new());
+        };
+
+        let bin_dir = tools_dir.join("bin").join(format!("Host{host_arch}"));
+
+        Ok([Arch::X86, Arch::X64, Arch::Arm, Arch::Arm64]
+            .into_iter()
+            .filter(|arch| {
+                tools_dir.join("lib").join(arch.as_str()).is_dir()
+                    && bin_dir.join(arch.as_str()).join("cl.exe").is_file()
+            })
+            .collect())
+    }
+
+    pub fn installed_sdks(&mut self) -> Result<Vec<SdkVersion>, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Returns the versions of the installed Windows SDKs, sorted in ascending order, for choosing the SDK to request. Doesn't run vcvars. The result is cached in memory.
+        //!
+        //! The Windows Kits root directories are read from the registry key `HKLM\SOFTWARE\Microsoft\Windows Kits\Installed Roots` with `reg.exe`, falling back to `Windows Kits` in the x86 program files directory. Windows 10+ SDKs are listed from the version directories in `Include`, and the Windows 8.1 SDK is included as version `8.1`. SDKs whose `um\Windows.h` is missing, as with partial installations, are skipped.
+
+        if let Some(versions) = &self.installed_sdks {
+            return Ok(versions.clone());
+        }
+
+        let Ok(win_dir) = env::var("WINDIR") else {
+            return Err(VcvarsError::MissingEnvVarDependency("WINDIR".to_owned()));
+        };
+
+        let mut reg_exe_path = PathBuf::from(win_dir);
+        reg_exe_path.push("System32");
+        reg_exe_path.push("reg.exe");
+
+        let mut versions = Vec::new();
+
+        if let Some(kits_root) = kits_root(&reg_exe_path, "KitsRoot10", "10") {
+            if let Ok(entries) = fs::read_dir(kits_root.join("Include")) {
+                versions.extend(
+                    entries
+                        .filter_map(Result::ok)
+                        .filter(|entry| entry.path().join(r"um\Windows.h").is_file())
+                        .filter_map(|entry| entry.file_name().to_str()?.parse::<SdkVersion>().ok()),
+                );
+            }
+        }
+
+        if let Some(kits_root) = kits_root(&reg_exe_path, "KitsRoot81", "8.1") {
+            if kits_root.join(r"Include\um\Windows.h").is_file() {
+                versions.push(SdkVersion::new(8, 1, 0, 0));
+            }
+        }
+
+        versions.sort_unstable();
+        self.installed_sdks = Some(versions.clone());
+
+        Ok(versions)
+    }
+
+    pub fn visual_studio_version(&mut self) -> Result<VsVersion, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Returns the parsed Visual Studio version from `VisualStudioVersion`, obtained with `get_cached()`.
+        //!
+        //! # Panics
+        //!
+        //! See `get_cached()`.
+
+        self.get_cached("VisualStudioVersion")?.parse()
+    }
+
+    pub fn ucrt(&mut self) -> Result<UcrtInfo, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Returns information about the Universal CRT vcvars selected, built from the variables `UniversalCRTSdkDir`, `UCRTVersion` and `VSCMD_ARG_TGT_ARCH`, obtained with `get_cached()`.
+        //!
+        //! # Panics
+        //!
+        //! See `get_cached()`.
+
+        let (sdk_dir, version) = match (
+            self.get_cached("UniversalCRTSdkDir").map(Cow::into_owned),
+            self.get_cached("UCRTVersion").map(Cow::into_owned),
+        ) {
+            (Ok(sdk_dir), Ok(version)) => (
+                PathBuf::from(sdk_dir),
+                version.trim_end_matches('\\').to_owned(),
+            ),
+            (Err(VcvarsError::VarNotFound(_)), _) | (_, Err(VcvarsError::VarNotFound(_))) => {
+                return Err(VcvarsError::UcrtNotFound);
+            }
+            (Err(err), _) | (_, Err(err)) => return Err(err),
+        };
+        let target_arch = self.get_cached("VSCMD_ARG_TGT_ARCH")?.into_owned();
+
+        let include_dir = sdk_dir.join("Include").join(&version).join("ucrt");
+        let lib_dir = sdk_dir
+            .join("Lib")
+            .join(&version)
+            .join("ucrt")
+            .join(target_arch);
+
+        if !include_dir.is_dir() || !lib_dir.is_dir() {
+            return Err(VcvarsError::UcrtNotFound);
+        }
+
+        Ok(UcrtInfo {
+            sdk_dir,
+            version,
+            include_dir,
+            lib_dir,
+        })
+    }
+
+    pub fn changed_vars(&mut self) -> Result<HashMap<String, String>, VcvarsError> {
+        #![allow(clippy::missing_errors_doc, clippy::missing_panics_doc)]
+        //! Like `get_all()`, but only returns the variables that weren't set in the current process when vcvars was run or whose values differed. Keys are compared case-insensitively.
+
+        self.ensure_env_map()?;
+        let env_map = self.env_map.as_ref().unwrap();
+        let parent_env_map = self.parent_env_map.as_ref();
+
+        Ok(env_map
+            .iter()
+            .filter(|&(key, value)| {
+                parent_env_map.and_then(|parent_env_map| parent_env_map.get(key)) != Some(value)
+            })
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect())
+    }
+
+    pub fn origin(&mut self, var_name: impl AsRef<str>) -> Result<VarOrigin, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Tells whether vcvars added or modified `var_name` or left it unchanged, compared to the env vars of the current process when vcvars was run. The key is compared case-insensitively, the value exactly.
+
+        let var_name = var_name.as_ref();
+
+        let key = var_name.to_uppercase();
+        let Some(value) = self.ensure_env_map()?.get(&key).cloned() else {
+            return Err(VcvarsError::VarNotFound(var_name.to_owned()));
+        };
+
+        match self
+            .parent_env_map
+            .as_ref()
+            .and_then(|parent_env_map| parent_env_map.get(&key))
+        {
+            None => Ok(VarOrigin::Added),
+            Some(previous) if *previous == value => Ok(VarOrigin::Unchanged),
+            Some(previous) => Ok(VarOrigin::Modified {
+                previous: previous.clone(),
+            }),
+        }
+    }
+
+    pub fn atlmfc(&mut self) -> Result<Option<AtlMfcDirs>, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Returns the ATL/MFC include and lib directories in `VCToolsInstallDir`, with the lib directory for `VSCMD_ARG_TGT_ARCH`, obtained with `get_cached()`. Returns `None` if the ATL/MFC component isn't installed.
+        //!
+        //! # Panics
+        //!
+        //! See `get_cached()`.
+
+        let mut atlmfc_dir = PathBuf::from(self.get_cached("VCToolsInstallDir")?.into_owned());
+        atlmfc_dir.push("atlmfc");
+        let target_arch = self.get_cached("VSCMD_ARG_TGT_ARCH")?.into_owned();
+
+        let include = atlmfc_dir.join("include");
+        let lib = atlmfc_dir.join("lib").join(target_arch);
+
+        if include.is_dir() && lib.is_dir() {
+            Ok(Some(AtlMfcDirs { include, lib }))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn require_atlmfc(&mut self) -> Result<AtlMfcDirs, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Like `atlmfc()`, but returns an error naming the Visual Studio component to install if ATL/MFC isn't installed.
+        //!
+        //! # Panics
+        //!
+        //! See `get_cached()`.
+
+        self.atlmfc()?.ok_or(VcvarsError::AtlMfcNotInstalled)
+    }
+
+    pub fn vscmd(&mut self) -> Result<VsCmdArgs, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Returns the parsed values of the variables `VSCMD_ARG_HOST_ARCH`, `VSCMD_ARG_TGT_ARCH`, `VSCMD_ARG_app_plat` and `VSCMD_VER`, obtained with `get_cached_opt()`, which tell what vcvars was actually run for. Variables that aren't set, as with older Visual Studio versions, result in `None`.
+        //!
+        //! # Panics
+        //!
+        //! See `get_cached()`.
+
+        Ok(VsCmdArgs {
+            host_arch: self
+                .get_cached_opt("VSCMD_ARG_HOST_ARCH")?
+                .map(|value| value.parse())
+                .transpose()?,
+            target_arch: self
+                .get_cached_opt("VSCMD_ARG_TGT_ARCH")?
+                .map(|value| value.parse())
+                .transpose()?,
+            app_platform: self
+                .get_cached_opt("VSCMD_ARG_app_plat")?
+                .map(|value| value.parse())
+                .transpose()?,
+            version: self
+                .get_cached_opt("VSCMD_VER")?
+                .map(|value| value.parse())
+                .transpose()?,
+        })
+    }
+
+    pub fn redist_dir(&mut self) -> Result<PathBuf, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Returns the directory with the redistributable files for `VSCMD_ARG_TGT_ARCH` in `VCToolsRedistDir`, obtained with `get_cached()`.
+        //!
+        //! # Panics
+        //!
+        //! See `get_cached()`.
+
+        let mut dir = PathBuf::from(self.get_cached("VCToolsRedistDir")?.into_owned());
+        dir.push(self.get_cached("VSCMD_ARG_TGT_ARCH")?.as_ref());
+
+        Ok(dir)
+    }
+
+    pub fn crt_redist_dlls(&mut self, kind: CrtRedistKind) -> Result<Vec<PathBuf>, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Returns the sorted paths of the CRT DLLs like `vcruntime140.dll` in the `Microsoft.VC14x.CRT` directory (or `Microsoft.VC14x.DebugCRT` in `debug_nonredist`) of the redistributable files for `VSCMD_ARG_TGT_ARCH`.
+        //!
+        //! # Panics
+        //!
+        //! See `get_cached()`.
+
+        let (base_dir, dir_name_suffix) = match kind {
+            CrtRedistKind::Release => (self.redist_dir()?, ".CRT"),
+            CrtRedistKind::Debug => {
+                let mut dir = PathBuf::from(self.get_cached("VCToolsRedistDir")?.into_owned());
+                dir.push("debug_nonredist");
+                dir.push(self.get_cached("VSCMD_ARG_TGT_ARCH")?.as_ref());
+
+                (dir, ".DebugCRT")
+            }
+        };
+
+        let read_dir = |dir: &Path| match fs::read_dir(dir) {
+            Ok(entries) => Ok(entries.filter_map(Result::ok).map(|entry| entry.path())),
+            Err(_) => Err(VcvarsError::CrtRedistNotFound(
+                dir.to_string_lossy().into_owned(),
+            )),
+        };
+
+        let Some(crt_dir) = read_dir(&base_dir)?.find(|path| {
+            path.is_dir()
+                && path.file_name().is_some_and(|name| {
+                    let name = name.to_string_lossy();
+                    name.starts_with("Microsoft.VC") && name.ends_with(dir_name_suffix)
+                })
+        }) else {
+            return Err(VcvarsError::CrtRedistNotFound(
+                base_dir.to_string_lossy().into_owned(),
+            ));
+        };
+
+        Ok(read_dir(&crt_dir)?
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|extension| extension.eq_ignore_ascii_case("dll"))
+            })
+            .sorted()
+            .collect())
+    }
+
+    pub fn dia_sdk(&mut self) -> Result<Option<DiaSdk>, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Returns the paths of the DIA SDK in the Visual Studio installation directory found by `vswhere.exe`, for `VSCMD_ARG_TGT_ARCH`, obtained with `get_cached()`. Returns `None` if the DIA SDK directory doesn't exist.
+        //!
+        //! # Panics
+        //!
+        //! See `get_cached()`.
+
+        let root = self.ensure_visual_studio_dir()?.join("DIA SDK");
+        if !root.is_dir() {
+            return Ok(None);
+        }
+
+        let arch_subdir = match self.get_cached("VSCMD_ARG_TGT_ARCH")?.parse()? {
+            Arch::X86 => "",
+            Arch::X64 => "amd64",
+            Arch::Arm => "arm",
+            Arch::Arm64 | Arch::Arm64Ec => "arm64",
+        };
+
+        Ok(Some(DiaSdk {
+            include: root.join("include"),
+            lib: root.join("lib").join(arch_subdir),
+            msdia_dll: root.join("bin").join(arch_subdir).join("msdia140.dll"),
+            root,
+        }))
+    }
+
+    pub fn llvm_bin_dir(&mut self) -> Result<Option<PathBuf>, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Returns the `bin` directory of the LLVM toolchain bundled with Visual Studio in `VC\Tools\Llvm` of the installation directory found by `vswhere.exe`. The subdirectory for the host architecture (`x64` or `ARM64`) is preferred, falling back to the `bin` directory directly in `Llvm`, which contains x86 binaries. Returns `None` if the "C++ Clang tools" component isn't installed.
+
+        let mut llvm_dir = self.ensure_visual_studio_dir()?;
+        llvm_dir.push("VC");
+        llvm_dir.push("Tools");
+        llvm_dir.push("Llvm");
+
+        let host_subdirs: &[&str] = match Arch::from_rust_arch(env::consts::ARCH) {
+            Some(Arch::X64) => &["x64"],
+            Some(Arch::Arm64) => &["ARM64", "x64"],
+            _ => &[],
+        };
+
+        Ok(host_subdirs
+            .iter()
+            .map(|subdir| llvm_dir.join(subdir).join("bin"))
+            .chain([llvm_dir.join("bin")])
+            .find(|bin_dir| bin_dir.join("clang-cl.exe").is_file()))
+    }
+
+    pub fn clang_cl(&mut self) -> Result<Option<PathBuf>, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Returns the path of `clang-cl.exe` in `llvm_bin_dir()`. Returns `None` if the "C++ Clang tools" component isn't installed.
+
+        Ok(self
+            .llvm_bin_dir()?
+            .map(|bin_dir| bin_dir.join("clang-cl.exe")))
+    }
+
+    pub fn bundled_cmake(&mut self) -> Result<Option<PathBuf>, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Returns the path of the `cmake.exe` bundled with Visual Studio in the installation directory found by `vswhere.exe`. Returns `None` if the component `Microsoft.VisualStudio.Component.VC.CMake.Project` isn't installed.
+
+        self.bundled_cmake_file(&["CMake", "bin", "cmake.exe"])
+    }
+
+    pub fn bundled_ninja(&mut self) -> Result<Option<PathBuf>, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Returns the path of the `ninja.exe` bundled with Visual Studio in the installation directory found by `vswhere.exe`. Returns `None` if the component `Microsoft.VisualStudio.Component.VC.CMake.Project`, which contains it, isn't installed.
+
+        self.bundled_cmake_file(&["Ninja", "ninja.exe"])
+    }
+
+    fn bundled_cmake_file(&mut self, components: &[&str]) -> Result<Option<PathBuf>, VcvarsError> {
+        let mut path = self.ensure_visual_studio_dir()?;
+        path.push(r"Common7\IDE\CommonExtensions\Microsoft\CMake");
+        path.extend(components);
+
+        Ok(path.is_file().then_some(path))
+    }
+
+    pub fn bundled_vcpkg(&mut self) -> Result<Option<PathBuf>, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Returns the root directory of the vcpkg instance bundled with Visual Studio 2022 and later, `VC\vcpkg` in the installation directory found by `vswhere.exe`, if it contains `vcpkg.exe`. Returns `None` otherwise. Like values obtained with `get_cached()`, the result is cached in a file.
+        //!
+        //! # Panics
+        //!
+        //! See `get_cached()`.
+
+        let root = self.get_cached_metadata("bundled_vcpkg", |vcvars| {
+            let mut root = vcvars.ensure_visual_studio_dir()?;
+            root.push("VC");
+            root.push("vcpkg");
+
+            if root.join("vcpkg.exe").is_file() {
+                Ok(root.to_string_lossy().into_owned())
+            } else {
+                Ok(String::new())
+            }
+        })?;
+
+        Ok((!root.is_empty()).then(|| PathBuf::from(root)))
+    }
+
+    pub fn bundled_vcpkg_env(&mut self) -> Result<Option<Vec<(String, String)>>, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Returns the env vars to set for a command to use the vcpkg instance from `bundled_vcpkg()`: `VCPKG_ROOT`, and `PATH` with vcpkg's root directory prepended to vcvars' `PATH`, obtained with `get_cached()`. Returns `None` if there's no bundled vcpkg instance.
+        //!
+        //! # Panics
+        //!
+        //! See `get_cached()`.
+
+        let Some(root) = self.bundled_vcpkg()? else {
+            return Ok(None);
+        };
+        let root = root.to_string_lossy().into_owned();
+        let path = format!("{root};{}", self.get_cached("PATH")?);
+
+        Ok(Some(vec![
+            ("VCPKG_ROOT".to_owned(), root),
+            ("PATH".to_owned(), path),
+        ]))
+    }
+
+    fn ensure_cache_dir(&mut self) -> Result<PathBuf, VcvarsError> {
+        //! Creates the cache directory for the configuration and architecture, if not present, and returns its path. If its manifest file `=config.txt` isn't current according to `is_manifest_current()`, the cache files in it are removed, since they're stale, and the manifest file is rewritten with just the `config_key()`. This is the case when the directory was used for another configuration with the same shortened `cache_key()`, or when the installation changed, like by a Visual Studio update, after which the cached paths may not exist anymore.
+
+        self.ensure_selected_installation()?;
+
+        let cache_dir = self.arch_cache_dir(self.resolved_target_arch()?)?;
+        if let Err(err) = fs::create_dir_all(&cache_dir) {
+            return Err(VcvarsError::CacheFailed(
+                cache_dir.to_string_lossy().into_owned(),
+                err,
+            ));
+        }
+
+        let manifest_file = cache_dir.join("=config.txt");
+        let config_key = self.config_key();
+        let is_current = || {
+            read_cache_file(&manifest_file)
+                .is_some_and(|manifest| is_manifest_current(&manifest, &config_key))
+        };
+        if !is_current() {
+            // Another process may have rewritten the manifest file in the meantime, after which its cache files must be kept.
+            let _lock = CacheLock::acquire(&cache_dir, CACHE_LOCK_TIMEOUT);
+            if is_current() {
+                return Ok(cache_dir);
+            }
+
+            remove_cache_files(&cache_dir);
+
+            if let Err(err) = write_cache_file(&manifest_file, &config_key) {
+                return Err(VcvarsError::CacheFailed(
+                    manifest_file.to_string_lossy().into_owned(),
+                    err,
+                ));
+            }
+        }
+
+        Ok(cache_dir)
+    }
+
+    fn ensure_cached_env_map(&mut self) -> Result<&EnvMap, VcvarsError> {
+        //! Reads the vcvars env vars from the cache file, if not done previously. If the file isn't present, or is empty or unreadable, like after an interrupted build of an earlier version of this crate, runs vcvars, if not done previously, and (re)creates the file, removing the cache files of earlier versions of this crate.
+
+        if self.cached_env_map.is_none() {
+            let cache_dir = self.ensure_cache_dir()?;
+
+            let cache_file = cache_dir.join("=env.txt");
+            let mut lock = None;
+            let content = read_cache_file(&cache_file).or_else(|| {
+                // Another process may be running vcvars, whose cache file is then read after waiting.
+                lock = CacheLock::acquire(&cache_dir, CACHE_LOCK_TIMEOUT);
+                read_cache_file(&cache_file)
+            });
+
+            let env_map = if let Some(content) = content {
+                parse_env_lines(&content)
+            } else {
+                let env_map = self.ensure_env_map()?.clone();
+                self.write_env_cache_file(&cache_dir, &env_map)?;
+                // Files of earlier versions of this crate, which lacked the subdirectories per architecture.
+                remove_cache_files(cache_dir.parent().unwrap());
+
+                env_map
+            };
+
+            self.cached_env_map = Some(env_map);
+        }
+
+        Ok(self.cached_env_map.as_ref().unwrap())
+    }
+
+    fn write_env_cache_file(&self, cache_dir: &Path, env_map: &EnvMap) -> Result<(), VcvarsError> {
+        //! Writes the env vars to the cache file `=env.txt` after running vcvars, the host architecture whose tools were used to `=host_arch.txt`, and rewrites the manifest file `=config.txt` with the `config_key()` and, per file of `installation_stamp_files()`, its path and `file_stamp()` on two lines.
+
+        let cache_file = cache_dir.join("=env.txt");
+        let manifest_file = cache_dir.join("=config.txt");
+        let mut manifest = self.config_key();
+        for path in self.installation_stamp_files() {
+            manifest = format!("{manifest}\n{}\n{}", path.display(), file_stamp(&path));
+        }
+
+        let mut files = vec![
+            (cache_file, format_env_lines(env_map)),
+            (manifest_file, manifest),
+        ];
+        if let Some(host_arch) = self.run_host_arch {
+            // Tried first in later build script runs.
+            files.push((
+                cache_dir.join("=host_arch.txt"),
+                host_arch.as_str().to_owned(),
+            ));
+        }
+
+        for (path, content) in &files {
+            if let Err(err) = write_cache_file(path, content) {
+                return Err(VcvarsError::CacheFailed(
+                    path.to_string_lossy().into_owned(),
+                    err,
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn installation_stamp_files(&self) -> Vec<PathBuf> {
+        //! Returns the files whose changes indicate that the installation vcvars was run from changed, like by a Visual Studio update, after which the cached paths may not exist anymore. These are the vcvars script that was run and `Microsoft.VCToolsVersion.default.txt`, which names the default toolset, since an update may change just the latter.
+
+        let Some(vcvars_path) = &self.vcvarsall_path else {
+            return Vec::new();
+        };
+        let build_dir = match &self.visual_studio_dir {
+            Some(dir) => dir.join(r"VC\Auxiliary\Build"),
+            None => vcvars_path.parent().unwrap_or(Path::new("")).to_owned(),
+        };
+
+        vec![
+            vcvars_path.clone(),
+            build_dir.join("Microsoft.VCToolsVersion.default.txt"),
+        ]
+    }
+
+    fn get_cached_metadata(
+        &mut self,
+        name: &str,
+        make_value: impl FnOnce(&mut Self) -> Result<String, VcvarsError>,
+    ) -> Result<String, VcvarsError> {
+        //! Like `get_cached()`, but for a value this crate determines itself. The cache file names start with `=`, which variable names can't contain. Since empty cache files are treated as missing, an empty value is determined again each time.
+
+        let cache_dir = self.ensure_cache_dir()?;
+        let cache_file = cache_dir.join(format!("={name}.txt"));
+
+        let mut lock = None;
+        let value = read_cache_file(&cache_file).or_else(|| {
+            lock = CacheLock::acquire(&cache_dir, CACHE_LOCK_TIMEOUT);
+            read_cache_file(&cache_file)
+        });
+
+        if let Some(value) = value {
+            Ok(value)
+        } else {
+            let value = make_value(self)?;
+            match write_cache_file(&cache_file, &value) {
+                Ok(()) => Ok(value),
+                Err(err) => Err(VcvarsError::CacheFailed(
+                    cache_file.to_string_lossy().into_owned(),
+                    err,
+                )),
+            }
+        }
+    }
+
+    pub fn asan_runtime(&mut self) -> Result<Option<AsanRuntime>, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Returns the runtime files of the address sanitizer (ASAN) of the toolset in `VCToolsInstallDir`, for the host and target architecture vcvars was run for. The variables are obtained with `get_cached()`. Returns `None` if the ASAN component isn't installed.
+        //!
+        //! # Panics
+        //!
+        //! See `get_cached()`.
+
+        let tools_dir = PathBuf::from(self.get_cached("VCToolsInstallDir")?.into_owned());
+        let host_arch = self.get_cached("VSCMD_ARG_HOST_ARCH")?.into_owned();
+        let target_arch = self.get_cached("VSCMD_ARG_TGT_ARCH")?.into_owned();
+
+        let lib_dir = tools_dir.join("lib").join(&target_arch);
+        let dll_dir = tools_dir
+            .join("bin")
+            .join(format!("Host{host_arch}"))
+            .join(&target_arch);
+
+        let libs = files_with_prefix(&lib_dir, "clang_rt.asan", "lib");
+        let dlls = files_with_prefix(&dll_dir, "clang_rt.asan", "dll");
+
+        if libs.is_empty() || dlls.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(AsanRuntime {
+            lib_dir,
+            libs,
+            dll_dir,
+            dlls,
+        }))
+    }
+
+    pub fn get_all(&mut self) -> Result<&HashMap<String, String>, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Runs vcvars and creates a memory cache of its variables, if not done previously, and returns the whole map. Contains every variable listed by `cmd.exe`'s `set` command after vcvars ran. The keys are uppercased, as with `get()`.
+
+        self.ensure_env_map()
+    }
+
+    pub fn vars(&mut self) -> Result<impl Iterator<Item = (&str, &str)>, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Like `get_all()`, but returns an iterator over the key-value pairs, sorted by key, so the order is the same across runs.
+
+        Ok(self
+            .ensure_env_map()?
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .sorted_unstable_by_key(|&(key, _)| key))
+    }
+
+    pub fn resolve(&mut self) -> Result<VcvarsEnvironment, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Runs vcvars and creates a memory cache of its variables, if not done previously, and returns an immutable snapshot of them, along with metadata like the Visual Studio installation directory. The snapshot can be cloned cheaply and passed around.
+
+        let env_map = self.ensure_env_map()?.clone();
+
+        Ok(VcvarsEnvironment::new(
+            env_map,
+            self.visual_studio_dir.clone(),
+        ))
+    }
+
+    pub fn for_targets(&mut self, targets: &[Arch]) -> Result<Vec<VcvarsEnvironment>, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Like `resolve()`, but runs vcvars once per target architecture in `targets`, with the configuration of `self` otherwise. `vswhere.exe` is only run once. Useful for ARM64X binaries, for which the sources are compiled for ARM64 and ARM64EC.
+        //!
+        //! If the `OUT_DIR` environment variable is set, like in a build script, the variables are obtained like with `get_cached()`, with a cache file per target architecture, so vcvars isn't run again in later build script runs.
+        //!
+        //! ```ignore
+        //! let mut vcvars = Vcvars::new();
+        //! let environments = vcvars.for_targets(&[Arch::Arm64, Arch::Arm64Ec]).unwrap();
+        //!
+        //! for (environment, flags) in environments.iter().zip([&[][..], &["/arm64EC"]]) {
+        //!     let mut build = cc::Build::new();
+        //!     build.file("src/demo.c").includes(environment.include_dirs().unwrap());
+        //!     for flag in flags {
+        //!         build.flag(flag);
+        //!     }
+        //!     build.compile(if flags.is_empty() { "demo_arm64" } else { "demo_arm64ec" });
+        //! }
+        //!
+        //! println!("cargo:rustc-link-arg=/MACHINE:ARM64X");
+        //! ```
+
+        let visual_studio_dir = self.ensure_visual_studio_dir()?;
+
+        targets
+            .iter()
+            .map(|&arch| {
+                let mut vcvars = Vcvars {
+                    env_map: None,
+                    cached_env_map: None,
+                    parent_env_map: None,
+                    visual_studio_dir: Some(visual_studio_dir.clone()),
+                    vcvarsall_path: None,
+                    command_line: None,
+                    arch_arg: None,
+                    run_host_arch: None,
+                    raw_output: None,
+                    target_arch: Some(arch),
+                    ..self.clone()
+                };
+
+                if env::var_os("OUT_DIR").is_some() {
+                    let env_map = vcvars.ensure_cached_env_map()?.clone();
+                    Ok(VcvarsEnvironment::new(env_map, vcvars.visual_studio_dir))
+                } else {
+                    vcvars.resolve()
+                }
+            })
+            .collect()
+    }
+
+    pub fn installation(&mut self) -> Result<&InstallationInfo, VcvarsError> {
+        #![allow(clippy::missing_errors_doc, clippy::missing_panics_doc)]
+        //! Returns information about the Visual Studio installation vcvars is run from. Runs `vswhere.exe` with the arguments that select the installation, if not done previously.
+
+        if self.installation.is_none() {
+            let (vswhere_path, vswhere_command) = self.vswhere_command()?;
+            self.used_vswhere_path = Some(vswhere_path.clone());
+            let installations = match vswhere::query_installations(&vswhere_path, vswhere_command) {
+                Ok(installations) => installations,
+                Err(VcvarsError::NoVisualStudioFound(args, stderr)) => {
+                    return Err(match self.requires {
+                        Some(component_ids) => VcvarsError::RequiredComponentsNotFound(
+                            component_ids.iter().map(|&id| id.to_owned()).collect(),
+                        ),
+                        None => VcvarsError::NoVisualStudioFound(args, stderr),
+                    });
+                }
+                Err(err) => return Err(err),
+            };
+
+            let installation = if self.selects_from_all() {
+                self.select_installation_from(installations)?
+            } else {
+                if installations.len() > 1 {
+                    let paths = installations
+                        .iter()
+                        .map(|installation| {
+                            installation
+                                .installation_path
+                                .to_string_lossy()
+                                .into_owned()
+                        })
+                        .collect_vec();
+                    if self.fail_on_ambiguous_installation {
+                        return Err(VcvarsError::AmbiguousInstallation(paths));
+                    }
+
+                    self.selection_log = Some(format!(
+                        "`vswhere.exe` returned several installations, of which the first was selected:\n{}",
+                        paths.join("\n")
+                    ));
+                }
+
+                installations
+                    .into_iter()
+                    .next()
+                    .expect("there should've been at least one installation")
+            };
+
+            self.installation = Some(installation);
+        }
+
+        Ok(self.installation.as_ref().unwrap())
+    }
+
+    pub fn installation_version(&mut self) -> Result<&str, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Returns the version of the Visual Studio installation vcvars is run from, like `17.8.34330.188`, from `installation()`. Doesn't require running vcvars. Parse it into a `VsInstallationVersion` for comparisons.
+
+        Ok(&self.installation()?.installation_version)
+    }
+
+    pub fn installation_display_name(&mut self) -> Result<&str, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Returns the display name of the Visual Studio installation vcvars is run from, like `Visual Studio Community 2022`, from `installation()`. Doesn't require running vcvars. Empty for installations found due to `include_legacy()`.
+
+        Ok(&self.installation()?.display_name)
+    }
+
+    pub fn product_id(&mut self) -> Result<&str, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Returns the product ID of the Visual Studio installation vcvars is run from, like `Microsoft.VisualStudio.Product.BuildTools`, from `installation()`. Doesn't require running vcvars. Empty for installations found due to `include_legacy()`.
+
+        Ok(&self.installation()?.product_id)
+    }
+
+    pub fn channel_id(&mut self) -> Result<&str, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Returns the channel ID of the Visual Studio installation vcvars is run from, like `VisualStudio.17.Release` or `VisualStudio.17.Preview`, from `installation()`. Doesn't require running vcvars. Empty for installations found due to `include_legacy()`.
+
+        Ok(&self.installation()?.channel_id)
+    }
+
+    pub fn used_vswhere_path(&self) -> Option<&Path> {
+        #![must_use]
+        //! Returns the path of the `vswhere.exe` that was run, if it was, which can be the per-user one or a standalone copy found on `PATH` if the machine-wide Visual Studio Installer directory lacks it. See `vswhere_path()`. For debugging.
+
+        self.used_vswhere_path.as_deref()
+    }
+
+    pub fn selection_log(&self) -> Option<&str> {
+        #![must_use]
+        //! Returns a description of how the installation was selected, with one line per candidate installation and a last line naming the selected one, if the installation was selected by this crate due to `select_installation()` or `selection_strategy()`, or a note listing the installations, if `vswhere.exe` returned several ones with `not_vswhere_latest_but()`, or a note on how the installation was found otherwise, like via `VSINSTALLDIR` with `prefer_ambient_installation()`. For debugging.
+
+        self.selection_log.as_deref()
+    }
+
+    pub fn vcvarsall_path(&mut self) -> Result<&Path, VcvarsError> {
+        #![allow(clippy::missing_errors_doc, clippy::missing_panics_doc)]
+        //! Returns the path of the `vcvarsall.bat` file used to run vcvars, or of `VsDevCmd.bat` with `use_vsdevcmd()` or `SetupBuildEnv.cmd` with `from_ewdk()`. Runs vcvars and creates a memory cache of its variables, if not done previously. The path is also available if running vcvars failed after the file was found.
+
+        if self.vcvarsall_path.is_none() {
+            if let Err(err) = self.ensure_env_map() {
+                if self.vcvarsall_path.is_none() {
+                    return Err(err);
+                }
+            }
+        }
+
+        Ok(self.vcvarsall_path.as_deref().unwrap())
+    }
+
+    pub fn command_line(&mut self) -> Result<String, VcvarsError> {
+        #![allow(clippy::missing_errors_doc, clippy::missing_panics_doc)]
+        //! Returns the `cmd.exe` command line used to run vcvars, for reproducing problems. Arguments containing spaces are shown in double quotes. Runs vcvars and creates a memory cache of its variables, if not done previously. The command line is also available if running vcvars failed after the command line was determined. Fails with `VcvarsError::AmbientEnvReused` if vcvars wasn't run, because the environment of the current process was reused (see `reuse_ambient_env()`).
+
+        if self.command_line.is_none() {
+            if let Err(err) = self.ensure_env_map() {
+                if self.command_line.is_none() {
+                    return Err(err);
+                }
+            }
+        }
+
+        if self.command_line.is_none() {
+            return Err(VcvarsError::AmbientEnvReused);
+        }
+
+        Ok(self.command_line.clone().unwrap())
+    }
+
+    pub fn arch_arg(&mut self) -> Result<&str, VcvarsError> {
+        #![allow(clippy::missing_errors_doc, clippy::missing_panics_doc)]
+        //! Returns the architecture argument in `vcvarsall.bat`'s syntax that vcvars was successfully run with, like `x64`, or `x86_x64` if the native x64 tools weren't found and the x86-hosted cross tools were used instead. Runs vcvars and creates a memory cache of its variables, if not done previously. For debugging.
+
+        self.ensure_env_map()?;
+
+        Ok(self.arch_arg.as_deref().unwrap())
+    }
+
+    pub fn raw_output(&self) -> Option<&str> {
+        #![must_use]
+        //! Returns the `cmd.exe` stdout output of the vcvars run, converted with [`std::string::String::from_utf8_lossy()`], if vcvars was run and `keep_raw_output()` was enabled, or if vcvars failed.
+
+        self.raw_output.as_deref()
+    }
+
+    pub fn msbuild_path(&mut self) -> Result<PathBuf, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Locates `MSBuild.exe` by running `vswhere.exe` with the arguments `-requires Microsoft.Component.MSBuild -find MSBuild\**\Bin\MSBuild.exe`, in addition to the arguments also used to find Visual Studio when running vcvars (see `not_vswhere_latest_but()`). Returns the first path found. The result is cached in memory.
+
+        if let Some(path) = &self.msbuild_path {
+            return Ok(path.clone());
+        }
+
+        let find_pattern = r"MSBuild\**\Bin\MSBuild.exe";
+        let Some(path) = self
+            .run_vswhere_find(&["-requires", "Microsoft.Component.MSBuild"], find_pattern)?
+            .into_iter()
+            .next()
+        else {
+            return Err(VcvarsError::VswhereFoundNothing(find_pattern.to_owned()));
+        };
+
+        if !path.is_file() {
+            return Err(VcvarsError::FileNotFound(
+                path.to_string_lossy().into_owned(),
+            ));
+        }
+
+        self.msbuild_path = Some(path.clone());
+
+        Ok(path)
+    }
+
+    pub fn vswhere_find(&mut self, pattern: &str) -> Result<Vec<PathBuf>, VcvarsError> {
+        #![allow(clippy::missing_errors_doc)]
+        //! Finds files inside the selected Visual Studio installation by running `vswhere.exe` with the arguments `-find <pattern>`, in addition to the arguments also used to find Visual Studio when running vcvars. The pattern is relative to the installation directory and may contain `*` and `**`, like `Common7\Tools\VsDevCmd.bat` or `**\Hostx64\x64\cl.exe`. Returns all paths found, which may be none. The result is cached in memory per pattern.
+        //!
+        //! ```ignore
+        //! let mut vcvars = Vcvars::new();
+        //! let vstest_paths = vcvars.vswhere_find(r"**\vstest.console.exe")?;
+        //! ```
+
+        if let Some(paths) = self.vswhere_find_results.get(pattern) {
+            return Ok(paths.clone());
+        }
+
+        let paths = self.run_vswhere_find(&[], pattern)?;
+        self.vswhere_find_results
+            .insert(pattern.to_owned(), paths.clone());
+
+        Ok(paths)
+    }
+
+    fn run_vswhere_find(
+        &mut self,
+        extra_args: &[&str],
+        pattern: &str,
+    ) -> Result<Vec<PathBuf>, VcvarsError> {
+        //! Runs `vswhere.exe` with `-find <pattern>` and the arguments that select the Visual Studio installation, plus `extra_args`. Returns the paths found in the selected installation.
+
+        // With `-all`, paths of other installations may be found.
+        let installation_dir = if self.selects_from_all() {
+            Some(self.installation()?.installation_path.clone())
+        } else {
+            None
+        };
+
+        let (vswhere_path, mut vswhere_command) = self.vswhere_command()?;
+        self.used_vswhere_path = Some(vswhere_path.clone());
+        vswhere_command
+            .args(extra_args)
+            .args(["-find", pattern, "-utf8"]);
+        let args = vswhere_command
+            .get_args()
+            .map(|arg| arg.to_string_lossy())
+            .join(" ");
+        let output = match vswhere_command.output() {
+            Ok(output) => output,
+            Err(err) => {
+                return Err(VcvarsError::CouldntRun(
+                    vswhere_path.to_string_lossy().into_owned(),
+                    err,
+                ));
+            }
+        };
+
+        vswhere::check_output(&args, &output)?;
+
+        let stdout = vswhere::decode_output(&output.stdout)?;
+
+        Ok(stdout
+            .lines()
+            .map(str::trim)
+            .filter(|line| {
+                !line.is_empty()
+                    && installation_dir
+                        .as_ref()
+                        .is_none_or(|dir| Path::new(line).starts_with(dir))
+            })
+            .map(PathBuf::from)
+            .collect())
+    }
+
+    fn host_arch(&self, target_arch_name: &str) -> Result<Arch, VcvarsError> {
+        //! Returns the host architecture set with `host()` or the one of the current process. `target_arch_name` is only used for the error. ARM64EC isn't a host architecture of the tools, so it's rejected with `host()`, while an ARM64EC process runs on an ARM64 host.
+
+        if let Some(arch) = self.host_arch {
+            return match arch {
+                Arch::Arm64Ec => Err(VcvarsError::UnsupportedArch(
+                    arch.to_string(),
+                    target_arch_name.to_owned(),
+                )),
+                arch => Ok(arch),
+            };
+        }
+
+        match Arch::from_rust_arch(env::consts::ARCH) {
+            Some(Arch::Arm64Ec) => Ok(Arch::Arm64),
+            Some(arch) => Ok(arch),
+            None => Err(VcvarsError::UnsupportedArch(
+                env::consts::ARCH.to_owned(),
+                target_arch_name.to_owned(),
+            )),
+        }
+    }
+
+    fn effective_host_arch(&mut self, target_arch_name: &str) -> Result<Arch, VcvarsError> {
+        //! Like `host_arch()`, but if an ARM64 host wasn't explicitly set with `host()` and the toolset lacks native ARM64 tools, as with older toolsets, falls back to the x64 tools, which are emulated.
+
+        let host_arch = self.host_arch(target_arch_name)?;
+
+        if host_arch == Arch::Arm64
+            && self.host_arch.is_none()
+            && !self
+                .tools_dir()?
+                .is_some_and(|tools_dir| tools_dir.join(r"bin\Hostarm64").is_dir())
+        {
+            return Ok(Arch::X64);
+        }
+
+        Ok(host_arch)
+    }
+
+    fn tools_dir(&mut self) -> Result<Option<PathBuf>, VcvarsError> {
+        //! Returns the directory of the MSVC toolset vcvars will select, determined without running vcvars, as documented with `available_targets()`.
+
+        let visual_studio_dir = self.ensure_visual_studio_dir()?;
+        let installed_toolsets = self.installed_toolsets();
+
+        let version = if let Some(requested_version) = self.toolset_version {
+            installed_toolsets?
+                .into_iter()
+                .rev()
+                .find(|version| version_matches(&version.to_string(), requested_version))
+        } else {
+            match fs::read_to_string(
+                visual_studio_dir.join(r"VC\Auxiliary\Build\Microsoft.VCToolsVersion.default.txt"),
+            )
+            .ok()
+            .and_then(|version| version.parse::<VcToolsVersion>().ok())
+            {
+                Some(version) => Some(version),
+                None => installed_toolsets?.pop(),
+            }
+        };
+
+        Ok(version.map(|version| {
+            let mut tools_dir = visual_studio_dir;
+            tools_dir.push("VC");
+            tools_dir.push("Tools");
+            tools_dir.push("MSVC");
+            tools_dir.push(version.to_string());
+
+            tools_dir
+        }))
+    }
+
+    fn config_args(&self) -> Vec<String> {
+        //! Returns the arguments to `vcvarsall.bat` that follow the architecture argument, in the documented order. In `VsDevCmd.bat` mode, returns the equivalent arguments for it.
+
+        let mut args = Vec::new();
+
+        if let Some(options) = self.vsdevcmd_options {
+            if options.no_logo {
+                args.push("-no_logo".to_owned());
+            }
+
+            if options.keep_start_dir {
+                args.push("-startdir=none".to_owned());
+            }
+
+            if let Some(platform) = self.app_platform {
+                args.push(format!("-app_platform={platform}"));
+            }
+
+            if let Some(version) = self.sdk_version {
+                args.push(format!("-winsdk={version}"));
+            }
+        } else {
+            match self.app_platform {
+                None | Some(AppPlatform::Desktop) => {}
+                Some(AppPlatform::Uwp) => args.push("uwp".to_owned()),
+                Some(AppPlatform::OneCore) => args.push("onecore".to_owned()),
+            }
+
+            if let Some(version) = self.sdk_version {
+                args.push(version.to_string());
+            }
+        }
+
+        if let Some(version) = self.toolset_version {
+            args.push(format!("-vcvars_ver={version}"));
+        }
+
+        if self.spectre_libs {
+            args.push("-vcvars_spectre_libs=spectre".to_owned());
+        }
+
+        if let Some(extra_args) = self.extra_vcvarsall_args {
+            args.extend(extra_args.iter().map(|&arg| arg.to_owned()));
+        }
+
+        args
+    }
+
+    fn cache_key(&self) -> String {
+        //! Returns the name of the cache directory for the configuration, which is `config_key()` made a valid filename. Keys longer than `MAX_CACHE_KEY_LEN` are shortened and suffixed with a hash of the configuration, to stay within path length limits. Since different configurations can result in the same name this way, the cache directory's manifest file holds the configuration (see `ensure_cache_dir()`). Empty for the default configuration.
+
+        let key = filenamify(self.config_key());
+        if key.len() <= MAX_CACHE_KEY_LEN {
+            return key;
+        }
+
+        let prefix = key
+            .chars()
+            .scan(0, |len, char| {
+                *len += char.len_utf8();
+                (*len <= MAX_CACHE_KEY_LEN - 17).then_some(char)
+            })
+            .collect::<String>();
+
+        format!("{prefix}~{:016x}", fnv1a_hash(self.config_key().as_bytes()))
+    }
+
+    fn config_key(&self) -> String {
+        //! Returns a string identifying the configuration, like the vswhere and vcvars arguments, to keep the cache files of different configurations apart. Empty for the default configuration.
+
+        let mut key_parts = Vec::new();
+
+        if self.vsdevcmd_options.is_some() {
+            key_parts.push("vsdevcmd".to_owned());
+        }
+
+        if self.telemetry {
+            key_parts.push("telemetry".to_owned());
+        }
+
+        if !self.include_prereleases {
+            key_parts.push("no_prereleases".to_owned());
+        }
+
+        if self.include_legacy {
+            key_parts.push("legacy".to_owned());
+        }
+
+        if self.allow_vs2015 {
+            key_parts.push("allow_vs2015".to_owned());
+        }
+
+        if let Some(product_ids) = self.products {
+            key_parts.push(format!("products={}", product_ids.join(",")));
+        }
+
+        if let Some(component_ids) = self.requires {
+            key_parts.push(format!("requires={}", component_ids.join(",")));
+        }
+
+        if self.requires_any {
+            key_parts.push("requires_any".to_owned());
+        }
+
+        if let Some(range) = self.version_range {
+            key_parts.push(format!("version={range}"));
+        }
+
+        if let Some(args) = self.vswhere_latest_substitute_args {
+            key_parts.push(format!("vswhere_args={}", args.join(" ")));
+        }
+
+        match self.selection_strategy {
+            SelectionStrategy::NewestInstallation => {}
+            SelectionStrategy::NewestStable => key_parts.push("newest_stable".to_owned()),
+            SelectionStrategy::NewestToolset => key_parts.push("newest_toolset".to_owned()),
+            SelectionStrategy::Custom(_) => key_parts.push("custom_selection".to_owned()),
+        }
+
+        if let Some(root) = &self.ewdk_root {
+            key_parts.push(format!("ewdk={}", root.display()));
+        } else if let Some(path) = env::var_os(VCVARSALL_ENV_VAR) {
+            key_parts.push(format!("vcvarsall={}", Path::new(&path).display()));
+        } else if let Some(dir) = &self.installation_dir {
+            key_parts.push(format!("installation_dir={}", dir.display()));
+        } else if let Some(dir) = self.ambient_installation_dir() {
+            key_parts.push(format!("ambient_installation={}", dir.display()));
+        } else if self.selects_from_all() {
+            if let Some(installation) = &self.installation {
+                key_parts.push(format!("instance={}", installation.instance_id));
+            }
+        }
+
+        if self.clean_env {
+            key_parts.push("clean_env".to_owned());
+        }
+
+        if !self.reuse_ambient_env {
+            key_parts.push("no_ambient_env".to_owned());
+        }
+
+        match self.toolchain_preference {
+            ToolchainPreference::PreferNative => {}
+            ToolchainPreference::NativeHostOnly => key_parts.push("native_host_only".to_owned()),
+            ToolchainPreference::PreferCross => key_parts.push("prefer_cross".to_owned()),
+        }
+
+        if let Some(arch) = self.host_arch {
+            key_parts.push(format!("host={arch}"));
+        }
+
+        if let Some(arch) = self.target_arch {
+            key_parts.push(format!("target={arch}"));
+        }
+
+        key_parts.extend(self.config_args());
+
+        for (key, value) in &self.child_env {
+            key_parts.push(format!("{key}={value}"));
+        }
+
+        key_parts.join(" ")
+    }
+
+    fn validate_version_range(&self) -> Result<(), VcvarsError> {
+        //! Checks the version range set with `version_range()`, if any, as part of `validate()`.
+
+        match self.version_range {
+            Some(range) if !is_version_range(range) => {
+                Err(VcvarsError::InvalidConfiguration(format!(
+                    "version range `{range}` isn't a version like `17.0` or a range like `[16.0,18.0)`"
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn vswhere_command(&self) -> Result<(PathBuf, Command), VcvarsError> {
+        //! Returns the path of `vswhere.exe` and a command to run it with the arguments that select the Visual Studio installation.
+
+        self.validate_version_range()?;
+
+        let vswhere_path = vswhere::vswhere_path(self.vswhere_path.as_deref())?;
+        vswhere::check_version(&vswhere_path)?;
+
+        let mut command = Command::new(&vswhere_path);
+        if self.include_prereleases {
+            command.arg("-prerelease"); // Allow Visual Studio Preview.
+        }
+        if self.include_legacy {
+            command.arg("-legacy");
+        } else {
+            command
+                .arg("-products")
+                .args(self.products.unwrap_or(&["*"])); // Include Build Tools installations.
+        }
+        if let Some(component_ids) = self.requires {
+            command.arg("-requires").args(component_ids);
+        }
+        if self.requires_any {
+            command.arg("-requiresAny");
+        }
+        if let Some(range) = self.version_range {
+            command.args(["-version", range]);
+        }
+        command.args(
+            self.vswhere_latest_substitute_args
+                .unwrap_or(if self.selects_from_all() {
+                    &["-all"]
+                } else {
+                    &["-latest"]
+                }),
+        );
+
+        Ok((vswhere_path, command))
+    }
+
+    fn selects_from_all(&self) -> bool {
+        //! Returns whether the installation is selected from all installations by this crate, instead of by `vswhere.exe` with `-latest`.
+
+        self.installation_selector.is_some()
+            || !matches!(
+                self.selection_strategy,
+                SelectionStrategy::NewestInstallation
+            )
+    }
+
+    fn select_installation_from(
+        &mut self,
+        mut installations: Vec<InstallationInfo>,
+    ) -> Result<InstallationInfo, VcvarsError> {
+        //! Selects the installation according to `select_installation()` and `selection_strategy()` and records the decision for `selection_log()`.
+
+        installations.retain(|installation| installation.is_complete);
+        installations.sort_by_cached_key(|installation| {
+            Reverse(vswhere::version_parts(&installation.installation_version))
+        });
+
+        let describe = |installation: &InstallationInfo| {
+            format!(
+                "{} ({})",
+                installation.display_name, installation.installation_version
+            )
+        };
+
+        if let Some(selector) = &self.installation_selector {
+            let (accepted, rejected): (Vec<_>, Vec<_>) = installations
+                .into_iter()
+                .partition(|installation| selector(installation));
+            if accepted.is_empty() {
+                return Err(VcvarsError::NoInstallationSelected(
+                    rejected.iter().map(describe).collect(),
+                ));
+            }
+
+            installations = accepted;
+        }
+
+        let mut log_lines = Vec::new();
+
+        let index = match &self.selection_strategy {
+            SelectionStrategy::NewestInstallation => Some(0),
+            SelectionStrategy::NewestStable => installations
+                .iter()
+                .position(|installation| !installation.is_prerelease),
+            SelectionStrategy::NewestToolset => {
+                let mut newest: Option<(usize, VcToolsVersion)> = None;
+
+                for (index, installation) in installations.iter().enumerate() {
+                    let toolset = toolsets_in(&installation.installation_path)
+                        .and_then(|toolsets| toolsets.last().copied());
+                    log_lines.push(format!(
+                        "candidate `{}` has newest toolset {}",
+                        describe(installation),
+                        toolset.map_or_else(|| "none".to_owned(), |version| format!("`{version}`"))
+                    ));
+
+                    if let Some(toolset) = toolset {
+                        if newest.is_none_or(|(_, newest_toolset)| toolset > newest_toolset) {
+                            newest = Some((index, toolset));
+                        }
+                    }
+                }
+
+                Some(newest.map_or(0, |(index, _)| index))
+            }
+            SelectionStrategy::Custom(strategy) => strategy(&installations),
+        };
+
+        let installation = match index {
+            Some(index) if index < installations.len() => installations.swap_remove(index),
+            _ => {
+                return Err(VcvarsError::NoInstallationSelected(
+                    installations.iter().map(describe).collect(),
+                ));
+            }
+        };
+
+        log_lines.push(format!("selected `{}`", describe(&installation)));
+        self.selection_log = Some(log_lines.join("\n"));
+
+        Ok(installation)
+    }
+
+    fn ensure_selected_installation(&mut self) -> Result<(), VcvarsError> {
+        //! Runs `vswhere.exe` to select the installation by this crate, if applicable and not done previously, since `cache_key()` depends on it.
+
+        if self.installation_dir.is_none()
+            && self.ewdk_root.is_none()
+            && env::var_os(VCVARSALL_ENV_VAR).is_none()
+            && self.selects_from_all()
+        {
+            self.installation()?;
+        }
+
+        Ok(())
+    }
+
+    fn ensure_visual_studio_dir(&mut self) -> Result<PathBuf, VcvarsError> {
+        //! Runs `vswhere.exe` to find the Visual Studio installation directory, if not done previously and not set with `with_installation_dir()`.
+
+        if let Some(dir) = &self.visual_studio_dir {
+            return Ok(dir.clone());
+        }
+
+        if let Some(dir) = self.ambient_installation_dir() {
+            self.selection_log = Some(format!(
+                "`{}`, named by `VSINSTALLDIR`, was selected, since Cargo was invoked from its Developer Command Prompt",
+                dir.display()
+            ));
+            self.visual_studio_dir = Some(dir.clone());
+
+            return Ok(dir);
+        }
+
+        let dir = match &self.installation_dir {
+            Some(dir) if !dir.is_dir() => {
+                return Err(VcvarsError::FileNotFound(
+                    dir.to_string_lossy().into_owned(),
+                ));
+            }
+            Some(dir) => Ok(dir.clone()),
+            None => match self
+                .installation()
+                .map(|installation| installation.installation_path.clone())
+            {
+                Ok(dir) => Ok(dir),
+                Err(VcvarsError::VswhereNotFound(probed_paths))
+                    if !self.has_installation_selection_options() =>
+                {
+                    self.registry_visual_studio_dir(probed_paths)
+                }
+                Err(err) => Err(err),
+            },
+        };
+
+        let dir = match dir {
+            Err(
+                err @ (VcvarsError::NoVisualStudioFound(..)
+                | VcvarsError::VswhereNotFound(_)
+                | VcvarsError::VisualStudioNotFound(_)),
+            ) if self.allow_vs2015 => {
+                let Some(dir) = vs2015_dir() else {
+                    return Err(err);
+                };
+
+                self.selection_log = Some(format!(
+                    "no newer installation was found, so Visual Studio 2015 in `{}` was selected",
+                    dir.display()
+                ));
+
+                dir
+            }
+            dir => dir?,
+        };
+
+        self.visual_studio_dir = Some(dir.clone());
+
+        Ok(dir)
+    }
+
+    fn ambient_installation_dir(&self) -> Option<PathBuf> {
+        //! Returns the installation directory named by `VSINSTALLDIR`, if `prefer_ambient_installation()` applies to it (see there).
+
+        if !self.prefer_ambient_installation
+            || self.installation_dir.is_some()
+            || self.has_installation_selection_options()
+        {
+            return None;
+        }
+
+        // Not valid UTF-8, it couldn't be passed to `cmd.exe`.
+        let dir = env::var(vars::VSINSTALLDIR).ok()?;
+        let dir = PathBuf::from(dir.trim_end_matches('\\'));
+
+        dir.join(r"VC\Auxiliary\Build\vcvarsall.bat")
+            .is_file()
+            .then_some(dir)
+    }
+
+    fn registry_visual_studio_dir(
+        &mut self,
+        probed_vswhere_paths: Vec<String>,
+    ) -> Result<PathBuf, VcvarsError> {
+        //! Finds the Visual Studio installation directory in the registry, as a fallback if `vswhere.exe` couldn't be found.
+        //!
+        //! The precedence order of the discovery mechanisms is:
+        //! 1. The directory set with `with_installation_dir()`.
+        //! 2. The directory named by `VSINSTALLDIR`, as described with `prefer_ambient_installation()`.
+        //! 3. `vswhere.exe` at the path set with `vswhere_path()` or found as described there.
+        //! 4. The registry key `VS7`, if no options selecting the installation are set, since they can't be applied to it. Visual Studio 2019 and later don't register there, though.
+        //! 5. Visual Studio 2015, if `allow_vs2015()` is set and no installation was found otherwise.
+
+        let Some((version, dir)) = registry::newest_vs7_installation() else {
+            return Err(VcvarsError::VisualStudioNotFound(
+                probed_vswhere_paths
+                    .into_iter()
+                    .chain(registry::vs7_key_names())
+                    .collect(),
+            ));
+        };
+
+        self.selection_log = Some(format!(
+            "`vswhere.exe` wasn't found, so version `{version}` was selected from the registry key `{}`",
+            registry::VS7_KEY
+        ));
+
+        Ok(dir)
+    }
+
+    fn has_installation_selection_options(&self) -> bool {
+        //! Returns whether options are set that select the installation with `vswhere.exe`.
+
+        self.products.is_some()
+            || self.requires.is_some()
+            || self.version_range.is_some()
+            || self.vswhere_latest_substitute_args.is_some()
+            || self.include_legacy
+            || self.selects_from_all()
+    }
+
+    fn ensure_env_map(&mut self) -> Result<&EnvMap, VcvarsError> {
+        if self.env_map.is_none() {
+            self.env_map = Some(Self::make_env_map(self)?);
+        };
+
+        Ok(self.env_map.as_ref().unwrap())
+    }
+
+    fn make_env_map(&mut self) -> Result<EnvMap, VcvarsError> {
+        #![allow(clippy::too_many_lines)] //TODO
+
+        self.validate()?;
+
+        if !Self::needed() {
+            return Err(VcvarsError::NonMsvcTarget(
+                env::var("CARGO_CFG_TARGET_ENV").unwrap_or_default(),
+            ));
+        }
+
+        // Read env var dependencies.
+        let Ok(win_dir) = env::var("WINDIR") else {
+            return Err(VcvarsError::MissingEnvVarDependency("WINDIR".to_owned()));
+        };
+
+        let target_arch = self.resolved_target_arch()?;
+
+        // Determine the env vars vcvars starts with.
+        let clean_env = self.clean_env.then(|| clean_env(&win_dir));
+
+        // Remember env vars to be able to tell vcvars' changes later.
+        self.parent_env_map = Some(match &clean_env {
+            Some(clean_env) => clean_env
+                .iter()
+                .map(|(key, value)| (key.to_uppercase(), value.to_string_lossy().into_owned()))
+                .collect(),
+            None => env::vars_os()
+                .map(|(key, value)| {
+                    (
+                        key.to_string_lossy().to_uppercase(),
+                        value.to_string_lossy().into_owned(),
+                    )
+                })
+                .collect(),
+        });
+
+        if let Some(env) = self.ambient_env_map(target_arch) {
+            return Ok(env);
+        }
+
+        // Find vcvars and determine its args.
+        let (vcvars_path, is_legacy_vcvarsall) = self.locate_vcvars()?;
+
+        if !vcvars_path.is_file() {
+            return Err(VcvarsError::FileNotFound(
+                vcvars_path.to_string_lossy().into_owned(),
+            ));
+        }
+
+        self.vcvarsall_path = Some(vcvars_path.clone());
+        let Some(vcvars_path) = vcvars_path.to_str() else {
+            return Err(VcvarsError::NonUtf8Path(
+                vcvars_path.to_string_lossy().into_owned(),
+            ));
+        };
+
+        // Check for the Windows 8.1 SDK beforehand, since vcvars' failure output is unspecific.
+        if let Some(version) = self.sdk_version {
+            if version.major() < 10 && !self.installed_sdks()?.contains(&version) {
+                return Err(VcvarsError::SdkNotFound(version.to_string()));
+            }
+        }
+
+        // Find `cmd.exe`.
+        let mut cmd_exe_path = PathBuf::from(win_dir);
+        cmd_exe_path.push("System32");
+        cmd_exe_path.push("cmd.exe");
+
+        // Note: Usage documented here: https://learn.microsoft.com/en-us/cpp/build/building-on-the-command-line?view=msvc-170#vcvarsall-syntax.
+
+        let falls_back = self.host_arch_fallback_applies(is_legacy_vcvarsall)?;
+        let mut host_arches = self
+            .host_arch_candidates(target_arch, falls_back)?
+            .into_iter()
+            .peekable();
+
+        let mut probed_dirs = Vec::new();
+
+        while let Some(host_arch) = host_arches.next() {
+            let arch_arg = if self.ewdk_root.is_some() {
+                Some(ewdk_arch_arg(target_arch))
+            } else if is_legacy_vcvarsall {
+                legacy_arch_arg(host_arch, target_arch)
+            } else {
+                arch_arg(host_arch, target_arch)
+            };
+            let Some(arch_arg) = arch_arg else {
+                return Err(VcvarsError::UnsupportedArch(
+                    host_arch.to_string(),
+                    target_arch.to_string(),
+                ));
+            };
+
+            let arch_args = if self.vsdevcmd_options.is_some() {
+                vec![
+                    format!("-arch={}", vsdevcmd_arch_name(target_arch)),
+                    format!("-host_arch={}", vsdevcmd_arch_name(host_arch)),
+                ]
+            } else {
+                vec![arch_arg.to_owned()]
+            };
+
+            let env =
+                self.run_vcvars(&cmd_exe_path, vcvars_path, arch_args, clean_env.as_deref())?;
+
+            // Fall back to the next host architecture, if the tools are missing. Without `VCToolsInstallDir`, that can't be told.
+            let compiler_exists = match env.get(&vars::VC_TOOLS_INSTALL_DIR.to_uppercase()) {
+                Some(tools_dir) => {
+                    let bin_dir = Path::new(tools_dir)
+                        .join("bin")
+                        .join(format!("Host{}", msvc_arch_dir_name(host_arch)))
+                        .join(msvc_arch_dir_name(target_arch));
+                    let exists = bin_dir.join("cl.exe").is_file();
+                    probed_dirs.push(bin_dir.to_string_lossy().into_owned());
+                    exists
+                }
+                None => true,
+            };
+            if !compiler_exists && falls_back {
+                if host_arches.peek().is_some() {
+                    continue;
+                }
+
+                return Err(VcvarsError::HostToolsNotFound(probed_dirs));
+            }
+
+            self.arch_arg = Some(arch_arg.to_owned());
+            self.run_host_arch = Some(host_arch);
+
+            return Ok(env);
+        }
+
+        unreachable!("there should've been at least one host architecture candidate");
+    }
+
+    fn ambient_env_map(&mut self, target_arch: Arch) -> Option<EnvMap> {
+        //! Returns the env vars of the current process, if `reuse_ambient_env()` applies to them (see there), and sets the fields vcvars would've set.
+
+        if !self.reuse_ambient_env
+            || env::var_os(VCVARSALL_ENV_VAR).is_some()
+            || self.ewdk_root.is_some()
+            || self.installation_dir.is_some()
+            || self.vsdevcmd_options.is_some()
+            || self.has_installation_selection_options()
+            || self.clean_env
+            || !self.child_env.is_empty()
+            || self.extra_vcvarsall_args.is_some()
+        {
+            return None;
+        }
+
+        let env = self.parent_env_map.clone()?;
+        env.get(vars::VSCMD_VER)?;
+        let vc_install_dir = env.get(vars::VCINSTALLDIR)?;
+
+        let parse_arch = |name: &str| env.get(name)?.parse::<Arch>().ok();
+        let ambient_target_arch = parse_arch(vars::VSCMD_ARG_TGT_ARCH)?;
+        let ambient_host_arch = parse_arch(vars::VSCMD_ARG_HOST_ARCH)?;
+        let requested_target_arch = match target_arch {
+            Arch::Arm64Ec => Arch::Arm64, // vcvars doesn't distinguish it.
+            arch => arch,
+        };
+        if ambient_target_arch != requested_target_arch
+            || self
+                .host_arch
+                .is_some_and(|host_arch| host_arch != ambient_host_arch)
+            || self.validate_env_map(&env).is_err()
+        {
+            return None;
+        }
+        let arch_arg = arch_arg(ambient_host_arch, ambient_target_arch)?;
+
+        self.visual_studio_dir = env
+            .get(vars::VSINSTALLDIR)
+            .map(|dir| PathBuf::from(dir.trim_end_matches('\\')));
+        self.vcvarsall_path =
+            Some(Path::new(vc_install_dir).join(r"Auxiliary\Build\vcvarsall.bat"));
+        self.arch_arg = Some(arch_arg.to_owned());
+
+        Some(env)
+    }
+
+    fn locate_vcvars(&mut self) -> Result<(PathBuf, bool), VcvarsError> {
+        //! Returns the path of the script to run as vcvars, which may not exist, and whether it's the legacy `vcvarsall.bat` of Visual Studio 2015 and earlier. That's `SetupBuildEnv.cmd` with `from_ewdk()`, the file set with `VCVARSALL_ENV_VAR`, or else the file found with `find_vcvars()`.
+
+        match (self.ewdk_root.clone(), vcvarsall_override()?) {
+            (Some(root), _) => Ok((root.join(r"BuildEnv\SetupBuildEnv.cmd"), false)),
+            (None, Some(path)) => Ok((path, false)),
+            (None, None) => self.find_vcvars(),
+        }
+    }
+
+    fn find_vcvars(&mut self) -> Result<(PathBuf, bool), VcvarsError> {
+        //! Finds Visual Studio and returns the path of `vcvarsall.bat` or, in `VsDevCmd.bat` mode, `VsDevCmd.bat` in it, which may not exist, and whether it's the legacy `vcvarsall.bat` of Visual Studio 2015 and earlier.
+
+        let visual_studio_dir = self.ensure_visual_studio_dir()?;
+
+        let mut vcvars_path = visual_studio_dir.clone();
+        let mut is_legacy_vcvarsall = false;
+        if self.vsdevcmd_options.is_some() {
+            vcvars_path.push("Common7");
+            vcvars_path.push("Tools");
+            vcvars_path.push("VsDevCmd.bat");
+        } else {
+            vcvars_path.push("VC");
+            vcvars_path.push("Auxiliary");
+            vcvars_path.push("Build");
+            vcvars_path.push("vcvarsall.bat");
+
+            // Visual Studio 2015 and earlier, found with `-legacy` or `allow_vs2015()`.
+            let legacy_vcvars_path = visual_studio_dir.join(r"VC\vcvarsall.bat");
+            if (self.include_legacy || self.allow_vs2015)
+                && !vcvars_path.is_file()
+                && legacy_vcvars_path.is_file()
+            {
+                vcvars_path = legacy_vcvars_path;
+                is_legacy_vcvarsall = true;
+            }
+        }
+
+        // Tell a missing C++ workload apart, since installations with only other workloads are common.
+        let has_empty_msvc_dir =
+            fs::read_dir(visual_studio_dir.join(r"VC\Tools\MSVC")).is_ok_and(|mut entries| {
+                !entries.any(|entry| entry.is_ok_and(|entry| entry.path().is_dir()))
+            });
+        if self.vsdevcmd_options.is_none()
+            && visual_studio_dir.is_dir()
+            && (!vcvars_path.is_file() || has_empty_msvc_dir)
+        {
+            return Err(VcvarsError::MissingCppWorkload {
+                installation: visual_studio_dir.to_string_lossy().into_owned(),
+                has_empty_msvc_dir,
+            });
+        }
+
+        Ok((vcvars_path, is_legacy_vcvarsall))
+    }
+
+    fn resolved_target_arch(&self) -> Result<Arch, VcvarsError> {
+        //! Returns the target architecture set with `target()` or else the one from `CARGO_CFG_TARGET_ARCH`.
+
+        match (self.target_arch, env::var("CARGO_CFG_TARGET_ARCH")) {
+            (Some(arch), _) => Ok(arch),
+            (None, Ok(rust_arch)) => match Arch::from_rust_arch(&rust_arch) {
+                Some(arch) => Ok(arch),
+                None => Err(VcvarsError::UnsupportedArch(
+                    self.host_arch
+                        .map_or_else(|| env::consts::ARCH.to_owned(), |arch| arch.to_string()),
+                    rust_arch,
+                )),
+            },
+            (None, Err(_)) => Err(VcvarsError::MissingEnvVarDependency(
+                "CARGO_CFG_TARGET_ARCH".to_owned(),
+            )),
+        }
+    }
+
+    fn arch_cache_dir(&self, target_arch: Arch) -> Result<PathBuf, VcvarsError> {
+        //! Returns the cache directory for the configuration and the requested host and target architecture, like `...\vcvars-cache\x64_arm64`.
+
+        Ok(cache_dir(&self.cache_key()).join(format!(
+            "{}_{target_arch}",
+            self.host_arch(target_arch.as_str())?
+        )))
+    }
+
+    fn host_arch_fallback_applies(&self, is_legacy_vcvarsall: bool) -> Result<bool, VcvarsError> {
+        //! Returns whether other host architectures are tried if the tools of one are missing. This isn't the case for scripts whose tools directory layout isn't known, which are `SetupBuildEnv.cmd` with `from_ewdk()`, the legacy `vcvarsall.bat` of Visual Studio 2015 and earlier, the file set with `VCVARSALL_ENV_VAR`, and the `vcvarsall.bat` of the installation named by `VSINSTALLDIR` with `prefer_ambient_installation()`, which the Developer Command Prompt was set up for.
+
+        Ok(self.ewdk_root.is_none()
+            && !is_legacy_vcvarsall
+            && vcvarsall_override()?.is_none()
+            && self.ambient_installation_dir().is_none())
+    }
+
+    fn host_arch_candidates(
+        &mut self,
+        target_arch: Arch,
+        falls_back: bool,
+    ) -> Result<Vec<Arch>, VcvarsError> {
+        //! Returns the host architectures whose tools to try, in order. With an x64 or ARM64 host that wasn't explicitly set with `host()`, the x86- or x64-hosted cross tools are tried after the native tools, since minimal installations may lack the latter, or before them, depending on the `ToolchainPreference`. The host architecture that worked in a previous build script run is tried first. Without `falls_back`, only the native host architecture is returned.
+
+        if self.host_arch.is_some() || !falls_back {
+            return Ok(vec![self.host_arch(target_arch.as_str())?]);
+        }
+
+        let mut host_arches = match self.toolchain_preference {
+            ToolchainPreference::NativeHostOnly => vec![self.host_arch(target_arch.as_str())?],
+            ToolchainPreference::PreferNative => {
+                match self.effective_host_arch(target_arch.as_str())? {
+                    Arch::X64 => vec![Arch::X64, Arch::X86],
+                    host_arch => vec![host_arch],
+                }
+            }
+            ToolchainPreference::PreferCross => match self.host_arch(target_arch.as_str())? {
+                Arch::X64 => vec![Arch::X86, Arch::X64],
+                Arch::Arm64 => vec![Arch::X64, Arch::Arm64],
+                host_arch => vec![host_arch],
+            },
+        };
+
+        if env::var_os("OUT_DIR").is_some() {
+            let cache_file = self.arch_cache_dir(target_arch)?.join("=host_arch.txt");
+            if let Some(cached_arch) =
+                read_cache_file(&cache_file).and_then(|arch| arch.parse().ok())
+            {
+                if let Some(index) = host_arches.iter().position(|&arch| arch == cached_arch) {
+                    host_arches[..=index].rotate_right(1);
+                }
+            }
+        }
+
+        Ok(host_arches)
+    }
+
+    fn run_vcvars(
+        &mut self,
+        cmd_exe_path: &Path,
+        vcvars_path: &str,
+        arch_args: Vec<String>,
+        clean_env: Option<&[(String, OsString)]>,
+    ) -> Result<EnvMap, VcvarsError> {
+        //! Runs vcvars with the architecture arguments and the configured arguments and parses the env vars.
+
+        // Run `cmd.exe` with vcvars.
+        let vcvars_path = escape_cmd_arg(vcvars_path);
+
+        // Note: Escaping `%` by writing `%%` doesn't work, and a path containing two `%`s and the name of an existing env var in between breaks the command.
+
+        let separator_line =
+            "=".repeat(20) + "_unique_separator_by_rust_crate_that_utilizes_vcvars";
+
+        let cmd_args = [
+            "/C".to_owned(),
+            // Note: On the regular, interactive command line, `chcp 65001` to change the active code page to UTF-8 doesn't seem to make a difference regarding the content.
+            vcvars_path,
+        ]
+        .into_iter()
+        .chain(arch_args)
+        .chain(self.config_args().iter().map(|arg| escape_cmd_arg(arg)))
+        .chain([
+            "&&".to_owned(),
+            format!("echo.{separator_line}"),
+            "&&".to_owned(),
+            "set".to_owned(), // Lists env vars.
+        ])
+        .collect_vec();
+
+        self.command_line = Some(
+            [cmd_exe_path.to_string_lossy().as_ref()]
+                .into_iter()
+                .chain(cmd_args.iter().map(String::as_str))
+                .map(|arg| {
+                    if arg.contains(' ') {
+                        format!("\"{arg}\"")
+                    } else {
+                        arg.to_owned()
+                    }
+                })
+                .join(" "),
+        );
+
+        let mut command = Command::new(cmd_exe_path);
+        command.args(&cmd_args);
+        if let Some(clean_env) = clean_env {
+            command
+                .env_clear()
+                .envs(clean_env.iter().map(|(key, value)| (key, value)));
+        }
+        if !self.telemetry {
+            // Note: Otherwise, vcvars starts a PowerShell process to send telemetry data, which takes a considerable amount of time.
+            command.env("VSCMD_SKIP_SENDTELEMETRY", "1");
+        }
+        let output = command
+            .envs(self.child_env.iter().map(|(key, value)| (key, value)))
+            .output();
+
+        // Note: vcvars always returns exit code 0, even if it failed (as of Dec. 2022).
+
+        let stdout = match output {
+            Ok(ref output) => String::from_utf8_lossy(&output.stdout),
+            Err(err) => {
+                return Err(VcvarsError::CouldntRun(
+                    cmd_exe_path.to_string_lossy().into_owned(),
+                    err,
+                ));
+            }
+        };
+
+        if stdout.starts_with("[ERROR:") {
+            self.raw_output = Some(stdout.clone().into_owned());
+
+            return Err(self.vcvars_failure(&stdout));
+        }
+
+        if self.keep_raw_output {
+            self.raw_output = Some(stdout.clone().into_owned());
+        }
+
+        // Transform output lines to key-value pairs.
+        let mut env = HashMap::new();
+        let mut may_collect = false;
+
+        // Note: The format in stdout that we get is basically identical to that of the Windows API function `GetEnvironmentStrings()`, which is only for the current process.
+
+        for line in stdout.lines() {
+            if may_collect {
+                if let Some((key, value)) = line.split_once('=') {
+                    env.insert(key.to_uppercase(), value.to_owned());
+                }
+            } else if line.starts_with(&separator_line) {
+                // Note: The notoriously erratic `cmd.exe` adds a space. Hence not `==`.
+
+                may_collect = true;
+            }
+        }
+
+        self.validate_env_map(&env)?;
+
+        Ok(env)
+    }
+
+    fn validate_env_map(&self, env: &EnvMap) -> Result<(), VcvarsError> {
+        //! Checks that the options took effect, since vcvars may silently ignore them.
+
+        if let Some(requested_version) = self.sdk_version {
+            let sdk_applied = if requested_version.major() < 10 {
+                // The Windows 8.1 SDK has its own kit directory, but no version subdirectories.
+                env.get(&vars::WINDOWS_SDK_DIR.to_uppercase())
+                    .is_some_and(|dir| {
+                        comparable_path(Path::new(dir)).ends_with(&format!(r"\{requested_version}"))
+                    })
+            } else {
+                env.get(&vars::WINDOWS_SDK_VERSION.to_uppercase())
+                    .and_then(|version| version.parse().ok())
+                    == Some(requested_version)
+            };
+
+            if !sdk_applied {
+                return Err(VcvarsError::SdkNotFound(requested_version.to_string()));
+            }
+        }
+
+        if let Some(requested_platform) = self.app_platform {
+            let platform = env
+                .get(&vars::VSCMD_ARG_APP_PLAT.to_uppercase())
+                .map_or("", String::as_str);
+
+            if platform.parse().ok() != Some(requested_platform) {
+                return Err(VcvarsError::AppPlatformNotApplied(
+                    requested_platform,
+                    platform.trim().to_owned(),
+                ));
+            }
+
+            if requested_platform == AppPlatform::OneCore
+                && !env
+                    .get(vars::LIB)
+                    .is_some_and(|lib| lib.to_lowercase().contains(r"\lib\onecore\"))
+            {
+                return Err(VcvarsError::OneCoreLibsNotFound);
+            }
+        }
+
+        if let Some(requested_version) = self.toolset_version {
+            let version = env
+                .get(&vars::VC_TOOLS_VERSION.to_uppercase())
+                .map_or("", String::as_str);
+            if !version_matches(version.trim(), requested_version) {
+                return Err(VcvarsError::ToolsetNotFound(
+                    requested_version.to_owned(),
+                    version.trim().to_owned(),
+                ));
+            }
+        }
+
+        if self.spectre_libs
+            && !env
+                .get(vars::LIB)
+                .is_some_and(|lib| lib.to_lowercase().contains(r"\spectre\"))
+        {
+            return Err(VcvarsError::SpectreLibsNotInstalled);
+        }
+
+        Ok(())
+    }
+
+    fn vcvars_failure(&self, stdout: &str) -> VcvarsError {
+        //! Makes an error from the output of failed vcvars. Errors regarding configured options get dedicated variants.
+
+        // Example: `[ERROR:winsdk.bat] Windows SDK 10.0.17134.0 : 'C:\Program Files (x86)\Windows Kits\10\include\10.0.17134.0\um'`
+        if let Some(version) = self.sdk_version {
+            if stdout
+                .lines()
+                .any(|line| line.starts_with("[ERROR:winsdk.bat]"))
+            {
+                return VcvarsError::SdkNotFound(version.to_string());
+            }
+        }
+
+        // Example: `[ERROR:ext\vcvars.bat] Toolset directory for version '14.2' was not found.`
+        if let Some(version) = self.toolset_version {
+            if stdout.contains("Toolset directory for version") {
+                return VcvarsError::ToolsetNotFound(version.to_owned(), String::new());
+            }
+        }
+
+        VcvarsError::VcvarsFailed(Itertools::intersperse(stdout.lines(), r"\n").collect())
+    }
+}
+
+impl fmt::Debug for Vcvars<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        //! Shows the configuration relevant for selecting Visual Studio and the results resolved so far, like the selected installation. The variables are omitted for brevity.
+
+        f.debug_struct("Vcvars")
+            .field("target_arch", &self.target_arch)
+            .field("host_arch", &self.host_arch)
+            .field("vsdevcmd_options", &self.vsdevcmd_options)
+            .field("selection_strategy", &self.selection_strategy)
+            .field("installation", &self.installation)
+            .field("visual_studio_dir", &self.visual_studio_dir)
+            .field("vcvarsall_path", &self.vcvarsall_path)
+            .field("arch_arg", &self.arch_arg)
+            .field("has_run", &self.env_map.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Where the value of a variable in the vcvars environment comes from. See `Vcvars::origin()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VarOrigin {
+    /// The variable wasn't set before vcvars was run.
+    Added,
+    /// vcvars changed the variable's value.
+    Modified { previous: String },
+    /// vcvars didn't change the variable's value.
+    Unchanged,
+}
+
+/// Options for running `VsDevCmd.bat` instead of `vcvarsall.bat`. See `Vcvars::use_vsdevcmd()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VsDevCmdOptions {
+    /// Whether to pass `-no_logo`, which suppresses the banner. Defaults to `true`.
+    pub no_logo: bool,
+    /// Whether to pass `-startdir=none`, which prevents changing the working directory. Defaults to `true`.
+    pub keep_start_dir: bool,
+}
+
+impl Default for VsDevCmdOptions {
+    fn default() -> Self {
+        Self {
+            no_logo: true,
+            keep_start_dir: true,
+        }
+    }
+}
+
+/// The ATL/MFC directories for the target architecture vcvars was run for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AtlMfcDirs {
+    pub include: PathBuf,
+    pub lib: PathBuf,
+}
+
+/// What vcvars was run for, according to the `VSCMD_*` variables. See `Vcvars::vscmd()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct VsCmdArgs {
+    /// From `VSCMD_ARG_HOST_ARCH`.
+    pub host_arch: Option<Arch>,
+    /// From `VSCMD_ARG_TGT_ARCH`.
+    pub target_arch: Option<Arch>,
+    /// From `VSCMD_ARG_app_plat`.
+    pub app_platform: Option<AppPlatform>,
+    /// From `VSCMD_VER`.
+    pub version: Option<VsCmdVersion>,
+}
+
+/// Whether to use the tools hosted on the architecture of the current process or cross tools hosted on a different architecture the current process can also run on, as set with `Vcvars::toolchain_preference()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ToolchainPreference {
+    /// Only uses the native tools. If they aren't installed, running vcvars fails with `VcvarsError::HostToolsNotFound`.
+    NativeHostOnly,
+    /// Uses the native tools, falling back to the x86-hosted tools on an x64 host if they aren't installed. On an ARM64 host, the x64-hosted tools are used if the toolset lacks native ones. If neither are installed, running vcvars fails with `VcvarsError::HostToolsNotFound`.
+    #[default]
+    PreferNative,
+    /// Uses the x86-hosted tools on an x64 host, which have a lower memory footprint, and the x64-hosted tools on an ARM64 host, falling back to the native tools if they aren't installed. If neither are installed, running vcvars fails with `VcvarsError::HostToolsNotFound`.
+    PreferCross,
+}
+
+/// How to select the Visual Studio installation, as set with `Vcvars::selection_strategy()`. The strategies other than the default one are applied to the complete installations accepted by `Vcvars::select_installation()`, sorted by descending version.
+#[derive(Clone, Default)]
+pub enum SelectionStrategy {
+    /// Selects the installation with the highest version, like `vswhere.exe` with `-latest`.
+    #[default]
+    NewestInstallation,
+    /// Selects the installation with the highest version that isn't a prerelease, like Visual Studio Preview. If there is none, selecting fails with `VcvarsError::NoInstallationSelected`.
+    NewestStable,
+    /// Selects the installation containing the MSVC toolset with the highest version in `VC\Tools\MSVC`, which can be an older Visual Studio version with a newer servicing toolset. If versions are equal, the installation with the higher version is selected. If no installation contains a toolset, falls back to `NewestInstallation`.
+    NewestToolset,
+    /// Selects the installation at the index returned by the function, which receives the candidate installations. If it returns `None` or an index out of bounds, selecting fails with `VcvarsError::NoInstallationSelected`.
+    ///
+    /// ```ignore
+    /// let strategy = SelectionStrategy::Custom(Arc::new(|installations| {
+    ///     let wanted_id = std::env::var("VS_INSTANCE_ID").ok()?;
+    ///     installations.iter().position(|installation| installation.instance_id == wanted_id)
+    /// }));
+    /// ```
+    #[allow(clippy::type_complexity)]
+    Custom(Arc<dyn Fn(&[InstallationInfo]) -> Option<usize> + Send + Sync>),
+}
+
+impl fmt::Debug for SelectionStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NewestInstallation => f.write_str("NewestInstallation"),
+            Self::NewestStable => f.write_str("NewestStable"),
+            Self::NewestToolset => f.write_str("NewestToolset"),
+            Self::Custom(_) => f.write_str("Custom(..)"),
+        }
+    }
+}
+
+/// Which CRT redistributable files to return from `Vcvars::crt_redist_dlls()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CrtRedistKind {
+    Release,
+    /// The debug CRT, which may not be redistributed.
+    Debug,
+}
+
+/// Paths of the DIA SDK for the target architecture vcvars was run for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DiaSdk {
+    pub root: PathBuf,
+    pub include: PathBuf,
+    pub lib: PathBuf,
+    /// The path of `msdia140.dll`.
+    pub msdia_dll: PathBuf,
+}
+
+/// Address sanitizer (ASAN) runtime files for the host and target architecture vcvars was run for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AsanRuntime {
+    /// The directory containing the `clang_rt.asan*.lib` files.
+    pub lib_dir: PathBuf,
+    /// The sorted paths of the `clang_rt.asan*.lib` files.
+    pub libs: Vec<PathBuf>,
+    /// The directory containing the `clang_rt.asan*.dll` files.
+    pub dll_dir: PathBuf,
+    /// The sorted paths of the `clang_rt.asan*.dll` files, which must be locatable by executables at runtime.
+    pub dlls: Vec<PathBuf>,
+}
+
+/// Paths of MSVC tools for the host and target architecture vcvars was run for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MsvcTools {
+    pub cl: PathBuf,
+    pub link: PathBuf,
+    pub lib: PathBuf,
+    pub nmake: PathBuf,
+    /// The MSVC toolset version, like `14.34.31933`.
+    pub version: String,
+}
+
+/// Information about the Windows SDK vcvars selected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WindowsSdkInfo {
+    /// The SDK's root directory, like `C:\Program Files (x86)\Windows Kits\10\`.
+    pub root: PathBuf,
+    /// The SDK version, like `10.0.22621.0` (without trailing backslash).
+    pub version: String,
+    /// The directory containing the host architecture subdirectories with tools like `rc.exe`.
+    pub bin: PathBuf,
+    /// The existing ones of the `ucrt`, `shared`, `um`, `winrt` and `cppwinrt` include directories.
+    pub include_dirs: Vec<PathBuf>,
+    /// The existing ones of the `ucrt` and `um` lib directories for the target architecture.
+    pub lib_dirs: Vec<PathBuf>,
+}
+
+/// Information about the Universal CRT vcvars selected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UcrtInfo {
+    /// The directory of the SDK containing the Universal CRT, like `C:\Program Files (x86)\Windows Kits\10\`.
+    pub sdk_dir: PathBuf,
+    /// The Universal CRT version, like `10.0.22621.0` (without trailing backslash).
+    pub version: String,
+    /// The `ucrt` include directory.
+    pub include_dir: PathBuf,
+    /// The `ucrt` lib directory for the target architecture.
+    pub lib_dir: PathBuf,
+}
+
+fn cache_dir(cache_key: &str) -> PathBuf {
+    //! Returns the cache directory path in Cargo's output directory. Non-empty cache keys get their own subdirectory.
+
+    let cargo_out_dir =
+        PathBuf::from(&env::var("OUT_DIR").expect("env var `OUT_DIR` should've been set by Cargo"));
+    assert!(
+        cargo_out_dir.is_dir(),
+        "env var `OUT_DIR` should be a valid directory path"
+    );
+
+    let mut cache_dir = cargo_out_dir;
+    cache_dir.push("vcvars-cache");
+    if !cache_key.is_empty() {
+        cache_dir.push(cache_key);
+    }
+
+    cache_dir
+}
+
+fn fnv1a_hash(bytes: &[u8]) -> u64 {
+    //! Returns the 64-bit FNV-1a hash of `bytes`, which, unlike `std`'s hashers, is guaranteed to be stable across Rust versions.
+
+    bytes.iter().fold(0xCBF2_9CE4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01B3)
+    })
+}
+
+fn format_env_lines(env_map: &EnvMap) -> String {
+    //! Formats the env vars for the cache file, as sorted `KEY=value` lines, like `cmd.exe`'s `set` prints them. Values can't contain line breaks, since they're parsed from such lines.
+
+    env_map
+        .iter()
+        .sorted()
+        .map(|(key, value)| format!("{key}={value}"))
+        .join("\n")
+}
+
+fn parse_env_lines(content: &str) -> EnvMap {
+    //! Parses the `KEY=value` lines of the cache file written with `format_env_lines()`.
+
+    content
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (key.to_owned(), value.to_owned()))
+        .collect()
+}
+
+fn file_stamp(path: &Path) -> String {
+    //! Returns the size and modification time of the file for the cache manifest file, or `missing` if its metadata can't be read.
+
+    let Ok(metadata) = fs::metadata(path) else {
+        return "missing".to_owned();
+    };
+    let modified_nanos = metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |duration| duration.as_nanos());
+
+    format!("size={} modified={modified_nanos}", metadata.len())
+}
+
+fn is_manifest_current(manifest: &str, config_key: &str) -> bool {
+    //! Returns whether the content of a cache manifest file was written for `config_key` and whether the files it records a `file_stamp()` of are unchanged. Only file metadata is read, so this is cheap enough to be checked whenever cached values are served.
+
+    let mut lines = manifest.lines();
+
+    lines.next() == Some(config_key)
+        && lines
+            .tuples()
+            .all(|(path, stamp)| file_stamp(Path::new(path)) == stamp)
+}
+
+fn read_cache_file(path: &Path) -> Option<String> {
+    //! Returns the content of the cache file, or `None` if it's missing, unreadable or empty, in which case it's to be (re)created.
+
+    fs::read_to_string(path)
+        .ok()
+        .filter(|content| !content.is_empty())
+}
+
+fn write_cache_file(path: &Path, content: &str) -> io::Result<()> {
+    //! Writes the cache file atomically, by writing a temporary file in the same directory and renaming it to `path`, replacing an existing file. This way, a build script that is killed, or that races with another one, can't leave a truncated file behind that would be read later. The temporary file name is unique per process and call, and doesn't end in `.txt`, so `remove_cache_files()` doesn't interfere.
+
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let mut temp_file_name = path.file_name().unwrap().to_owned();
+    temp_file_name.push(format!(
+        ".{}-{}.tmp",
+        process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let temp_file = path.with_file_name(temp_file_name);
+
+    let result = fs::write(&temp_file, content).and_then(|()| fs::rename(&temp_file, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_file);
+    }
+
+    result
+}
+
+/// An exclusive lock on a cache directory, held while creating cache files, so parallel build scripts don't all run vcvars. Implemented by keeping the file `=lock` in the directory open without sharing it, which Windows releases when the handle is closed, also when the process is killed. So, a lock can't become stale.
+struct CacheLock {
+    _file: fs::File,
+}
+
+impl CacheLock {
+    fn acquire(cache_dir: &Path, timeout: Duration) -> Option<Self> {
+        //! Waits until the lock is acquired. Returns `None` if that didn't happen within `timeout`, or if the lock file can't be opened for another reason, in which case the cache files are to be created anyway, since they're written atomically.
+
+        const ERROR_ACCESS_DENIED: i32 = 5;
+        const ERROR_SHARING_VIOLATION: i32 = 32;
+
+        let lock_file = cache_dir.join("=lock");
+        let start = Instant::now();
+
+        loop {
+            match fs::OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .share_mode(0)
+                .open(&lock_file)
+            {
+                Ok(file) => return Some(Self { _file: file }),
+                Err(err)
+                    if matches!(
+                        err.raw_os_error(),
+                        Some(ERROR_ACCESS_DENIED | ERROR_SHARING_VIOLATION)
+                    ) && start.elapsed() < timeout =>
+                {
+                    thread::sleep(Duration::from_millis(50));
+                }
+                Err(_) => return None,
+            }
+        }
+    }
+}
+
+fn remove_cache_files(dir: &Path) {
+    //! Removes the cache files directly in `dir`, like `=env.txt` or the per-variable files of earlier versions of this crate, like `INCLUDE.txt`, since they're stale. Subdirectories are kept. Failures are ignored, since the files are then just unused.
+
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+
+    for path in entries.filter_map(|entry| Some(entry.ok()?.path())) {
+        if path.is_file() && path.extension().is_some_and(|extension| extension == "txt") {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+fn arch_arg(host_arch: Arch, target_arch: Arch) -> Option<&'static str> {
+    //! Returns the architecture argument to `vcvarsall.bat` for the host and target architecture, if supported.
+
+    match host_arch {
+        Arch::X86 => match target_arch {
+            Arch::X86 => Some("x86"),
+            Arch::X64 => Some("x86_x64"),
+            Arch::Arm => Some("x86_arm"),
+            Arch::Arm64 | Arch::Arm64Ec => Some("x86_arm64"),
+        },
+        Arch::X64 => match target_arch {
+            Arch::X86 => Some("x64_x86"), // Or `Some("x86")`? Usage table not clear.
+            Arch::X64 => Some("x64"),     // Or `Some("x86_x64")`? Usage table not clear.
+            Arch::Arm => Some("x64_arm"), // Or `Some("x86_arm")`? Usage table not clear.
+            Arch::Arm64 | Arch::Arm64Ec => Some("x64_arm64"), // Or `Some("x86_arm64")`? Usage table not clear.
+        },
+        Arch::Arm64 => match target_arch {
+            Arch::X86 => Some("arm64_x86"),
+            Arch::X64 => Some("arm64_amd64"),
+            Arch::Arm => Some("arm64_arm"),
+            Arch::Arm64 | Arch::Arm64Ec => Some("arm64"),
+        },
+        Arch::Arm | Arch::Arm64Ec => None,
+    }
+}
+
+fn legacy_arch_arg(host_arch: Arch, target_arch: Arch) -> Option<&'static str> {
+    //! Returns the architecture argument to the `vcvarsall.bat` of Visual Studio 2015 and earlier for the host and target architecture, if supported. These versions name x64 `amd64` and have no ARM64 tools.
+
+    match host_arch {
+        Arch::X86 => match target_arch {
+            Arch::X86 => Some("x86"),
+            Arch::X64 => Some("x86_amd64"),
+            Arch::Arm => Some("arm"), // The x86-hosted cross tools.
+            Arch::Arm64 | Arch::Arm64Ec => None,
+        },
+        Arch::X64 => match target_arch {
+            Arch::X86 => Some("amd64_x86"),
+            Arch::X64 => Some("amd64"),
+            Arch::Arm => Some("amd64_arm"),
+            Arch::Arm64 | Arch::Arm64Ec => None,
+        },
+        Arch::Arm | Arch::Arm64 | Arch::Arm64Ec => None,
+    }
+}
+
+fn vcvarsall_override() -> Result<Option<PathBuf>, VcvarsError> {
+    //! Returns the path set with the environment variable `VCVARSALL_ENV_VAR`, if any, after checking that it's an existing `.bat` or `.cmd` file.
+
+    let Some(path) = env::var_os(VCVARSALL_ENV_VAR).map(PathBuf::from) else {
+        return Ok(None);
+    };
+
+    let invalid = |problem: &str| {
+        Err(VcvarsError::InvalidVcvarsallOverride(
+            path.to_string_lossy().into_owned(),
+            problem.to_owned(),
+        ))
+    };
+
+    if !path.extension().is_some_and(|extension| {
+        extension.eq_ignore_ascii_case("bat") || extension.eq_ignore_ascii_case("cmd")
+    }) {
+        return invalid("doesn't have the extension `.bat` or `.cmd`");
+    }
+
+    if !path.is_file() {
+        return invalid("doesn't exist");
+    }
+
+    if path.to_str().is_none() {
+        return invalid("isn't valid UTF-8");
+    }
+
+    Ok(Some(path))
+}
+
+fn ewdk_arch_arg(target_arch: Arch) -> &'static str {
+    //! Returns the architecture argument to the Enterprise WDK's `SetupBuildEnv.cmd` for the target architecture. It names x64 `amd64` and selects no host architecture.
+
+    match target_arch {
+        Arch::X86 => "x86",
+        Arch::X64 => "amd64",
+        Arch::Arm => "arm",
+        Arch::Arm64 | Arch::Arm64Ec => "arm64",
+    }
+}
+
+fn vs2015_dir() -> Option<PathBuf> {
+    //! Returns the directory of a Visual Studio 2015 installation containing `VC\vcvarsall.bat`, derived from `VS140COMNTOOLS`, which names its `Common7\Tools` directory, or else the default directory.
+
+    let from_env_var = env::var_os("VS140COMNTOOLS")
+        .and_then(|tools_dir| Some(Path::new(&tools_dir).parent()?.parent()?.to_owned()));
+    let default_dirs = vswhere::program_files_x86_dirs()
+        .into_iter()
+        .map(|program_files_x86_dir| program_files_x86_dir.join("Microsoft Visual Studio 14.0"));
+
+    from_env_var
+        .into_iter()
+        .chain(default_dirs)
+        .find(|dir| dir.join(r"VC\vcvarsall.bat").is_file())
+}
+
+fn clean_env(win_dir: &str) -> Vec<(String, OsString)> {
+    //! Returns the env vars of the current process that vcvars needs, plus a `PATH` only consisting of `System32`.
+
+    const ALLOWED_VAR_NAMES: [&str; 12] = [
+        "SYSTEMROOT",
+        "SYSTEMDRIVE",
+        "WINDIR",
+        "TEMP",
+        "TMP",
+        "PROGRAMFILES",
+        "PROGRAMFILES(X86)",
+        "PROGRAMW6432",
+        "COMMONPROGRAMFILES",
+        "COMMONPROGRAMFILES(X86)",
+        "PROCESSOR_ARCHITECTURE",
+        "PROCESSOR_ARCHITEW6432",
+    ];
+
+    env::vars_os()
+        .filter_map(|(key, value)| {
+            let key = key.to_str()?;
+            ALLOWED_VAR_NAMES
+                .contains(&key.to_uppercase().as_str())
+                .then(|| (key.to_owned(), value))
+        })
+        .chain([(
+            "Path".to_owned(),
+            Path::new(win_dir).join("System32").into_os_string(),
+        )])
+        .collect()
+}
+
+fn msvc_arch_dir_name(arch: Arch) -> &'static str {
+    //! Returns the architecture name as used in the directory names of the MSVC toolset, like `bin\Hostx64\arm64`.
+
+    match arch {
+        Arch::Arm64Ec => "arm64",
+        _ => arch.as_str(),
+    }
+}
+
+fn vsdevcmd_arch_name(arch: Arch) -> &'static str {
+    //! Returns the architecture name as used by `VsDevCmd.bat`'s arguments `-arch` and `-host_arch`.
+
+    match arch {
+        Arch::X64 => "amd64",
+        Arch::Arm64 | Arch::Arm64Ec => "arm64",
+        _ => arch.as_str(),
+    }
+}
+
+fn version_matches(version: &str, requested_version: &str) -> bool {
+    //! Returns whether the dot-separated `version` starts with the components of `requested_version`, like `14.29.30133` with `14.29`.
+
+    let mut version_parts = version.split('.');
+
+    requested_version
+        .split('.')
+        .all(|requested_part| version_parts.next() == Some(requested_part))
+}
+
+fn toolsets_in(visual_studio_dir: &Path) -> Option<Vec<VcToolsVersion>> {
+    //! Returns the sorted versions of the MSVC toolsets in `VC\Tools\MSVC` of the installation directory, as described with `Vcvars::installed_toolsets()`. Returns `None` if the directory can't be read.
+
+    let entries = fs::read_dir(visual_studio_dir.join(r"VC\Tools\MSVC")).ok()?;
+
+    Some(
+        entries
+            .filter_map(Result::ok)
+            .filter(|entry| entry.path().is_dir())
+            .filter_map(|entry| entry.file_name().to_str()?.parse().ok())
+            .sorted_unstable()
+            .collect(),
+    )
+}
+
+fn is_version_range(range: &str) -> bool {
+    //! Returns whether `range` is a version range as accepted by `vswhere.exe`, like `17.0` or `[16.0,18.0)`, where the lower bound mustn't be greater than the upper bound.
+
+    let is_version = |version: &str| {
+        version.split('.').count() <= 4
+            && version
+                .split('.')
+                .all(|part| !part.is_empty() && part.bytes().all(|byte| byte.is_ascii_digit()))
+    };
+    let parse_version = |version: &str| -> Vec<u64> {
+        version
+            .split('.')
+            .map(|part| part.parse().unwrap_or(u64::MAX))
+            .collect()
+    };
+
+    let range = range.trim();
+    let Some(inner) = range
+        .strip_prefix(['[', '('])
+        .and_then(|inner| inner.strip_suffix([']', ')']))
+    else {
+        return is_version(range);
+    };
+
+    match inner.split_once(',') {
+        Some((lower, upper)) => {
+            let (lower, upper) = (lower.trim(), upper.trim());
+
+            (lower.is_empty() || is_version(lower))
+                && (upper.is_empty() || is_version(upper))
+                && !(lower.is_empty() && upper.is_empty())
+                && (lower.is_empty()
+                    || upper.is_empty()
+                    || parse_version(lower) <= parse_version(upper))
+        }
+        // An exact version.
+        None => range.starts_with('[') && range.ends_with(']') && is_version(inner.trim()),
+    }
+}
+
+fn escape_cmd_arg(arg: &str) -> String {
+    arg.replace('^', "^^").replace('&', "^&") // Try to follow `cmd.exe`'s erratic escaping rules (tested).
+}
+
+fn kits_root(reg_exe_path: &Path, value_name: &str, fallback_dir_name: &str) -> Option<PathBuf> {
+    //! Reads a Windows Kits root directory from the registry. If the value is missing, falls back to the default location in the x86 program files directory. Returns `None` if the directory doesn't exist.
+
+    let registry_dir = Command::new(reg_exe_path)
+        .args([
+            "query",
+            r"HKLM\SOFTWARE\Microsoft\Windows Kits\Installed Roots",
+            "/v",
+            value_name,
+            "/reg:32",
+        ])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| {
+            // Output line format: `    KitsRoot10    REG_SZ    C:\Program Files (x86)\Windows Kits\10\`
+            String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .find_map(|line| Some(PathBuf::from(line.split_once("REG_SZ")?.1.trim())))
+        });
+
+    match registry_dir {
+        Some(dir) => dir.is_dir().then_some(dir),
+        None => vswhere::program_files_x86_dirs()
+            .into_iter()
+            .map(|program_files_x86_dir| {
+                program_files_x86_dir
+                    .join("Windows Kits")
+                    .join(fallback_dir_name)
+            })
+            .find(|dir| dir.is_dir()),
+    }
+}
+
+fn files_with_prefix(dir: &Path, prefix: &str, extension: &str) -> Vec<PathBuf> {
+    //! Returns the sorted paths of the files in `dir` whose names start with `prefix` and that have `extension`, all case-insensitively. Returns an empty vector if `dir` can't be read.
+
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    let prefix = prefix.to_lowercase();
+
+    entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.is_file()
+                && path
+                    .file_name()
+                    .is_some_and(|name| name.to_string_lossy().to_lowercase().starts_with(&prefix))
+                && path
+                    .extension()
+                    .is_some_and(|path_extension| path_extension.eq_ignore_ascii_case(extension))
+        })
+        .sorted()
+        .collect()
+}
+
+fn split_path_list(value: &str) -> Vec<PathBuf> {
+    env::split_paths(value)
+        .filter(|path| !path.as_os_str().is_empty())
+        .unique()
+        .collect()
+}
+
+fn comparable_path(path: &Path) -> String {
+    path.to_string_lossy().trim_end_matches('\\').to_lowercase()
+}
+
+#[derive(Error, Debug)]
+pub enum VcvarsError {
+    #[error("env var `{0}` isn't set, which is a dependency to run vcvars")]
+    MissingEnvVarDependency(String),
+    #[error("the Cargo target environment is `{0}`, not `msvc`, so the vcvars environment is irrelevant; use `Vcvars::needed()` to skip running vcvars")]
+    NonMsvcTarget(String),
+    #[error("couldn't find file `{0}`")]
+    FileNotFound(String),
+    #[error("the path `{0}` isn't valid UTF-8, which is required to run it with `cmd.exe`")]
+    NonUtf8Path(String),
+    #[error("unsupported host architecture `{0}` for target architecture `{1}`")]
+    UnsupportedArch(String, String),
+    #[error("couldn't run `{0}`: {1}")]
+    CouldntRun(String, io::Error),
+    #[error("`vcvarsall.bat` failed: {0}")]
+    VcvarsFailed(String),
+    #[error("the path `{0}` set with the env var `VCVARS_RS_VCVARSALL` {1}")]
+    InvalidVcvarsallOverride(String, String),
+    #[error("vcvars wasn't run, since the environment of the current process was reused; disable this with `Vcvars::reuse_ambient_env(false)`")]
+    AmbientEnvReused,
+    #[error("vcvars couldn't find Windows SDK `{0}`; install it with the Visual Studio Installer")]
+    SdkNotFound(String),
+    #[error("vcvars couldn't find MSVC toolset `{0}`{}; install it with the Visual Studio Installer", if .1.is_empty() { String::new() } else { format!(" and selected `{}` instead", .1) })]
+    ToolsetNotFound(String, String),
+    #[error("Spectre-mitigated libraries aren't installed; install the Visual Studio component \"MSVC Spectre-mitigated libs\" matching the toolset and architecture")]
+    SpectreLibsNotInstalled,
+    #[error("vcvars didn't apply app platform `{0}`, but `{1}`")]
+    AppPlatformNotApplied(AppPlatform, String),
+    #[error("`LIB` doesn't contain the MSVC OneCore libraries")]
+    OneCoreLibsNotFound,
+    #[error("I/O operation regarding cache path `{0}` failed: {1}")]
+    CacheFailed(String, io::Error),
+    #[error("variable `{0}` not found in vcvars environment")]
+    VarNotFound(String),
+    #[error("variables {} not found in vcvars environment", .0.iter().map(|name| format!("`{name}`")).join(", "))]
+    VarsNotFound(Vec<String>),
+    #[error("Visual Studio installation `{installation}` lacks the C++ build tools{}; install the \"Desktop development with C++\" workload or the component `Microsoft.VisualStudio.Component.VC.Tools.x86.x64` with the Visual Studio Installer", if *.has_empty_msvc_dir { ", since `VC\\Tools\\MSVC` contains no toolset, probably after an incomplete installation or uninstallation" } else { ", since `vcvarsall.bat` is missing" })]
+    MissingCppWorkload {
+        installation: String,
+        has_empty_msvc_dir: bool,
+    },
+    #[error("couldn't find `{0}`; the MSVC build tools for this host and target architecture are probably not installed")]
+    MsvcToolNotFound(String),
+    #[error("couldn't find the MSVC compiler for any candidate host architecture; probed {}", .0.iter().map(|dir| format!("`{dir}`")).join(", "))]
+    HostToolsNotFound(Vec<String>),
+    #[error("couldn't find `{0}`; the Windows SDK is probably not installed")]
+    WindowsSdkToolNotFound(String),
+    #[error("couldn't find `{0}`; install a Windows SDK with {1}")]
+    WindowsSdkFeatureNotFound(String, String),
+    #[error("couldn't find `vswhere.exe`; probed {}", .0.iter().map(|path| format!("`{path}`")).join(", "))]
+    VswhereNotFound(Vec<String>),
+    #[error("couldn't find Visual Studio, neither with `vswhere.exe` nor in the registry; probed {}", .0.iter().map(|location| format!("`{location}`")).join(", "))]
+    VisualStudioNotFound(Vec<String>),
+    #[error("`vswhere.exe` failed with {} for the arguments `{args}`: {}", .exit_code.map_or_else(|| "no exit code".to_owned(), |code| format!("exit code {code}")), .stderr.lines().take(5).join("\n"))]
+    VswhereFailed {
+        exit_code: Option<i32>,
+        stderr: String,
+        args: String,
+    },
+    #[error("`vswhere.exe` version `{found}` is too old, since version `{required}` or newer is required; update the Visual Studio Installer or the standalone copy")]
+    VswhereTooOld { found: String, required: String },
+    #[error("`vswhere.exe` found nothing for `{0}`")]
+    VswhereFoundNothing(String),
+    #[error("`vswhere.exe` found no Visual Studio installation with the required components {}; install them with the Visual Studio Installer", .0.iter().map(|id| format!("`{id}`")).join(", "))]
+    RequiredComponentsNotFound(Vec<String>),
+    #[error("no Visual Studio installation was selected; rejected {}", if .0.is_empty() { "no complete installations were found".to_owned() } else { .0.iter().map(|candidate| format!("`{candidate}`")).join(", ") })]
+    NoInstallationSelected(Vec<String>),
+    #[error("`vswhere.exe` found no Visual Studio installation with the arguments `{0}`{}; install Visual Studio or the Build Tools for Visual Studio, or check the selection, like with `Vcvars::products()` and `Vcvars::include_prereleases()`", if .1.is_empty() { String::new() } else { format!(" (stderr: {})", .1) })]
+    NoVisualStudioFound(String, String),
+    #[error("`vswhere.exe` returned several Visual Studio installations: {}; adjust the arguments passed with `Vcvars::not_vswhere_latest_but()`", .0.iter().map(|path| format!("`{path}`")).join(", "))]
+    AmbiguousInstallation(Vec<String>),
+    #[error("couldn't decode `vswhere.exe` output as UTF-8 or in the OEM code page: {}", .0.iter().map(|byte| format!("{byte:02x}")).join(" "))]
+    UndecodableVswhereOutput(Vec<u8>),
+    #[error("couldn't parse `vswhere.exe` output: {0}")]
+    InvalidVswhereOutput(String),
+    #[error("couldn't find the Universal CRT; install the \"Windows Universal CRT SDK\" component or a Windows SDK with the Visual Studio Installer")]
+    UcrtNotFound,
+    #[error("ATL/MFC isn't installed; install the Visual Studio component `Microsoft.VisualStudio.Component.VC.ATLMFC`")]
+    AtlMfcNotInstalled,
+    #[error("couldn't find CRT redistributable files in `{0}`")]
+    CrtRedistNotFound(String),
+    #[error("couldn't parse version `{0}`")]
+    InvalidVersion(String),
+    #[error("unknown architecture `{0}`")]
+    InvalidArch(String),
+    #[error("unknown app platform `{0}`")]
+    InvalidAppPlatform(String),
+    #[error("invalid configuration: {0}")]
+    InvalidConfiguration(String),
+    #[error("couldn't join paths: {0}")]
+    JoinPathsFailed(env::JoinPathsError),
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        vars, AppPlatform, Arch, AsanRuntime, AtlMfcDirs, CacheLock, CrtRedistKind, DiaSdk, EnvMap,
+        InstallationInfo, SdkVersion, SelectionStrategy, ToolchainPreference, VarOrigin,
+        VcToolsVersion, Vcvars, VcvarsEnvironment, VcvarsError, VsCmdArgs, VsDevCmdOptions,
+        VsInstallationVersion, VswhereOptions, VswhereQuery, WellKnownVar,
+    };
+    use itertools::Itertools;
+    use regex::Regex;
+    use serial_test::serial;
+    use std::{
+        env,
+        ffi::OsString,
+        fs, io,
+        os::windows::ffi::OsStringExt,
+        path::{Path, PathBuf},
+        process::Command,
+        sync::Arc,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    fn prepare() {
+        // Normally set by Cargo.
+        env::set_var("CARGO_CFG_TARGET_ARCH", env::consts::ARCH);
+        env::set_var("CARGO_CFG_TARGET_ENV", "msvc");
+
+        // Run vcvars even if the tests run in a Developer Command Prompt.
+        env::remove_var(vars::VSCMD_VER);
+        env::remove_var(vars::VSINSTALLDIR);
+        env::remove_var(crate::VCVARSALL_ENV_VAR);
+
+        // Undo `prepare_synthetic()`.
+        env::set_var("OUT_DIR", env!("OUT_DIR"));
+    }
+
+    fn prepare_synthetic(test_name: &str, env_map: &[(&str, &str)]) -> Vcvars<'static> {
+        // Use an own cache directory, so the real values cached by other tests don't interfere.
+        let mut out_dir = PathBuf::from(env!("OUT_DIR"));
+        out_dir.push("synthetic");
+        out_dir.push(test_name);
+        if let Err(err) = fs::remove_dir_all(&out_dir) {
+            assert!(
+                matches!(err.kind(), io::ErrorKind::NotFound),
+                "should've been able to remove synthetic out dir: {err}"
+            );
+        }
+        fs::create_dir_all(&out_dir).unwrap();
+        env::set_var("OUT_DIR", out_dir);
+
+        // Normally set by Cargo. The cache paths depend on the target architecture.
+        env::set_var("CARGO_CFG_TARGET_ARCH", env::consts::ARCH);
+        env::set_var("CARGO_CFG_TARGET_ENV", "msvc");
+
+        Vcvars {
+            env_map: Some(
+                env_map
+                    .iter()
+                    .map(|&(key, value)| (key.to_uppercase(), value.to_owned()))
+                    .collect(),
+            ),
+            ..Vcvars::new()
+        }
+    }
+
+    fn version_number_regex() -> Regex {
+        Regex::new(r"^(\d+\.)+\d+$").unwrap()
+    }
+
+    #[test]
+    #[serial]
+    fn get() {
+        prepare();
+
+        let mut vcvars = Vcvars::new();
 
         let start = Instant::now();
         let value = vcvars.get("VisualStudioVersion").unwrap();
         assert!(version_number_regex().is_match(value), "{value}");
         let initial_get_duration = start.elapsed();
 
-        let start = Instant::now();
-        let value = vcvars.get("INCLUDE").unwrap();
+        let start = Instant::now();
+        let value = vcvars.get("INCLUDE").unwrap();
+        assert!(
+            Regex::new(r"(?i)^[A-Z]:\\").unwrap().is_match(value)
+                && value.contains("Visual Studio")
+                && value.matches(';').count() >= 4,
+            "{value}"
+        );
+        let followup_get_duration = start.elapsed();
+
+        assert!(
+            followup_get_duration < initial_get_duration / 1000,
+            "getting 2nd env var should've been much faster than getting 1st"
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn get_all() {
+        prepare();
+
+        let mut vcvars = Vcvars::new();
+        let env_map = vcvars.get_all().unwrap();
+
+        for key in ["INCLUDE", "LIB", "PATH", "VISUALSTUDIOVERSION"] {
+            assert!(env_map.contains_key(key), "{key}");
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn vars() {
+        prepare();
+
+        let mut vcvars = Vcvars::new();
+        let keys: Vec<_> = vcvars.vars().unwrap().map(|(key, _)| key).collect();
+        assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(keys.iter().any(|key| key.starts_with("VSCMD_")));
+    }
+
+    #[test]
+    fn split_path_list() {
+        let paths = crate::split_path_list(r"C:\a;;C:\b;C:\a;C:\c;");
+        assert_eq!(
+            paths,
+            [r"C:\a", r"C:\b", r"C:\c"].map(PathBuf::from),
+            "should've dropped empty and duplicate entries"
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn get_paths() {
+        prepare();
+
+        let mut vcvars = Vcvars::new();
+        let paths = vcvars.get_paths("INCLUDE").unwrap();
+        assert!(paths.len() >= 4, "{paths:?}");
+        assert!(paths.iter().all(|path| path.is_absolute()), "{paths:?}");
+    }
+
+    #[test]
+    #[serial]
+    fn include_dirs() {
+        prepare();
+
+        let mut vcvars = Vcvars::new();
+        let dirs = vcvars.include_dirs().unwrap();
+        assert!(!dirs.is_empty());
+        assert!(dirs.iter().all(|dir| dir.is_dir()), "{dirs:?}");
+    }
+
+    #[test]
+    #[serial]
+    fn include_dirs_synthetic() {
+        let existing_dir = env!("OUT_DIR");
+        let missing_dir = r"C:\vcvars-rs-test\missing";
+        let include = format!("{existing_dir};{missing_dir};");
+        let mut vcvars = prepare_synthetic("include_dirs", &[("INCLUDE", &include)]);
+
+        assert_eq!(
+            vcvars.include_dirs().unwrap(),
+            [PathBuf::from(existing_dir)]
+        );
+        assert_eq!(
+            vcvars.include_dirs_unfiltered().unwrap(),
+            [existing_dir, missing_dir].map(PathBuf::from)
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn lib_dirs() {
+        prepare();
+
+        let arch_dir_name = match env::consts::ARCH {
+            "x86" => "x86",
+            "x86_64" => "x64",
+            "aarch64" => "arm64",
+            arch => panic!("unexpected architecture `{arch}`"),
+        };
+
+        let mut vcvars = Vcvars::new();
+        let dirs = vcvars.lib_dirs().unwrap();
+        assert!(
+            dirs.iter()
+                .any(|dir| dir.ends_with(format!(r"lib\{arch_dir_name}"))),
+            "{dirs:?}"
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn libpath_dirs_synthetic() {
+        let mut vcvars = prepare_synthetic(
+            "libpath_dirs_present",
+            &[("LIBPATH", r"C:\a\References;relative;C:\b;")],
+        );
+        assert_eq!(
+            vcvars.libpath_dirs().unwrap(),
+            [r"C:\a\References", r"C:\b"].map(PathBuf::from)
+        );
+
+        let mut vcvars = prepare_synthetic("libpath_dirs_absent", &[("LIB", r"C:\a")]);
+        assert!(vcvars.libpath_dirs().unwrap().is_empty());
+    }
+
+    #[test]
+    #[serial]
+    fn path_additions_synthetic() {
+        let mut vcvars =
+            prepare_synthetic("path_additions", &[("PATH", r"C:\vs\x;C:\b;C:\vs\y;C:\A\")]);
+        vcvars.parent_env_map = Some([("PATH".to_owned(), r"C:\a;C:\b".to_owned())].into());
+
+        assert_eq!(
+            vcvars.path_additions().unwrap(),
+            [r"C:\vs\x", r"C:\vs\y"].map(PathBuf::from)
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn merged_path_synthetic() {
+        let mut vcvars = prepare_synthetic(
+            "merged_path",
+            &[("PATH", r"C:\vc\bin;C:\Existing\;C:\vc\bin\;C:\vc\tools")],
+        );
+
+        let original_path = env::var_os("PATH").unwrap();
+        env::set_var("PATH", r"C:\existing;C:\other");
+        let merged_path = vcvars.merged_path();
+        env::set_var("PATH", original_path);
+
+        assert_eq!(
+            merged_path.unwrap(),
+            r"C:\vc\bin;C:\vc\tools;C:\existing;C:\other"
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn compiler_path() {
+        prepare();
+
+        let mut vcvars = Vcvars::new();
+        for name in ["cl", "link", "lib"] {
+            let path = vcvars.compiler_path(name).unwrap();
+            assert!(path.is_file(), "{path:?}");
+        }
+
+        assert!(matches!(
+            vcvars.compiler_path("nonexistent-tool"),
+            Err(VcvarsError::MsvcToolNotFound(_))
+        ));
+    }
+
+    #[test]
+    #[serial]
+    fn msvc_tools() {
+        prepare();
+
+        let mut vcvars = Vcvars::new();
+        let tools = vcvars.msvc_tools().unwrap();
+        for path in [&tools.cl, &tools.link, &tools.lib, &tools.nmake] {
+            assert!(path.is_file(), "{path:?}");
+        }
+        assert!(version_number_regex().is_match(&tools.version), "{tools:?}");
+    }
+
+    #[test]
+    #[serial]
+    fn rc_exe_and_mt_exe() {
+        prepare();
+
+        let mut vcvars = Vcvars::new();
+        for path in [vcvars.rc_exe().unwrap(), vcvars.mt_exe().unwrap()] {
+            assert!(path.is_file(), "{path:?}");
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn midl_exe_and_cppwinrt_exe() {
+        prepare();
+
+        let mut vcvars = Vcvars::new();
+        for path in [vcvars.midl_exe().unwrap(), vcvars.cppwinrt_exe().unwrap()] {
+            assert!(path.is_file(), "{path:?}");
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn midl_exe_synthetic() {
+        let sdk_bin_dir = PathBuf::from(env!("OUT_DIR"))
+            .join("synthetic")
+            .join("midl_exe")
+            .join(r"sdk\bin\10.0.1.0");
+        let sdk_bin_dir_string = sdk_bin_dir.to_string_lossy().into_owned();
+        let env_map = [
+            ("VSCMD_ARG_HOST_ARCH", "arm64"),
+            ("WindowsSdkVerBinPath", sdk_bin_dir_string.as_str()),
+        ];
+
+        let mut vcvars = prepare_synthetic("midl_exe", &env_map);
+        assert!(matches!(
+            vcvars.midl_exe(),
+            Err(VcvarsError::WindowsSdkFeatureNotFound(_, _))
+        ));
+
+        let midl_path = sdk_bin_dir.join(r"arm64\midl.exe");
+        fs::create_dir_all(midl_path.parent().unwrap()).unwrap();
+        fs::write(&midl_path, "").unwrap();
+        assert_eq!(vcvars.midl_exe().unwrap(), midl_path);
+    }
+
+    #[test]
+    #[serial]
+    fn windows_sdk_tool_path_synthetic() {
+        let sdk_dir = PathBuf::from(env!("OUT_DIR"))
+            .join("synthetic")
+            .join("windows_sdk_tool_path")
+            .join("sdk");
+        let sdk_dir_string = format!("{}\\", sdk_dir.display());
+        let env_map = [
+            ("VSCMD_ARG_HOST_ARCH", "x64"),
+            ("WindowsSdkDir", &sdk_dir_string),
+            ("WindowsSDKVersion", r"10.0.1.0\"),
+        ];
+
+        // Newer layout.
+        let mut vcvars = prepare_synthetic("windows_sdk_tool_path", &env_map);
+        let rc_path = sdk_dir.join(r"bin\10.0.1.0\x64\rc.exe");
+        fs::create_dir_all(rc_path.parent().unwrap()).unwrap();
+        fs::write(&rc_path, "").unwrap();
+        assert_eq!(vcvars.rc_exe().unwrap(), rc_path);
+
+        // Older layout.
+        let mt_path = sdk_dir.join(r"bin\x64\mt.exe");
+        fs::create_dir_all(mt_path.parent().unwrap()).unwrap();
+        fs::write(&mt_path, "").unwrap();
+        assert_eq!(vcvars.mt_exe().unwrap(), mt_path);
+
+        assert!(matches!(
+            vcvars.windows_sdk_tool_path("nonexistent-tool"),
+            Err(VcvarsError::WindowsSdkToolNotFound(_))
+        ));
+    }
+
+    #[test]
+    #[serial]
+    fn msbuild_path() {
+        prepare();
+
+        let mut vcvars = Vcvars::new();
+        let path = vcvars.msbuild_path().unwrap();
+        assert!(path.ends_with("MSBuild.exe"), "{path:?}");
+        assert!(path.is_file(), "{path:?}");
+        assert_eq!(vcvars.msbuild_path().unwrap(), path);
+    }
+
+    #[test]
+    #[serial]
+    fn vswhere_find() {
+        prepare();
+
+        let mut vcvars = Vcvars::new();
+        let paths = vcvars.vswhere_find(r"Common7\Tools\VsDevCmd.bat").unwrap();
+        assert_eq!(paths.len(), 1, "{paths:?}");
+        assert!(paths[0].is_file(), "{paths:?}");
+        assert_eq!(
+            vcvars.vswhere_find(r"Common7\Tools\VsDevCmd.bat").unwrap(),
+            paths
+        );
+
+        let cl_paths = vcvars.vswhere_find(r"**\bin\Host*\*\cl.exe").unwrap();
+        assert!(!cl_paths.is_empty());
+        assert!(cl_paths.iter().all(|path| path.ends_with("cl.exe")));
+
+        assert!(vcvars
+            .vswhere_find("does_not_exist.exe")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    #[serial]
+    fn windows_sdk() {
+        prepare();
+
+        let mut vcvars = Vcvars::new();
+        let sdk = vcvars.windows_sdk().unwrap();
+        assert!(version_number_regex().is_match(&sdk.version), "{sdk:?}");
+        assert!(sdk.bin.is_dir(), "{sdk:?}");
+        assert!(sdk.include_dirs.iter().all(|dir| dir.is_dir()), "{sdk:?}");
+        assert!(
+            sdk.include_dirs
+                .iter()
+                .any(|dir| dir.ends_with("um") && dir.join("Windows.h").is_file()),
+            "{sdk:?}"
+        );
+        assert!(!sdk.lib_dirs.is_empty(), "{sdk:?}");
+    }
+
+    #[test]
+    #[serial]
+    fn vc_tools_version() {
+        prepare();
+
+        let mut vcvars = Vcvars::new();
+        assert!(vcvars.vc_tools_version().unwrap() >= VcToolsVersion::new(14, 0, 0));
+    }
+
+    #[test]
+    #[serial]
+    fn vc_tools_version_synthetic() {
+        let mut vcvars = prepare_synthetic(
+            "vc_tools_version",
+            &[("VCToolsInstallDir", r"C:\VS\VC\Tools\MSVC\14.29.30133\")],
+        );
+        assert_eq!(
+            vcvars.vc_tools_version().unwrap(),
+            VcToolsVersion::new(14, 29, 30133)
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn missing_cpp_workload_synthetic() {
+        prepare();
+
+        let visual_studio_dir = PathBuf::from(env!("OUT_DIR"))
+            .join("synthetic")
+            .join("missing_cpp_workload")
+            .join("vs");
+        if let Err(err) = fs::remove_dir_all(&visual_studio_dir) {
+            assert!(matches!(err.kind(), io::ErrorKind::NotFound), "{err}");
+        }
+        fs::create_dir_all(visual_studio_dir.join(r"Common7\IDE")).unwrap();
+
+        let new_vcvars = || Vcvars {
+            visual_studio_dir: Some(visual_studio_dir.clone()),
+            ..Vcvars::new()
+        };
+
+        // Only other workloads.
+        let err = new_vcvars().get(vars::INCLUDE).unwrap_err();
+        assert!(
+            matches!(
+                &err,
+                VcvarsError::MissingCppWorkload {
+                    installation,
+                    has_empty_msvc_dir: false,
+                } if Path::new(installation) == visual_studio_dir
+            ),
+            "{err:?}"
+        );
+        assert!(err.to_string().contains("Desktop development with C++"));
+        assert!(err
+            .to_string()
+            .contains("Microsoft.VisualStudio.Component.VC.Tools.x86.x64"));
+
+        // Leftovers of the C++ workload.
+        let build_dir = visual_studio_dir.join(r"VC\Auxiliary\Build");
+        fs::create_dir_all(&build_dir).unwrap();
+        fs::write(build_dir.join("vcvarsall.bat"), "").unwrap();
+        fs::create_dir_all(visual_studio_dir.join(r"VC\Tools\MSVC")).unwrap();
+        let err = new_vcvars().get(vars::INCLUDE).unwrap_err();
+        assert!(
+            matches!(
+                err,
+                VcvarsError::MissingCppWorkload {
+                    has_empty_msvc_dir: true,
+                    ..
+                }
+            ),
+            "{err:?}"
+        );
+        assert!(err.to_string().contains(r"VC\Tools\MSVC"));
+
+        // Not an installation.
+        let mut vcvars = Vcvars {
+            visual_studio_dir: Some(visual_studio_dir.join("nonexistent")),
+            ..Vcvars::new()
+        };
+        assert!(matches!(
+            vcvars.get(vars::INCLUDE),
+            Err(VcvarsError::FileNotFound(_))
+        ));
+    }
+
+    #[test]
+    #[serial]
+    fn with_installation_dir_synthetic() {
+        prepare();
+
+        let visual_studio_dir = PathBuf::from(env!("OUT_DIR"))
+            .join("synthetic")
+            .join("with_installation_dir")
+            .join("vs");
+
+        let mut vcvars = prepare_synthetic("with_installation_dir", &[]);
+        vcvars.env_map = None;
+        let mut vcvars = vcvars
+            .with_installation_dir(&visual_studio_dir)
+            .vswhere_path(visual_studio_dir.join("nonexistent_vswhere.exe"));
+        assert!(vcvars.cache_key().starts_with("installation_dir="));
+        assert_ne!(
+            vcvars.cache_key(),
+            Vcvars::new()
+                .with_installation_dir(visual_studio_dir.join("other"))
+                .cache_key()
+        );
+        assert!(matches!(
+            vcvars.get_cached(vars::INCLUDE),
+            Err(VcvarsError::FileNotFound(path)) if Path::new(&path) == visual_studio_dir
+        ));
+
+        // Reaches the point of running vcvars without `vswhere.exe`.
+        fs::create_dir_all(&visual_studio_dir).unwrap();
+        assert!(matches!(
+            vcvars.get_cached(vars::INCLUDE),
+            Err(VcvarsError::MissingCppWorkload { installation, .. }) if Path::new(&installation) == visual_studio_dir
+        ));
+
+        // A directory name with an unpaired surrogate can't be passed to `cmd.exe`.
+        let mut dir_name = OsString::from_wide(&[u16::from(b'v'), 0xD800]);
+        dir_name.push("s");
+        let visual_studio_dir = visual_studio_dir.with_file_name(dir_name);
+        let build_dir = visual_studio_dir.join(r"VC\Auxiliary\Build");
+        fs::create_dir_all(&build_dir).unwrap();
+        fs::write(build_dir.join("vcvarsall.bat"), "").unwrap();
+        let mut vcvars = Vcvars::new()
+            .with_installation_dir(&visual_studio_dir)
+            .vswhere_path(visual_studio_dir.join("nonexistent_vswhere.exe"));
+        assert!(matches!(
+            vcvars.get(vars::INCLUDE),
+            Err(VcvarsError::NonUtf8Path(path)) if path.ends_with("vcvarsall.bat")
+        ));
+    }
+
+    #[test]
+    #[serial]
+    fn installed_toolsets_synthetic() {
+        let visual_studio_dir = PathBuf::from(env!("OUT_DIR"))
+            .join("synthetic")
+            .join("installed_toolsets")
+            .join("vs");
+
+        let mut vcvars = prepare_synthetic("installed_toolsets", &[]);
+        vcvars.visual_studio_dir = Some(visual_studio_dir.clone());
+        assert!(matches!(
+            vcvars.installed_toolsets(),
+            Err(VcvarsError::FileNotFound(_))
+        ));
+
+        let msvc_dir = visual_studio_dir.join(r"VC\Tools\MSVC");
+        for dir_name in ["14.38.33130", "14.29.30133", "14.9.0", "not_a_version"] {
+            fs::create_dir_all(msvc_dir.join(dir_name)).unwrap();
+        }
+        fs::write(msvc_dir.join("14.40.33807"), "").unwrap();
+
+        assert_eq!(
+            vcvars.installed_toolsets().unwrap(),
+            [
+                VcToolsVersion::new(14, 9, 0),
+                VcToolsVersion::new(14, 29, 30133),
+                VcToolsVersion::new(14, 38, 33130),
+            ]
+        );
+
+        assert_eq!(
+            vcvars.tools_dir().unwrap(),
+            Some(msvc_dir.join("14.38.33130"))
+        );
+        vcvars.toolset_version = Some("14.29");
+        assert_eq!(
+            vcvars.tools_dir().unwrap(),
+            Some(msvc_dir.join("14.29.30133"))
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn available_targets_synthetic() {
+        let visual_studio_dir = PathBuf::from(env!("OUT_DIR"))
+            .join("synthetic")
+            .join("available_targets")
+            .join("vs");
+
+        let mut vcvars = prepare_synthetic("available_targets", &[]);
+        vcvars.visual_studio_dir = Some(visual_studio_dir.clone());
+
+        let default_file =
+            visual_studio_dir.join(r"VC\Auxiliary\Build\Microsoft.VCToolsVersion.default.txt");
+        fs::create_dir_all(default_file.parent().unwrap()).unwrap();
+        fs::write(default_file, "14.38.33130\r\n").unwrap();
+
+        let host_arch = Arch::from_rust_arch(env::consts::ARCH).unwrap();
+        let tools_dir = visual_studio_dir.join(r"VC\Tools\MSVC\14.38.33130");
+        for (arch, has_lib, has_cl) in [
+            ("x86", true, true),
+            ("x64", true, true),
+            ("arm", true, false),
+            ("arm64", false, true),
+        ] {
+            if has_lib {
+                fs::create_dir_all(tools_dir.join("lib").join(arch)).unwrap();
+            }
+            if has_cl {
+                let bin_dir = tools_dir
+                    .join("bin")
+                    .join(format!("Host{host_arch}"))
+                    .join(arch);
+                fs::create_dir_all(&bin_dir).unwrap();
+                fs::write(bin_dir.join("cl.exe"), "").unwrap();
+            }
+        }
+
+        assert_eq!(vcvars.available_targets().unwrap(), [Arch::X86, Arch::X64]);
+    }
+
+    #[test]
+    #[serial]
+    fn installed_sdks() {
+        prepare();
+
+        let sdks = Vcvars::new().installed_sdks().unwrap();
+        assert!(sdks.windows(2).all(|pair| pair[0] <= pair[1]));
+
+        let mut vcvars = Vcvars::new();
+        if let Some(version) = vcvars.get_cached_opt(vars::WINDOWS_SDK_VERSION).unwrap() {
+            assert!(sdks.contains(&version.parse().unwrap()));
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn visual_studio_version() {
+        prepare();
+
+        let mut vcvars = Vcvars::new();
+        let version = vcvars.visual_studio_version().unwrap();
+        assert!(version.year().is_some(), "{version}");
+    }
+
+    #[test]
+    #[serial]
+    fn ucrt() {
+        prepare();
+
+        let mut vcvars = Vcvars::new();
+        let ucrt = vcvars.ucrt().unwrap();
+        assert!(version_number_regex().is_match(&ucrt.version), "{ucrt:?}");
+        assert!(ucrt.include_dir.join("stdio.h").is_file(), "{ucrt:?}");
+        assert!(ucrt.lib_dir.join("ucrt.lib").is_file(), "{ucrt:?}");
+    }
+
+    #[test]
+    #[serial]
+    fn get_many() {
+        prepare();
+
+        let mut vcvars = Vcvars::new();
+        let values = vcvars
+            .get_many(&["INCLUDE", "LIB", "PATH", "VCToolsInstallDir"])
+            .unwrap();
+        assert_eq!(values.len(), 4);
+        assert!(values.contains_key("VCToolsInstallDir"));
+    }
+
+    #[test]
+    #[serial]
+    fn get_cached_many_synthetic() {
+        let mut vcvars = prepare_synthetic("get_cached_many", &[("A", "a"), ("B", "b")]);
+        vcvars.get_cached("A").unwrap();
+
+        // `A` can only come from the cache file.
+        let mut vcvars = Vcvars {
+            env_map: Some([("B".to_owned(), "b".to_owned())].into()),
+            ..Vcvars::new()
+        };
+        let values = vcvars.get_cached_many(&["A", "B"]).unwrap();
+        assert_eq!(values["A"], "a");
+        assert_eq!(values["B"], "b");
+
+        match vcvars.get_cached_many(&["A", "C", "D"]) {
+            Err(VcvarsError::VarsNotFound(names)) => assert_eq!(names, ["C", "D"]),
+            result => panic!("should've reported missing vars: {result:?}"),
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn env_cache_file_synthetic() {
+        let mut vcvars = prepare_synthetic("env_cache_file", &[("A", "a"), ("B", "b=c")]);
+        let legacy_cache_dir = crate::cache_dir("");
+        fs::create_dir_all(&legacy_cache_dir).unwrap();
+        fs::write(legacy_cache_dir.join("LEGACY.txt"), "legacy").unwrap();
+        fs::write(legacy_cache_dir.join("=env.txt"), "A=legacy").unwrap();
+        assert_eq!(vcvars.get_cached("A").unwrap(), "a");
+        let cache_dir = vcvars
+            .arch_cache_dir(vcvars.resolved_target_arch().unwrap())
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(cache_dir.join("=env.txt")).unwrap(),
+            "A=a\nB=b=c"
+        );
+        assert!(!legacy_cache_dir.join("LEGACY.txt").exists());
+        assert!(!legacy_cache_dir.join("=env.txt").exists());
+
+        // Another variable in a later run doesn't require vcvars to be run again.
+        let mut vcvars = Vcvars::new();
+        assert_eq!(vcvars.get_cached("b").unwrap(), "b=c");
+        assert_eq!(vcvars.get_cached_opt("C").unwrap(), None);
+        assert!(vcvars.env_map.is_none());
+    }
+
+    #[test]
+    #[serial]
+    fn empty_cache_file_synthetic() {
+        let mut vcvars = prepare_synthetic("empty_cache_file", &[("A", "a")]);
+        // Like left behind by a killed build script of an earlier version of this crate.
+        let cache_dir = vcvars.ensure_cache_dir().unwrap();
+        let cache_file = cache_dir.join("=env.txt");
+        fs::write(&cache_file, "").unwrap();
+
+        assert_eq!(vcvars.get_cached("A").unwrap(), "a");
+        assert_eq!(fs::read_to_string(&cache_file).unwrap(), "A=a");
+
+        let file_names = fs::read_dir(&cache_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect::<Vec<_>>();
+        assert!(
+            file_names
+                .iter()
+                .all(|file_name| !file_name.to_string_lossy().ends_with(".tmp")),
+            "{file_names:?}"
+        );
+
+        crate::write_cache_file(&cache_file, "A=b").unwrap();
+        assert_eq!(fs::read_to_string(&cache_file).unwrap(), "A=b");
+    }
+
+    #[test]
+    #[serial]
+    fn cache_lock_synthetic() {
+        prepare_synthetic("cache_lock", &[]);
+        let cache_dir = Vcvars::new().ensure_cache_dir().unwrap();
+
+        // Each thread would create the cache file with its own value, if it didn't wait for the first one.
+        let values = thread::scope(|scope| {
+            let handles = (0..8)
+                .map(|index| {
+                    scope.spawn(move || {
+                        let mut vcvars = Vcvars {
+                            env_map: Some([("A".to_owned(), index.to_string())].into()),
+                            ..Vcvars::new()
+                        };
+                        vcvars.get_cached("A").unwrap().into_owned()
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+        assert!(values.iter().all_equal(), "{values:?}");
+        assert_eq!(
+            fs::read_to_string(cache_dir.join("=env.txt")).unwrap(),
+            format!("A={}", values[0])
+        );
+
+        let lock = CacheLock::acquire(&cache_dir, Duration::ZERO).unwrap();
+        assert!(CacheLock::acquire(&cache_dir, Duration::from_millis(200)).is_none());
+        drop(lock);
+        assert!(CacheLock::acquire(&cache_dir, Duration::ZERO).is_some());
+    }
+
+    #[test]
+    #[serial]
+    fn clear_cache_synthetic() {
+        let mut vcvars = prepare_synthetic("clear_cache", &[("A", "a")]);
+        assert_eq!(vcvars.get_cached("A").unwrap(), "a");
+        let cache_dir = crate::cache_dir("");
+        assert!(cache_dir.is_dir());
+
+        vcvars.clear_cache().unwrap();
+        assert!(!cache_dir.exists());
+        assert!(vcvars.env_map.is_none());
+        assert!(vcvars.cached_env_map.is_none());
+        // Not failing if already cleared.
+        Vcvars::clear_cache_in(cache_dir.parent().unwrap()).unwrap();
+        assert!(cache_dir.parent().unwrap().is_dir());
+
+        // The value isn't served from a cache anymore, but from the memory cache created by running vcvars, as faked here.
+        vcvars.env_map = Some([("A".to_owned(), "b".to_owned())].into());
+        assert_eq!(vcvars.get_cached("A").unwrap(), "b");
+        assert!(cache_dir.is_dir());
+    }
+
+    #[test]
+    #[serial]
+    fn force_refresh() {
+        prepare();
+
+        let mut vcvars = Vcvars::new();
+        vcvars.get_cached("INCLUDE").unwrap();
+        let cache_dir = vcvars.ensure_cache_dir().unwrap();
+        let modified = |file_name| {
+            fs::metadata(cache_dir.join(file_name))
+                .unwrap()
+                .modified()
+                .unwrap()
+        };
+        let env_modified = modified("=env.txt");
+        let host_arch_modified = modified("=host_arch.txt");
+        thread::sleep(Duration::from_millis(50));
+
+        let mut vcvars = Vcvars::new();
+        vcvars.force_refresh().unwrap();
+        assert!(vcvars.env_map.is_some());
+        assert!(modified("=env.txt") > env_modified);
+        assert!(modified("=host_arch.txt") > host_arch_modified);
+        assert!(!vcvars.get_cached("INCLUDE").unwrap().is_empty());
+    }
+
+    #[test]
+    #[serial]
+    fn arch_cache_dir_synthetic() {
+        let mut vcvars = prepare_synthetic("arch_cache_dir", &[("INCLUDE", "x64_include")]);
+        env::set_var("CARGO_CFG_TARGET_ARCH", "x86_64");
+        assert_eq!(vcvars.get_cached("INCLUDE").unwrap(), "x64_include");
+
+        env::set_var("CARGO_CFG_TARGET_ARCH", "x86");
+        let mut vcvars = Vcvars {
+            env_map: Some([("INCLUDE".to_owned(), "x86_include".to_owned())].into()),
+            ..Vcvars::new()
+        };
+        assert_eq!(vcvars.get_cached("INCLUDE").unwrap(), "x86_include");
+        assert!(vcvars.arch_cache_dir(Arch::X86).unwrap().ends_with(format!(
+            "vcvars-cache\\{}_x86",
+            vcvars.host_arch("x86").unwrap()
+        )));
+
+        // Served from the cache file of the architecture.
+        env::set_var("CARGO_CFG_TARGET_ARCH", "x86_64");
+        let mut vcvars = Vcvars::new();
+        assert_eq!(vcvars.get_cached("INCLUDE").unwrap(), "x64_include");
+        assert!(vcvars.env_map.is_none());
+
+        prepare();
+    }
+
+    #[test]
+    #[serial]
+    fn config_manifest_synthetic() {
+        prepare_synthetic("config_manifest", &[]);
+
+        // Different configurations with the same cache directory don't share values.
+        let products = [&["a/b"][..], &["a\\b"]];
+        let new_vcvars = |products, value: &str| Vcvars {
+            env_map: Some([("A".to_owned(), value.to_owned())].into()),
+            ..Vcvars::new().products(products)
+        };
+        let mut vcvars = new_vcvars(products[0], "a/b");
+        let mut other_vcvars = new_vcvars(products[1], "a\\b");
+        assert_eq!(vcvars.cache_key(), other_vcvars.cache_key());
+        assert_ne!(vcvars.config_key(), other_vcvars.config_key());
+        assert_eq!(vcvars.get_cached("A").unwrap(), "a/b");
+        assert_eq!(other_vcvars.get_cached("A").unwrap(), "a\\b");
+
+        // An identical configuration reuses the values.
+        let mut vcvars = Vcvars::new().products(products[1]);
+        assert_eq!(vcvars.get_cached("A").unwrap(), "a\\b");
+        assert!(vcvars.env_map.is_none());
+
+        let long_value = "x".repeat(300);
+        let vcvars = Vcvars::new().child_env("A", &long_value);
+        let cache_key = vcvars.cache_key();
+        assert_eq!(cache_key.len(), crate::MAX_CACHE_KEY_LEN);
+        assert!(cache_key.starts_with("A=xxx"), "{cache_key}");
+        assert_ne!(
+            cache_key,
+            Vcvars::new().child_env("A", &long_value[1..]).cache_key()
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn installation_stamp_synthetic() {
+        prepare_synthetic("installation_stamp", &[]);
+        let visual_studio_dir = PathBuf::from(env::var("OUT_DIR").unwrap()).join("vs");
+        let build_dir = visual_studio_dir.join(r"VC\Auxiliary\Build");
+        let vcvarsall_path = build_dir.join("vcvarsall.bat");
+        let toolset_file = build_dir.join("Microsoft.VCToolsVersion.default.txt");
+        fs::create_dir_all(&build_dir).unwrap();
+        fs::write(&vcvarsall_path, "@rem old").unwrap();
+        fs::write(&toolset_file, "14.38.33130\r\n").unwrap();
+
+        // Faking that vcvars was run from the installation.
+        let new_vcvars = |value: &str| Vcvars {
+            env_map: Some([("A".to_owned(), value.to_owned())].into()),
+            visual_studio_dir: Some(visual_studio_dir.clone()),
+            vcvarsall_path: Some(vcvarsall_path.clone()),
+            ..Vcvars::new()
+        };
+        let mut vcvars = new_vcvars("old");
+        assert_eq!(vcvars.get_cached("A").unwrap(), "old");
+
+        let manifest_file = vcvars.ensure_cache_dir().unwrap().join("=config.txt");
+        let manifest = fs::read_to_string(&manifest_file).unwrap();
+        let lines = manifest.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 5, "{manifest}");
+        assert_eq!(lines[0], vcvars.config_key());
+        assert_eq!(Path::new(lines[1]), vcvarsall_path);
+        assert!(lines[2].starts_with("size=8 modified="), "{manifest}");
+        assert_eq!(Path::new(lines[3]), toolset_file);
+        assert!(lines[4].starts_with("size=13 modified="), "{manifest}");
+
+        // Cached values are served while the installation is unchanged.
+        let mut vcvars = new_vcvars("new");
+        assert_eq!(vcvars.get_cached("A").unwrap(), "old");
+
+        // An update changing just the default toolset makes the values be refreshed. The size stays the same, so the modification time must differ.
+        thread::sleep(Duration::from_millis(50));
+        fs::write(&toolset_file, "14.40.33807\r\n").unwrap();
+        let mut vcvars = new_vcvars("new");
+        assert_eq!(vcvars.get_cached("A").unwrap(), "new");
+        assert_ne!(fs::read_to_string(&manifest_file).unwrap(), manifest);
+
+        fs::write(&vcvarsall_path, "@rem new file").unwrap();
+        let mut vcvars = new_vcvars("newer");
+        assert_eq!(vcvars.get_cached("A").unwrap(), "newer");
+
+        fs::remove_file(&vcvarsall_path).unwrap();
+        let mut vcvars = new_vcvars("newest");
+        assert_eq!(vcvars.get_cached("A").unwrap(), "newest");
+    }
+
+    #[test]
+    #[serial]
+    fn get_opt_synthetic() {
+        let mut vcvars = prepare_synthetic("get_opt", &[("A", "a")]);
+        assert_eq!(vcvars.get_opt("a").unwrap(), Some("a"));
+        assert_eq!(vcvars.get_opt("B").unwrap(), None);
+        assert_eq!(vcvars.get_or("B", "default").unwrap(), "default");
+        assert_eq!(vcvars.get_cached_opt("A").unwrap().as_deref(), Some("a"));
+        assert_eq!(vcvars.get_cached_opt("B").unwrap(), None);
+        assert_eq!(vcvars.get_cached_or("B", "default").unwrap(), "default");
+    }
+
+    #[test]
+    #[serial]
+    fn get_opt_failing() {
+        prepare();
+        env::remove_var("CARGO_CFG_TARGET_ARCH");
+
+        // Not being able to run vcvars must be distinguishable from a variable being absent.
+        let mut vcvars = Vcvars::new();
+        assert!(matches!(
+            vcvars.get_opt("INCLUDE"),
+            Err(VcvarsError::MissingEnvVarDependency(_))
+        ));
+
+        prepare();
+    }
+
+    #[test]
+    #[serial]
+    fn contains_synthetic() {
+        let mut vcvars = prepare_synthetic("contains", &[("A", "a")]);
+        assert!(vcvars.contains("a").unwrap());
+        assert!(!vcvars.contains("B").unwrap());
+        vcvars.get_cached("A").unwrap();
+
+        // `A` can only be found via the cache file.
+        let mut vcvars = Vcvars {
+            env_map: Some(EnvMap::new()),
+            ..Vcvars::new()
+        };
+        assert!(vcvars.contains("A").unwrap());
+    }
+
+    #[test]
+    #[serial]
+    fn changed_vars_synthetic() {
+        let mut vcvars = prepare_synthetic(
+            "changed_vars",
+            &[("ADDED", "a"), ("MODIFIED", "new"), ("UNCHANGED", "u")],
+        );
+        vcvars.parent_env_map = Some(
+            [("MODIFIED", "old"), ("UNCHANGED", "u"), ("REMOVED", "r")]
+                .map(|(key, value)| (key.to_owned(), value.to_owned()))
+                .into(),
+        );
+
+        let changed_vars = vcvars.changed_vars().unwrap();
+        assert_eq!(
+            changed_vars,
+            [("ADDED", "a"), ("MODIFIED", "new")]
+                .map(|(key, value)| (key.to_owned(), value.to_owned()))
+                .into()
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn origin_synthetic() {
+        let mut vcvars = prepare_synthetic(
+            "origin",
+            &[("ADDED", "a"), ("INCLUDE", "new"), ("UNCHANGED", "u")],
+        );
+        vcvars.parent_env_map = Some(
+            [("INCLUDE", "old"), ("UNCHANGED", "u")]
+                .map(|(key, value)| (key.to_owned(), value.to_owned()))
+                .into(),
+        );
+
+        assert_eq!(vcvars.origin("added").unwrap(), VarOrigin::Added);
+        assert_eq!(
+            vcvars.origin("Include").unwrap(),
+            VarOrigin::Modified {
+                previous: "old".to_owned()
+            }
+        );
+        assert_eq!(vcvars.origin("UNCHANGED").unwrap(), VarOrigin::Unchanged);
+        assert!(matches!(
+            vcvars.origin("MISSING"),
+            Err(VcvarsError::VarNotFound(_))
+        ));
+    }
+
+    #[test]
+    #[serial]
+    fn vcvarsall_path_and_command_line() {
+        prepare();
+
+        let mut vcvars = Vcvars::new();
+        let vcvarsall_path = vcvars.vcvarsall_path().unwrap().to_owned();
+        assert!(
+            vcvarsall_path.ends_with("vcvarsall.bat"),
+            "{vcvarsall_path:?}"
+        );
+
+        let command_line = vcvars.command_line().unwrap();
+        assert!(command_line.contains(" /C "), "{command_line}");
+        assert!(command_line.contains("vcvarsall.bat"), "{command_line}");
+        assert!(command_line.ends_with(" && set"), "{command_line}");
+    }
+
+    #[test]
+    #[serial]
+    fn sdk_version_synthetic() {
+        let mut vcvars = prepare_synthetic("sdk_version", &[("INCLUDE", "default")]);
+        assert_eq!(vcvars.get_cached("INCLUDE").unwrap(), "default");
+
+        let sdk_version = SdkVersion::new(10, 0, 19041, 0);
+        let mut vcvars = Vcvars {
+            env_map: Some([("INCLUDE".to_owned(), "pinned".to_owned())].into()),
+            ..Vcvars::new().sdk_version(sdk_version)
+        };
+        assert_eq!(vcvars.config_args(), ["10.0.19041.0"]);
+        assert_eq!(vcvars.get_cached("INCLUDE").unwrap(), "pinned");
+
+        let error = vcvars.vcvars_failure("[ERROR:winsdk.bat] Windows SDK 10.0.19041.0 : 'C:\\x'\r\n[ERROR:VsDevCmd.bat] *** VsDevCmd.bat encountered errors.");
+        assert!(matches!(error, VcvarsError::SdkNotFound(version) if version == "10.0.19041.0"));
+    }
+
+    #[test]
+    #[serial]
+    fn sdk_version_validation_synthetic() {
+        prepare_synthetic("sdk_version_validation", &[]);
+
+        let vcvars = Vcvars::new().sdk_version(SdkVersion::new(8, 1, 0, 0));
+        let env_map = |key: &str, value: &str| [(key.to_owned(), value.to_owned())].into();
+        assert!(vcvars
+            .validate_env_map(&env_map(
+                "WINDOWSSDKDIR",
+                r"C:\Program Files (x86)\Windows Kits\8.1\"
+            ))
+            .is_ok());
+        assert!(matches!(
+            vcvars.validate_env_map(&env_map("WINDOWSSDKDIR", r"C:\Program Files (x86)\Windows Kits\10\")),
+            Err(VcvarsError::SdkNotFound(version)) if version == "8.1"
+        ));
+
+        let vcvars = Vcvars::new().sdk_version(SdkVersion::new(10, 0, 19041, 0));
+        assert!(vcvars
+            .validate_env_map(&env_map("WINDOWSSDKVERSION", "10.0.19041.0\\"))
+            .is_ok());
+        assert!(vcvars
+            .validate_env_map(&env_map("WINDOWSSDKVERSION", "10.0.22621.0\\"))
+            .is_err());
+    }
+
+    #[test]
+    fn validate() {
+        let sdk_8_1 = SdkVersion::new(8, 1, 0, 0);
+
+        for (vcvars, message_part) in [
+            (
+                Vcvars::new()
+                    .app_platform(AppPlatform::Uwp)
+                    .sdk_version(sdk_8_1),
+                "app platform `UWP`",
+            ),
+            (
+                Vcvars::new().target(Arch::Arm64Ec).sdk_version(sdk_8_1),
+                "target architecture `arm64ec`",
+            ),
+            (
+                Vcvars::new().toolset_version("14.29 "),
+                "toolset version `14.29 `",
+            ),
+            (
+                Vcvars::new().child_env("A=B", "C"),
+                "child env var name `A=B`",
+            ),
+        ] {
+            match vcvars.validate() {
+                Err(VcvarsError::InvalidConfiguration(message)) => {
+                    assert!(message.contains(message_part), "{message}");
+                }
+                result => panic!("{message_part}: {result:?}"),
+            }
+        }
+
+        assert!(matches!(
+            Vcvars::new().host(Arch::Arm64Ec).target(Arch::Arm64).validate(),
+            Err(VcvarsError::UnsupportedArch(host, target)) if host == "arm64ec" && target == "arm64"
+        ));
+
+        Vcvars::new()
+            .use_vsdevcmd(VsDevCmdOptions::default())
+            .host(Arch::X86)
+            .target(Arch::Arm64)
+            .app_platform(AppPlatform::Uwp)
+            .sdk_version(SdkVersion::new(10, 0, 22621, 0))
+            .toolset_version("14.38.33130")
+            .spectre_libs(true)
+            .child_env("VSCMD_DEBUG", "1")
+            .validate()
+            .unwrap();
+    }
+
+    #[test]
+    #[serial]
+    fn toolset_version_synthetic() {
+        prepare_synthetic("toolset_version", &[]);
+
+        let mut vcvars = Vcvars::new()
+            .sdk_version(SdkVersion::new(8, 1, 0, 0))
+            .toolset_version("14.29");
+        assert_eq!(vcvars.config_args(), ["8.1", "-vcvars_ver=14.29"]);
+        assert_eq!(vcvars.cache_key(), "8.1 -vcvars_ver=14.29");
+        vcvars.sdk_version = None;
+
+        let env_map = |version: &str| [("VCTOOLSVERSION".to_owned(), version.to_owned())].into();
+        assert!(vcvars.validate_env_map(&env_map("14.29.30133")).is_ok());
+        assert!(matches!(
+            vcvars.validate_env_map(&env_map("14.38.33130")),
+            Err(VcvarsError::ToolsetNotFound(requested, selected)) if requested == "14.29" && selected == "14.38.33130"
+        ));
+        assert!(vcvars.validate_env_map(&env_map("14.2")).is_err());
+
+        vcvars.toolset_version = Some("14.29.30133");
+        assert!(vcvars.validate_env_map(&env_map("14.29.30133")).is_ok());
+    }
+
+    #[test]
+    #[serial]
+    fn spectre_libs() {
+        prepare();
+
+        let mut vcvars = Vcvars::new().spectre_libs(true);
+        assert!(vcvars
+            .config_args()
+            .ends_with(&["-vcvars_spectre_libs=spectre".to_owned()]));
+
+        match vcvars.get_cached("LIB") {
+            Ok(lib) => assert!(lib.to_lowercase().contains(r"\spectre\"), "{lib}"),
+            Err(VcvarsError::SpectreLibsNotInstalled) => {}
+            Err(err) => panic!("{err}"),
+        }
+
+        let lib = Vcvars::new().get_cached("LIB").unwrap().into_owned();
+        assert!(!lib.to_lowercase().contains(r"\spectre\"), "{lib}");
+    }
+
+    #[test]
+    #[serial]
+    fn app_platform() {
+        prepare();
+
+        let mut vcvars = Vcvars::new().app_platform(AppPlatform::Uwp);
+        assert_eq!(vcvars.config_args(), ["uwp"]);
+        assert_eq!(vcvars.vscmd().unwrap().app_platform, Some(AppPlatform::Uwp));
+
+        let mut vcvars = Vcvars::new().app_platform(AppPlatform::Desktop);
+        assert!(vcvars.config_args().is_empty());
+        assert_eq!(
+            vcvars.vscmd().unwrap().app_platform,
+            Some(AppPlatform::Desktop)
+        );
+
+        let env_map = [("VSCMD_ARG_APP_PLAT".to_owned(), "Desktop".to_owned())].into();
+        assert!(matches!(
+            Vcvars::new()
+                .app_platform(AppPlatform::Uwp)
+                .validate_env_map(&env_map),
+            Err(VcvarsError::AppPlatformNotApplied(AppPlatform::Uwp, platform)) if platform == "Desktop"
+        ));
+    }
+
+    #[test]
+    #[serial]
+    fn app_platform_onecore_synthetic() {
+        prepare_synthetic("app_platform_onecore", &[]);
+
+        let vcvars = Vcvars::new()
+            .spectre_libs(true)
+            .toolset_version("14.38")
+            .sdk_version(SdkVersion::new(10, 0, 22621, 0))
+            .app_platform(AppPlatform::OneCore);
+        assert_eq!(
+            vcvars.config_args(),
+            [
+                "onecore",
+                "10.0.22621.0",
+                "-vcvars_ver=14.38",
+                "-vcvars_spectre_libs=spectre"
+            ]
+        );
+
+        let vcvars = Vcvars::new().app_platform(AppPlatform::OneCore);
+        let env_map = |lib: &str| {
+            [
+                ("VSCMD_ARG_APP_PLAT".to_owned(), "OneCore".to_owned()),
+                ("LIB".to_owned(), lib.to_owned()),
+            ]
+            .into()
+        };
+        assert!(vcvars
+            .validate_env_map(&env_map(
+                r"C:\VS\VC\Tools\MSVC\14.38.33130\lib\onecore\x64;C:\um\x64"
+            ))
+            .is_ok());
+        assert!(matches!(
+            vcvars.validate_env_map(&env_map(r"C:\VS\VC\Tools\MSVC\14.38.33130\lib\x64")),
+            Err(VcvarsError::OneCoreLibsNotFound)
+        ));
+    }
+
+    #[test]
+    #[serial]
+    fn extra_vcvarsall_args_synthetic() {
+        prepare_synthetic("extra_vcvarsall_args", &[]);
+
+        let vcvars = Vcvars::new()
+            .spectre_libs(true)
+            .extra_vcvarsall_args(&["-vcvars_ver=14.29", "a&b"]);
+        assert_eq!(
+            vcvars.config_args(),
+            ["-vcvars_spectre_libs=spectre", "-vcvars_ver=14.29", "a&b"]
+        );
+        assert_ne!(
+            vcvars.cache_key(),
+            Vcvars::new().spectre_libs(true).cache_key()
+        );
+        assert_eq!(crate::escape_cmd_arg("a&b^c"), "a^&b^^c");
+    }
+
+    #[test]
+    fn arch_arg() {
+        assert_eq!(crate::arch_arg(Arch::X64, Arch::X64), Some("x64"));
+        assert_eq!(crate::arch_arg(Arch::X64, Arch::Arm64), Some("x64_arm64"));
+        assert_eq!(crate::arch_arg(Arch::X86, Arch::X64), Some("x86_x64"));
+        assert_eq!(crate::arch_arg(Arch::X86, Arch::X86), Some("x86"));
+        assert_eq!(crate::arch_arg(Arch::Arm64, Arch::Arm64), Some("arm64"));
+        assert_eq!(crate::arch_arg(Arch::Arm64, Arch::X64), Some("arm64_amd64"));
+        assert_eq!(crate::arch_arg(Arch::Arm64, Arch::X86), Some("arm64_x86"));
+        assert_eq!(crate::arch_arg(Arch::X64, Arch::Arm64Ec), Some("x64_arm64"));
+        assert_eq!(crate::arch_arg(Arch::Arm64, Arch::Arm64Ec), Some("arm64"));
+        assert_eq!(crate::arch_arg(Arch::Arm, Arch::X64), None);
+    }
+
+    #[test]
+    fn legacy_arch_arg() {
+        assert_eq!(crate::legacy_arch_arg(Arch::X86, Arch::X86), Some("x86"));
+        assert_eq!(crate::legacy_arch_arg(Arch::X64, Arch::X64), Some("amd64"));
+        assert_eq!(
+            crate::legacy_arch_arg(Arch::X86, Arch::X64),
+            Some("x86_amd64")
+        );
+        assert_eq!(
+            crate::legacy_arch_arg(Arch::X64, Arch::X86),
+            Some("amd64_x86")
+        );
+        assert_eq!(crate::legacy_arch_arg(Arch::X86, Arch::Arm), Some("arm"));
+        assert_eq!(
+            crate::legacy_arch_arg(Arch::X64, Arch::Arm),
+            Some("amd64_arm")
+        );
+        assert_eq!(crate::legacy_arch_arg(Arch::X64, Arch::Arm64), None);
+        assert_eq!(crate::legacy_arch_arg(Arch::Arm64, Arch::X64), None);
+    }
+
+    #[test]
+    #[serial]
+    fn reuse_ambient_env_synthetic() {
+        prepare();
+        prepare_synthetic("reuse_ambient_env", &[]);
+
+        let ambient_vars = [
+            (vars::VSCMD_VER, "17.9.2"),
+            (vars::VSCMD_ARG_TGT_ARCH, "x64"),
+            (vars::VSCMD_ARG_HOST_ARCH, "x64"),
+            (vars::VSINSTALLDIR, r"C:\VS\"),
+            (vars::VCINSTALLDIR, r"C:\VS\VC\"),
+            (vars::INCLUDE, "ambient_include"),
+        ];
+        let previous_values = ambient_vars.map(|(key, _)| (key, env::var_os(key)));
+        for (key, value) in ambient_vars {
+            env::set_var(key, value);
+        }
+
+        // No `cmd.exe` process is spawned.
+        let mut vcvars = Vcvars::new().target(Arch::X64);
+        assert_eq!(vcvars.cache_key(), "target=x64");
+        assert_eq!(vcvars.get(vars::INCLUDE).unwrap(), "ambient_include");
+        assert!(matches!(
+            vcvars.command_line(),
+            Err(VcvarsError::AmbientEnvReused)
+        ));
+        assert_eq!(vcvars.arch_arg().unwrap(), "x64");
+        assert_eq!(
+            vcvars.vcvarsall_path().unwrap(),
+            Path::new(r"C:\VS\VC\Auxiliary\Build\vcvarsall.bat")
+        );
+        assert_eq!(
+            vcvars.ensure_visual_studio_dir().unwrap(),
+            Path::new(r"C:\VS")
+        );
+        assert_eq!(vcvars.origin(vars::INCLUDE).unwrap(), VarOrigin::Unchanged);
+
+        // Mismatching requests fall through to running vcvars.
+        for mut vcvars in [
+            Vcvars::new().target(Arch::X86),
+            Vcvars::new().target(Arch::X64).host(Arch::X86),
+            Vcvars::new().target(Arch::X64).toolset_version("14.0"),
+            Vcvars::new().target(Arch::X64).reuse_ambient_env(false),
+        ] {
+            assert!(!vcvars
+                .get(vars::INCLUDE)
+                .is_ok_and(|include| include == "ambient_include"));
+            assert!(vcvars.command_line.is_some() || vcvars.env_map.is_none());
+        }
+
+        assert_eq!(
+            Vcvars::new().reuse_ambient_env(false).cache_key(),
+            "no_ambient_env"
+        );
+
+        for (key, value) in previous_values {
+            match value {
+                Some(value) => env::set_var(key, value),
+                None => env::remove_var(key),
+            }
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn vcvarsall_override_synthetic() {
+        prepare();
+        prepare_synthetic("vcvarsall_override", &[]);
+
+        let dir = PathBuf::from(env!("OUT_DIR"))
+            .join("synthetic")
+            .join("vcvarsall_override");
+        let vcvarsall_path = dir.join("custom_vcvarsall.cmd");
+        let new_vcvars = || {
+            Vcvars::new()
+                .target(Arch::X64)
+                .vswhere_path(dir.join("nonexistent_vswhere.exe"))
+        };
+
+        let mut non_utf8_file_name = OsString::from_wide(&[0xD800]);
+        non_utf8_file_name.push(".cmd");
+        let non_utf8_path = dir.join(non_utf8_file_name);
+        fs::write(&non_utf8_path, "").unwrap();
+
+        for (path, problem) in [
+            (vcvarsall_path.clone(), "doesn't exist"),
+            (dir.join("vcvarsall.txt"), "`.bat` or `.cmd`"),
+            (non_utf8_path, "UTF-8"),
+        ] {
+            env::set_var(crate::VCVARSALL_ENV_VAR, &path);
+            assert!(matches!(
+                new_vcvars().get(vars::INCLUDE),
+                Err(VcvarsError::InvalidVcvarsallOverride(invalid_path, message)) if invalid_path == path.to_string_lossy() && message.contains(problem)
+            ));
+        }
+
+        // Runs the file without `vswhere.exe`.
+        fs::write(&vcvarsall_path, "@set INCLUDE=override_include_%1\r\n").unwrap();
+        env::set_var(crate::VCVARSALL_ENV_VAR, &vcvarsall_path);
+        let mut vcvars = new_vcvars();
+        assert_eq!(
+            vcvars.config_key(),
+            format!("vcvarsall={} target=x64", vcvarsall_path.display())
+        );
+        assert_eq!(
+            vcvars.get_cached(vars::INCLUDE).unwrap(),
+            "override_include_x64"
+        );
+        assert_eq!(vcvars.vcvarsall_path().unwrap(), vcvarsall_path);
+        // The file doesn't set `VCToolsInstallDir`, which mustn't make it run again for other host tools.
+        assert_eq!(vcvars.arch_arg().unwrap(), "x64");
+        assert!(!vcvars.host_arch_fallback_applies(false).unwrap());
+
+        env::remove_var(crate::VCVARSALL_ENV_VAR);
+    }
+
+    #[test]
+    #[serial]
+    fn prefer_ambient_installation_synthetic() {
+        prepare();
+        prepare_synthetic("prefer_ambient_installation", &[]);
+
+        let visual_studio_dir = PathBuf::from(env!("OUT_DIR"))
+            .join("synthetic")
+            .join("prefer_ambient_installation")
+            .join("vs");
+        let vcvarsall_path = visual_studio_dir.join(r"VC\Auxiliary\Build\vcvarsall.bat");
+        fs::create_dir_all(vcvarsall_path.parent().unwrap()).unwrap();
+        fs::write(&vcvarsall_path, "@set INCLUDE=ambient_installation_%1\r\n").unwrap();
+        env::set_var(
+            vars::VSINSTALLDIR,
+            format!("{}\\", visual_studio_dir.display()),
+        );
+
+        // `vswhere.exe` isn't run.
+        let new_vcvars = || {
+            Vcvars::new()
+                .target(Arch::X64)
+                .vswhere_path(visual_studio_dir.join("nonexistent_vswhere.exe"))
+        };
+        let mut vcvars = new_vcvars();
+        assert_eq!(
+            vcvars.config_key(),
+            format!(
+                "ambient_installation={} target=x64",
+                visual_studio_dir.display()
+            )
+        );
+        assert_eq!(
+            vcvars.get(vars::INCLUDE).unwrap(),
+            "ambient_installation_x64"
+        );
+        assert!(!vcvars.host_arch_fallback_applies(false).unwrap());
+        assert!(vcvars.selection_log().unwrap().contains("VSINSTALLDIR"));
+
+        for mut vcvars in [
+            new_vcvars().prefer_ambient_installation(false),
+            new_vcvars().version_range("[17.0,18.0)"),
+        ] {
+            assert!(!vcvars.cache_key().contains("ambient_installation"));
+            assert!(matches!(
+                vcvars.get(vars::INCLUDE),
+                Err(VcvarsError::FileNotFound(path)) if path.ends_with("nonexistent_vswhere.exe")
+            ));
+        }
+
+        // Ignored if not valid UTF-8.
+        env::set_var(vars::VSINSTALLDIR, OsString::from_wide(&[0xD800]));
+        assert!(new_vcvars().ambient_installation_dir().is_none());
+
+        env::remove_var(vars::VSINSTALLDIR);
+    }
+
+    #[test]
+    fn ewdk_arch_arg() {
+        assert_eq!(crate::ewdk_arch_arg(Arch::X86), "x86");
+        assert_eq!(crate::ewdk_arch_arg(Arch::X64), "amd64");
+        assert_eq!(crate::ewdk_arch_arg(Arch::Arm64), "arm64");
+        assert_eq!(crate::ewdk_arch_arg(Arch::Arm64Ec), "arm64");
+    }
+
+    #[test]
+    #[serial]
+    fn from_ewdk_synthetic() {
+        let ewdk_root = PathBuf::from(env!("OUT_DIR"))
+            .join("synthetic")
+            .join("from_ewdk")
+            .join("ewdk");
+
+        prepare_synthetic("from_ewdk", &[]);
+        let mut vcvars = Vcvars::from_ewdk(&ewdk_root)
+            .target(Arch::X64)
+            .vswhere_path(ewdk_root.join("nonexistent_vswhere.exe"));
+        assert!(vcvars.cache_key().starts_with("ewdk="));
+        assert!(matches!(
+            vcvars.get_cached(vars::INCLUDE),
+            Err(VcvarsError::FileNotFound(path)) if path.ends_with(r"BuildEnv\SetupBuildEnv.cmd")
+        ));
+
+        // Runs `SetupBuildEnv.cmd` with the EWDK's architecture name, without `vswhere.exe`.
+        fs::create_dir_all(ewdk_root.join("BuildEnv")).unwrap();
+        fs::write(
+            ewdk_root.join(r"BuildEnv\SetupBuildEnv.cmd"),
+            "@set INCLUDE=ewdk_include_%1\r\n",
+        )
+        .unwrap();
+        assert_eq!(
+            vcvars.get_cached(vars::INCLUDE).unwrap(),
+            "ewdk_include_amd64"
+        );
+
+        assert!(matches!(
+            Vcvars::from_ewdk(&ewdk_root).toolset_version("14.29").validate(),
+            Err(VcvarsError::InvalidConfiguration(message)) if message.contains("toolset_version()")
+        ));
+        let non_utf8_root = ewdk_root.with_file_name(OsString::from_wide(&[0xD800]));
+        assert!(matches!(
+            Vcvars::from_ewdk(non_utf8_root).validate(),
+            Err(VcvarsError::InvalidConfiguration(message)) if message.contains("UTF-8")
+        ));
+    }
+
+    #[test]
+    #[serial]
+    fn allow_vs2015() {
+        prepare();
+
+        assert_eq!(Vcvars::new().allow_vs2015(true).cache_key(), "allow_vs2015");
+
+        let tools_dir = PathBuf::from(env!("OUT_DIR"))
+            .join("synthetic")
+            .join("allow_vs2015")
+            .join(r"Microsoft Visual Studio 14.0\Common7\Tools\");
+        let visual_studio_dir = tools_dir.parent().unwrap().parent().unwrap();
+        fs::create_dir_all(&tools_dir).unwrap();
+        fs::create_dir_all(visual_studio_dir.join("VC")).unwrap();
+        fs::write(visual_studio_dir.join(r"VC\vcvarsall.bat"), "").unwrap();
+
+        let previous_value = env::var_os("VS140COMNTOOLS");
+        env::set_var("VS140COMNTOOLS", &tools_dir);
+        let dir = crate::vs2015_dir();
+        match previous_value {
+            Some(value) => env::set_var("VS140COMNTOOLS", value),
+            None => env::remove_var("VS140COMNTOOLS"),
+        }
+        assert_eq!(dir.as_deref(), Some(visual_studio_dir));
+
+        // Newer installations take precedence.
+        let mut vcvars = Vcvars::new().allow_vs2015(true);
+        assert!(vcvars
+            .vcvarsall_path()
+            .unwrap()
+            .ends_with(r"VC\Auxiliary\Build\vcvarsall.bat"));
+
+        // The legacy `vcvarsall.bat` doesn't set `VCToolsInstallDir`, so missing native tools can't be told.
+        assert!(vcvars.host_arch_fallback_applies(false).unwrap());
+        assert!(!vcvars.host_arch_fallback_applies(true).unwrap());
+    }
+
+    #[test]
+    #[serial]
+    fn target() {
+        prepare();
+        env::remove_var("CARGO_CFG_TARGET_ARCH");
+
+        let mut vcvars = Vcvars::new();
+        assert!(matches!(
+            vcvars.get_all(),
+            Err(VcvarsError::MissingEnvVarDependency(name)) if name == "CARGO_CFG_TARGET_ARCH"
+        ));
+
+        let mut vcvars = Vcvars::new().target(Arch::X86);
+        assert_eq!(vcvars.cache_key(), "target=x86");
+        assert_eq!(vcvars.get_cached("VSCMD_ARG_TGT_ARCH").unwrap(), "x86");
+        assert!(!vcvars.is_arm64ec());
+
+        assert!(Vcvars::new().target(Arch::Arm64Ec).is_arm64ec());
+        env::set_var("CARGO_CFG_TARGET_ARCH", "arm64ec");
+        assert!(Vcvars::new().is_arm64ec());
+
+        prepare();
+    }
+
+    #[test]
+    #[serial]
+    fn host() {
+        prepare();
+
+        let mut vcvars = Vcvars::new().host(Arch::X86).target(Arch::X64);
+        assert_eq!(vcvars.cache_key(), "host=x86 target=x64");
+        assert_eq!(vcvars.get("VSCMD_ARG_HOST_ARCH").unwrap(), "x86");
+        assert_eq!(vcvars.get("VSCMD_ARG_TGT_ARCH").unwrap(), "x64");
+
+        let mut vcvars = Vcvars::new().host(Arch::Arm).target(Arch::X64);
+        assert!(matches!(
+            vcvars.get_all(),
+            Err(VcvarsError::UnsupportedArch(host, target)) if host == "arm" && target == "x64"
+        ));
+
+        let mut vcvars = Vcvars::new().host(Arch::Arm64Ec).target(Arch::Arm64);
+        assert!(matches!(
+            vcvars.get_all(),
+            Err(VcvarsError::UnsupportedArch(host, target)) if host == "arm64ec" && target == "arm64"
+        ));
+    }
+
+    #[test]
+    #[serial]
+    fn for_targets() {
+        prepare();
+
+        let environments = Vcvars::new().for_targets(&[Arch::X86, Arch::X64]).unwrap();
+        let target_arches = environments
+            .iter()
+            .map(VcvarsEnvironment::target_arch)
+            .collect::<Vec<_>>();
+        assert_eq!(target_arches, [Some(Arch::X86), Some(Arch::X64)]);
+
+        // A later build script run reads the cache files of the targets.
+        let mut vcvars = Vcvars::new().target(Arch::X86);
+        let cache_file = vcvars.ensure_cache_dir().unwrap().join("=env.txt");
+        let content = fs::read_to_string(&cache_file).unwrap();
+        fs::write(&cache_file, format!("{content}\nVCVARS_RS_TEST=cached")).unwrap();
+
+        let environments = Vcvars::new().for_targets(&[Arch::X86, Arch::X64]).unwrap();
+        assert_eq!(environments[0].get("VCVARS_RS_TEST").unwrap(), "cached");
+        assert!(!environments[1].contains("VCVARS_RS_TEST"));
+
+        fs::write(&cache_file, content).unwrap();
+    }
+
+    #[test]
+    #[serial]
+    fn use_vsdevcmd() {
+        prepare();
+
+        let mut vcvars = Vcvars::new()
+            .use_vsdevcmd(VsDevCmdOptions::default())
+            .app_platform(AppPlatform::Desktop);
+        assert_eq!(
+            vcvars.config_args(),
+            ["-no_logo", "-startdir=none", "-app_platform=Desktop"]
+        );
+        assert_eq!(
+            vcvars.cache_key(),
+            "vsdevcmd -no_logo -startdir=none -app_platform=Desktop"
+        );
+
+        assert!(vcvars.vcvarsall_path().unwrap().ends_with("VsDevCmd.bat"));
+        let command_line = vcvars.command_line().unwrap();
+        assert!(command_line.contains(" -arch="), "{command_line}");
+        assert!(vcvars.get("DEVENVDIR").is_ok());
+    }
+
+    #[test]
+    #[serial]
+    fn child_env() {
+        prepare();
+
+        let mut vcvars = Vcvars::new()
+            .child_env("VCVARS_RS_MARKER", "1")
+            .child_env("VCVARS_RS_OTHER_MARKER", "a b");
+        assert_eq!(
+            vcvars.cache_key(),
+            "VCVARS_RS_MARKER=1 VCVARS_RS_OTHER_MARKER=a b"
+        );
+        assert_eq!(vcvars.get("VCVARS_RS_MARKER").unwrap(), "1");
+        assert_eq!(vcvars.get("VCVARS_RS_OTHER_MARKER").unwrap(), "a b");
+        assert!(env::var_os("VCVARS_RS_MARKER").is_none());
+    }
+
+    #[test]
+    #[serial]
+    fn telemetry() {
+        prepare();
+
+        let mut vcvars = Vcvars::new();
+        assert_eq!(vcvars.get("VSCMD_SKIP_SENDTELEMETRY").unwrap(), "1");
+
+        if env::var_os("VSCMD_SKIP_SENDTELEMETRY").is_none() {
+            let mut vcvars = Vcvars::new().telemetry(true);
+            assert_eq!(vcvars.cache_key(), "telemetry");
+            assert!(vcvars
+                .get_opt("VSCMD_SKIP_SENDTELEMETRY")
+                .unwrap()
+                .is_none());
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn include_prereleases() {
+        prepare();
+
+        let vcvars = Vcvars::new()
+            .include_prereleases(false)
+            .not_vswhere_latest_but(&["-all"]);
+        assert_eq!(vcvars.cache_key(), "no_prereleases vswhere_args=-all");
+        let (_, command) = vcvars.vswhere_command().unwrap();
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            ["-products", "*", "-all"]
+        );
+
+        let (_, command) = Vcvars::new().vswhere_command().unwrap();
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            ["-prerelease", "-products", "*", "-latest"]
+        );
+
+        let mut vcvars = Vcvars::new().include_prereleases(false);
+        assert!(!vcvars.installed_toolsets().unwrap().is_empty());
+    }
+
+    #[test]
+    #[serial]
+    fn include_legacy() {
+        prepare();
+
+        let mut vcvars = Vcvars::new().include_legacy(true);
+        assert_eq!(vcvars.cache_key(), "legacy");
+        let (_, command) = vcvars.vswhere_command().unwrap();
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            ["-prerelease", "-legacy", "-latest"]
+        );
+        assert!(!vcvars.get("INCLUDE").unwrap().is_empty());
+
+        let component_ids = ["Microsoft.VisualStudio.Component.VC.Tools.x86.x64"];
+        assert!(matches!(
+            Vcvars::new()
+                .include_legacy(true)
+                .requires(&component_ids)
+                .validate(),
+            Err(VcvarsError::InvalidConfiguration(_))
+        ));
+        assert!(matches!(
+            Vcvars::list_installations(&VswhereOptions {
+                include_legacy: true,
+                products: vec!["*".to_owned()],
+                ..VswhereOptions::default()
+            }),
+            Err(VcvarsError::InvalidConfiguration(_))
+        ));
+    }
+
+    #[test]
+    #[serial]
+    fn installation() {
+        prepare();
+
+        let mut vcvars = Vcvars::new();
+        assert!(!format!("{vcvars:?}").contains("display_name"));
+        let installation = vcvars.installation().unwrap().clone();
+        assert!(format!("{vcvars:?}").contains(&format!("{:?}", installation.display_name)));
+        assert_eq!(
+            vcvars.installation_display_name().unwrap(),
+            installation.display_name
+        );
+        assert_eq!(vcvars.product_id().unwrap(), installation.product_id);
+        assert!(
+            vcvars.channel_id().unwrap().starts_with("VisualStudio."),
+            "{installation:?}"
+        );
+        assert!(installation.installation_path.is_dir());
+        assert!(installation
+            .product_id
+            .starts_with("Microsoft.VisualStudio.Product."));
+        assert!(Path::new(vcvars.get("VSINSTALLDIR").unwrap())
+            .starts_with(&installation.installation_path));
+    }
+
+    #[test]
+    #[serial]
+    fn installation_version() {
+        prepare();
+
+        let mut vcvars = Vcvars::new();
+        let version: VsInstallationVersion =
+            vcvars.installation_version().unwrap().parse().unwrap();
+        assert!(version >= VsInstallationVersion::new(15, 0, 0, 0));
+        assert_eq!(
+            version.vs_version().to_string(),
+            vcvars.get("VisualStudioVersion").unwrap()
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn list_installations() {
+        prepare();
+
+        let installations = Vcvars::list_installations(&VswhereOptions::default()).unwrap();
+        assert!(!installations.is_empty());
+        assert!(installations
+            .iter()
+            .any(|installation| installation.instance_id
+                == Vcvars::new().installation().unwrap().instance_id));
+
+        let installations = Vcvars::list_installations(&VswhereOptions {
+            version_range: Some("[1.0,2.0)".to_owned()),
+            ..VswhereOptions::default()
+        })
+        .unwrap();
+        assert!(installations.is_empty());
+    }
+
+    #[test]
+    #[serial]
+    fn select_installation() {
+        prepare();
+
+        let instance_id = Vcvars::new().installation().unwrap().instance_id.clone();
+
+        let mut vcvars = Vcvars::new()
+            .select_installation(|installation| installation.instance_id == instance_id);
+        assert_eq!(vcvars.installation().unwrap().instance_id, instance_id);
+        assert_eq!(vcvars.cache_key(), format!("instance={instance_id}"));
+        let (_, command) = vcvars.vswhere_command().unwrap();
+        assert_eq!(command.get_args().last().unwrap(), "-all");
+        assert!(!vcvars.get_cached("INCLUDE").unwrap().is_empty());
+
+        let mut vcvars = Vcvars::new().select_installation(|_| false);
+        assert!(matches!(
+            vcvars.installation(),
+            Err(VcvarsError::NoInstallationSelected(candidates)) if !candidates.is_empty()
+        ));
+    }
+
+    #[test]
+    #[serial]
+    fn selection_strategy() {
+        prepare();
+
+        let newest_toolset = Vcvars::list_installations(&VswhereOptions::default())
+            .unwrap()
+            .iter()
+            .filter(|installation| installation.is_complete)
+            .filter_map(|installation| {
+                crate::toolsets_in(&installation.installation_path)?
+                    .last()
+                    .copied()
+            })
+            .max()
+            .unwrap();
+
+        let mut vcvars = Vcvars::new().selection_strategy(SelectionStrategy::NewestToolset);
+        assert_eq!(
+            vcvars.installed_toolsets().unwrap().last(),
+            Some(&newest_toolset)
+        );
+        assert!(vcvars.cache_key().starts_with("newest_toolset instance="));
+        let log = vcvars.selection_log().unwrap();
+        assert!(
+            log.lines().last().unwrap().starts_with("selected `"),
+            "{log}"
+        );
+
+        assert!(Vcvars::new().selection_log().is_none());
+    }
+
+    #[test]
+    #[serial]
+    fn selection_strategies_synthetic() {
+        let installations_dir = PathBuf::from(env!("OUT_DIR"))
+            .join("synthetic")
+            .join("selection_strategies");
+        let installation = |name: &str, version: &str, is_prerelease: bool| InstallationInfo {
+            installation_path: installations_dir.join(name),
+            installation_version: version.to_owned(),
+            display_name: name.to_owned(),
+            product_id: "Microsoft.VisualStudio.Product.Community".to_owned(),
+            channel_id: String::new(),
+            instance_id: name.to_owned(),
+            is_prerelease,
+            product_line_version: String::new(),
+            is_complete: true,
+            is_legacy: false,
+        };
+        let installations = vec![
+            installation("vs2019", "16.11.34601.136", false),
+            installation("vs2022_preview", "17.10.34607.79", true),
+            installation("vs2022", "17.9.34607.119", false),
+            InstallationInfo {
+                is_complete: false,
+                ..installation("vs2022_broken", "17.11.0.0", false)
+            },
+        ];
+
+        if let Err(err) = fs::remove_dir_all(&installations_dir) {
+            assert!(matches!(err.kind(), io::ErrorKind::NotFound), "{err}");
+        }
+        for (name, toolset) in [
+            ("vs2019", "14.29.30133"),
+            ("vs2022_preview", "14.40.33807"),
+            ("vs2022", "14.39.33519"),
+            ("vs2022_broken", "14.41.34120"),
+        ] {
+            fs::create_dir_all(
+                installations_dir
+                    .join(name)
+                    .join(r"VC\Tools\MSVC")
+                    .join(toolset),
+            )
+            .unwrap();
+        }
+        fs::create_dir_all(installations_dir.join(r"vs2019\VC\Tools\MSVC\14.50.0")).unwrap();
+
+        let select = |strategy: SelectionStrategy| {
+            Vcvars::new()
+                .selection_strategy(strategy)
+                .select_installation_from(installations.clone())
+                .map(|installation| installation.instance_id)
+        };
+
+        assert_eq!(
+            select(SelectionStrategy::NewestInstallation).unwrap(),
+            "vs2022_preview"
+        );
+        assert_eq!(select(SelectionStrategy::NewestStable).unwrap(), "vs2022");
+        assert_eq!(select(SelectionStrategy::NewestToolset).unwrap(), "vs2019");
+        assert_eq!(
+            select(SelectionStrategy::Custom(Arc::new(|installations| {
+                installations
+                    .iter()
+                    .position(|installation| installation.installation_version.starts_with("16."))
+            })))
+            .unwrap(),
+            "vs2019"
+        );
+        assert!(matches!(
+            select(SelectionStrategy::Custom(Arc::new(|installations| Some(installations.len())))),
+            Err(VcvarsError::NoInstallationSelected(candidates)) if candidates.len() == 3
+        ));
+
+        let mut vcvars = Vcvars::new().selection_strategy(SelectionStrategy::NewestStable);
+        assert!(matches!(
+            vcvars.select_installation_from(vec![installation("vs2022_preview", "17.10.34607.79", true)]),
+            Err(VcvarsError::NoInstallationSelected(candidates)) if candidates == ["vs2022_preview (17.10.34607.79)"]
+        ));
+
+        for (strategy, key) in [
+            (SelectionStrategy::NewestInstallation, ""),
+            (SelectionStrategy::NewestStable, "newest_stable"),
+            (SelectionStrategy::NewestToolset, "newest_toolset"),
+            (
+                SelectionStrategy::Custom(Arc::new(|_| Some(0))),
+                "custom_selection",
+            ),
+        ] {
+            assert_eq!(Vcvars::new().selection_strategy(strategy).cache_key(), key);
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn vswhere_path() {
+        prepare();
+
+        let (default_path, _) = Vcvars::new().vswhere_command().unwrap();
+        assert!(default_path.ends_with(r"Microsoft Visual Studio\Installer\vswhere.exe"));
+
+        // Found via `ProgramW6432` or `ProgramFiles`.
+        let program_files_x86_dir = env::var_os("PROGRAMFILES(X86)").unwrap();
+        env::remove_var("PROGRAMFILES(X86)");
+        let result = Vcvars::new()
+            .vswhere_path(&default_path)
+            .installed_toolsets();
+        let fallback_result = Vcvars::new().installed_toolsets();
+        env::set_var("PROGRAMFILES(X86)", program_files_x86_dir);
+        assert!(!result.unwrap().is_empty());
+        assert!(!fallback_result.unwrap().is_empty());
+
+        let mut vcvars = Vcvars::new();
+        assert!(vcvars.used_vswhere_path().is_none());
+        vcvars.installation().unwrap();
+        assert_eq!(vcvars.used_vswhere_path(), Some(default_path.as_path()));
+
+        let nonexistent_path = default_path.with_file_name("nonexistent.exe");
+        assert!(matches!(
+            Vcvars::new().vswhere_path(&nonexistent_path).installed_toolsets(),
+            Err(VcvarsError::FileNotFound(path)) if Path::new(&path) == nonexistent_path
+        ));
+    }
+
+    #[test]
+    #[serial]
+    fn vswhere_failed() {
+        prepare();
+
+        let fake_vswhere_path = PathBuf::from(env!("OUT_DIR")).join("fake_vswhere.cmd");
+        fs::write(
+            &fake_vswhere_path,
+            "@echo Error: unknown argument 1>&2\r\n@echo Usage: vswhere.exe [options] 1>&2\r\n@exit /b 2\r\n",
+        )
+        .unwrap();
+
+        let mut vcvars = Vcvars::new().vswhere_path(&fake_vswhere_path);
+        let err = vcvars.installation().unwrap_err();
+        assert!(
+            matches!(
+                &err,
+                VcvarsError::VswhereFailed { exit_code: Some(2), stderr, args }
+                    if stderr.starts_with("Error: unknown argument") && args.ends_with("-format json -utf8")
+            ),
+            "{err}"
+        );
+        assert!(err.to_string().contains("exit code 2"));
+    }
+
+    #[test]
+    #[serial]
+    fn registry_fallback() {
+        prepare();
+
+        let hidden_vars = [
+            "PROGRAMFILES(X86)",
+            "PATH",
+            "ChocolateyInstall",
+            "ProgramData",
+        ]
+        .map(|var_name| (var_name, env::var_os(var_name)));
+        for (var_name, _) in &hidden_vars {
+            env::remove_var(var_name);
+        }
+        let mut vcvars = Vcvars::new();
+        let result = vcvars.get(vars::INCLUDE).map(str::to_owned);
+        let registry_installation = crate::registry::newest_vs7_installation();
+        for (var_name, value) in hidden_vars {
+            if let Some(value) = value {
+                env::set_var(var_name, value);
+            }
+        }
+
+        // Depends on whether Visual Studio 2017 or earlier registered itself.
+        match registry_installation {
+            Some((_, dir)) => {
+                assert!(!result.unwrap().is_empty());
+                assert!(vcvars.vcvarsall_path().unwrap().starts_with(dir));
+                assert!(vcvars.selection_log().unwrap().contains("registry"));
+            }
+            None => assert!(matches!(
+                result,
+                Err(VcvarsError::VisualStudioNotFound(probed_locations))
+                    if probed_locations.iter().any(|location| location.contains("vswhere"))
+                        && probed_locations.iter().any(|location| location.ends_with(r"SxS\VS7"))
+            )),
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn vswhere_too_old() {
+        prepare();
+
+        let fake_vswhere_path = PathBuf::from(env!("OUT_DIR")).join("fake_vswhere_old.cmd");
+        fs::write(
+            &fake_vswhere_path,
+            "@echo Visual Studio Locator version 2.5.2+gebb9f26a3d [query version 1.16.1247.518]\r\n@echo Copyright (C) Microsoft Corporation. All rights reserved.\r\n",
+        )
+        .unwrap();
+
+        let too_old = |result: Result<_, VcvarsError>| {
+            matches!(
+                result,
+                Err(VcvarsError::VswhereTooOld { found, required }) if found == "2.5.2" && required == "2.7.1"
+            )
+        };
+        assert!(too_old(
+            Vcvars::new()
+                .vswhere_path(&fake_vswhere_path)
+                .installation()
+                .map(|_| ())
+        ));
+        assert!(too_old(
+            Vcvars::new()
+                .vswhere_path(&fake_vswhere_path)
+                .vswhere_find("*")
+                .map(|_| ())
+        ));
+        assert!(too_old(
+            Vcvars::list_installations(&VswhereOptions {
+                vswhere_path: Some(fake_vswhere_path.clone()),
+                ..VswhereOptions::default()
+            })
+            .map(|_| ())
+        ));
+    }
+
+    #[test]
+    #[serial]
+    fn ambiguous_installation() {
+        prepare();
+
+        let fake_vswhere_path = PathBuf::from(env!("OUT_DIR")).join("fake_vswhere_ambiguous.cmd");
+        fs::write(
+            &fake_vswhere_path,
+            concat!(
+                r#"@echo [{"instanceId": "a", "installationPath": "C:\\VS\\A", "installationVersion": "16.11.34601.136"},"#,
+                "\r\n",
+                r#"@echo {"instanceId": "b", "installationPath": "C:\\VS\\B", "installationVersion": "17.8.34330.188"}]"#,
+                "\r\n",
+            ),
+        )
+        .unwrap();
+        let substitute_args = ["-all"];
+
+        let mut vcvars = Vcvars::new()
+            .vswhere_path(&fake_vswhere_path)
+            .not_vswhere_latest_but(&substitute_args);
+        assert_eq!(
+            vcvars.installation().unwrap().installation_path,
+            Path::new(r"C:\VS\A")
+        );
         assert!(
-            Regex::new(r"(?i)^[A-Z]:\\").unwrap().is_match(value)
-                && value.contains("Visual Studio")
-                && value.matches(';').count() >= 4,
-            "{value}"
+            vcvars
+                .selection_log()
+                .unwrap()
+                .ends_with("\nC:\\VS\\A\nC:\\VS\\B"),
+            "{:?}",
+            vcvars.selection_log()
         );
-        let followup_get_duration = start.elapsed();
 
+        let mut vcvars = Vcvars::new()
+            .vswhere_path(&fake_vswhere_path)
+            .not_vswhere_latest_but(&substitute_args)
+            .fail_on_ambiguous_installation(true);
+        assert!(matches!(
+            vcvars.installation(),
+            Err(VcvarsError::AmbiguousInstallation(paths)) if paths == [r"C:\VS\A", r"C:\VS\B"]
+        ));
+    }
+
+    #[test]
+    #[serial]
+    fn products() {
+        prepare();
+
+        let product_ids = [
+            "Microsoft.VisualStudio.Product.Community",
+            "Microsoft.VisualStudio.Product.BuildTools",
+        ];
+        let vcvars = Vcvars::new().products(&product_ids).version_range("17.0");
+        assert_eq!(
+            vcvars.cache_key(),
+            "products=Microsoft.VisualStudio.Product.Community,Microsoft.VisualStudio.Product.BuildTools version=17.0"
+        );
+        let (_, command) = vcvars.vswhere_command().unwrap();
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            [
+                "-prerelease",
+                "-products",
+                product_ids[0],
+                product_ids[1],
+                "-version",
+                "17.0",
+                "-latest"
+            ]
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn vswhere_query() {
+        prepare();
+
+        let args = |vcvars: &Vcvars| {
+            let (_, command) = vcvars.vswhere_command().unwrap();
+            command
+                .get_args()
+                .map(|arg| arg.to_str().unwrap().to_owned())
+                .collect::<Vec<_>>()
+        };
+
+        let vcvars = Vcvars::new().vswhere_query(VswhereQuery::new());
+        assert_eq!(args(&vcvars), ["-prerelease", "-products", "*", "-latest"]);
+        assert_eq!(vcvars.cache_key(), "");
+
+        let vcvars = Vcvars::new().vswhere_query(
+            VswhereQuery::new()
+                .all()
+                .prerelease(false)
+                .version_range("[15.0,16.0)"),
+        );
+        assert_eq!(
+            args(&vcvars),
+            ["-products", "*", "-version", "[15.0,16.0)", "-all"]
+        );
+        assert_eq!(
+            vcvars.cache_key(),
+            "no_prereleases version=[15.0,16.0) vswhere_args=-all"
+        );
+
+        let product_ids = ["Microsoft.VisualStudio.Product.BuildTools"];
+        let component_ids = ["Microsoft.VisualStudio.Component.VC.Tools.x86.x64"];
+        let vcvars = Vcvars::new().include_prereleases(false).vswhere_query(
+            VswhereQuery::new()
+                .products(&product_ids)
+                .requires(&component_ids)
+                .all()
+                .latest(),
+        );
+        assert_eq!(
+            args(&vcvars),
+            [
+                "-products",
+                product_ids[0],
+                "-requires",
+                component_ids[0],
+                "-latest"
+            ]
+        );
+
+        // Same as the raw arguments.
+        let substitute_args = ["-all"];
+        let raw_vcvars = Vcvars::new().not_vswhere_latest_but(&substitute_args);
+        let typed_vcvars = Vcvars::new().vswhere_query(VswhereQuery::new().all());
+        assert_eq!(args(&raw_vcvars), args(&typed_vcvars));
+        assert_eq!(raw_vcvars.cache_key(), typed_vcvars.cache_key());
+
+        // A query not setting `all()` keeps the raw arguments.
+        let substitute_args = ["-all", "-sort"];
+        let vcvars = Vcvars::new()
+            .not_vswhere_latest_but(&substitute_args)
+            .vswhere_query(VswhereQuery::new().version_range("[17.0,18.0)"));
+        assert_eq!(
+            args(&vcvars),
+            [
+                "-prerelease",
+                "-products",
+                "*",
+                "-version",
+                "[17.0,18.0)",
+                "-all",
+                "-sort"
+            ]
+        );
+        let vcvars = vcvars.vswhere_query(VswhereQuery::new().latest());
+        assert_eq!(args(&vcvars).last().unwrap(), "-latest");
+    }
+
+    #[test]
+    #[serial]
+    fn requires() {
+        prepare();
+
+        let component_ids = ["Microsoft.VisualStudio.Component.VC.Tools.x86.x64"];
+        let mut vcvars = Vcvars::new().requires(&component_ids).version_range("15.0");
+        let (_, command) = vcvars.vswhere_command().unwrap();
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            [
+                "-prerelease",
+                "-products",
+                "*",
+                "-requires",
+                component_ids[0],
+                "-version",
+                "15.0",
+                "-latest"
+            ]
+        );
+        assert!(!vcvars.installed_toolsets().unwrap().is_empty());
+
+        let component_ids = ["Nonexistent.Component"];
+        let mut vcvars = Vcvars::new().requires(&component_ids);
+        assert!(matches!(
+            vcvars.installed_toolsets(),
+            Err(VcvarsError::RequiredComponentsNotFound(ids)) if ids == component_ids
+        ));
+    }
+
+    #[test]
+    #[serial]
+    fn requires_any() {
+        prepare();
+
+        let component_ids = [
+            "Nonexistent.Component",
+            "Microsoft.VisualStudio.Component.VC.Tools.x86.x64",
+        ];
+        let mut vcvars = Vcvars::new().requires(&component_ids).requires_any(true);
+        assert_eq!(
+            vcvars.cache_key(),
+            "requires=Nonexistent.Component,Microsoft.VisualStudio.Component.VC.Tools.x86.x64 requires_any"
+        );
+        let (_, command) = vcvars.vswhere_command().unwrap();
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            [
+                "-prerelease",
+                "-products",
+                "*",
+                "-requires",
+                component_ids[0],
+                component_ids[1],
+                "-requiresAny",
+                "-latest"
+            ]
+        );
+        assert!(!vcvars.installed_toolsets().unwrap().is_empty());
+
+        assert!(matches!(
+            Vcvars::new().requires_any(true).validate(),
+            Err(VcvarsError::InvalidConfiguration(_))
+        ));
+        assert!(matches!(
+            Vcvars::new().requires(&[]).requires_any(true).validate(),
+            Err(VcvarsError::InvalidConfiguration(_))
+        ));
+    }
+
+    #[test]
+    fn is_version_range() {
+        for valid in [
+            "17.0",
+            "[16.0,18.0)",
+            "(16.0,]",
+            "[,17.0)",
+            "[17.8.34330.188]",
+        ] {
+            assert!(crate::is_version_range(valid), "{valid}");
+        }
+
+        for invalid in [
+            "",
+            "17.",
+            "x",
+            "[16.0,18.0",
+            "16.0,18.0)",
+            "[,]",
+            "[18.0,16.0)",
+            "(17.0)",
+            "[16.0;18.0)",
+        ] {
+            assert!(!crate::is_version_range(invalid), "{invalid}");
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn version_range() {
+        prepare();
+
+        let mut vcvars = Vcvars::new().version_range("[16.0,18.0");
+        assert!(matches!(
+            vcvars.validate(),
+            Err(VcvarsError::InvalidConfiguration(_))
+        ));
+        assert!(matches!(
+            vcvars.installed_toolsets(),
+            Err(VcvarsError::InvalidConfiguration(_))
+        ));
+
+        let mut vcvars = Vcvars::new().version_range("[1.0,2.0)");
+        assert_eq!(vcvars.cache_key(), "version=[1.0,2.0)");
+        assert!(matches!(
+            vcvars.installed_toolsets(),
+            Err(VcvarsError::NoVisualStudioFound(args, _)) if args.contains("-version [1.0,2.0)")
+        ));
+
+        let mut vcvars = Vcvars::new().version_range("15.0");
+        assert!(!vcvars.installed_toolsets().unwrap().is_empty());
+    }
+
+    #[test]
+    #[serial]
+    fn clean_env() {
+        prepare();
+
+        let user_profile_dir = PathBuf::from(env::var("USERPROFILE").unwrap().to_lowercase());
+
+        let mut vcvars = Vcvars::new().clean_env(true);
+        assert_eq!(vcvars.cache_key(), "clean_env");
+        let paths = vcvars.get_paths("PATH").unwrap();
         assert!(
-            followup_get_duration < initial_get_duration / 1000,
-            "getting 2nd env var should've been much faster than getting 1st"
+            paths
+                .iter()
+                .all(|path| !PathBuf::from(path.to_string_lossy().to_lowercase())
+                    .starts_with(&user_profile_dir)),
+            "{paths:?}"
+        );
+        assert!(vcvars.get_opt("USERPROFILE").unwrap().is_none());
+        assert!(!vcvars.path_additions().unwrap().is_empty());
+    }
+
+    #[test]
+    #[serial]
+    fn arch_arg_and_host_arch_candidates() {
+        prepare();
+
+        let mut vcvars = Vcvars::new().target(Arch::X64);
+        let arch_arg = vcvars.arch_arg().unwrap().to_owned();
+        assert!(
+            ["x64", "x86_x64"].contains(&arch_arg.as_str()),
+            "{arch_arg}"
+        );
+
+        if env::consts::ARCH == "x86_64" {
+            let mut vcvars = prepare_synthetic("host_arch_candidates", &[]);
+            assert_eq!(
+                vcvars.host_arch_candidates(Arch::X64, true).unwrap(),
+                [Arch::X64, Arch::X86]
+            );
+
+            let cache_dir = vcvars.arch_cache_dir(Arch::X64).unwrap();
+            fs::create_dir_all(&cache_dir).unwrap();
+            fs::write(cache_dir.join("=host_arch.txt"), "x86").unwrap();
+            assert_eq!(
+                vcvars.host_arch_candidates(Arch::X64, true).unwrap(),
+                [Arch::X86, Arch::X64]
+            );
+
+            let mut vcvars = Vcvars::new().host(Arch::X64);
+            assert_eq!(
+                vcvars.host_arch_candidates(Arch::X64, true).unwrap(),
+                [Arch::X64]
+            );
+
+            let mut vcvars = Vcvars::new().toolchain_preference(ToolchainPreference::PreferCross);
+            assert_eq!(
+                vcvars.host_arch_candidates(Arch::X64, true).unwrap(),
+                [Arch::X86, Arch::X64]
+            );
+            assert_eq!(
+                vcvars.host_arch_candidates(Arch::X64, false).unwrap(),
+                [Arch::X64]
+            );
+            assert_ne!(vcvars.cache_key(), "");
+
+            let mut vcvars =
+                Vcvars::new().toolchain_preference(ToolchainPreference::NativeHostOnly);
+            assert_eq!(
+                vcvars.host_arch_candidates(Arch::X64, true).unwrap(),
+                [Arch::X64]
+            );
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn host_tools_not_found_synthetic() {
+        prepare();
+        prepare_synthetic("host_tools_not_found", &[]);
+
+        // An installation whose toolset lacks the compiler for all host architectures.
+        let visual_studio_dir = PathBuf::from(env::var("OUT_DIR").unwrap()).join("vs");
+        let tools_dir = visual_studio_dir.join(r"VC\Tools\MSVC\14.40.33807");
+        let build_dir = visual_studio_dir.join(r"VC\Auxiliary\Build");
+        fs::create_dir_all(&tools_dir).unwrap();
+        fs::create_dir_all(&build_dir).unwrap();
+        fs::write(
+            build_dir.join("vcvarsall.bat"),
+            format!("@set VCToolsInstallDir={}\\\r\n", tools_dir.display()),
+        )
+        .unwrap();
+
+        let mut vcvars = Vcvars::new()
+            .with_installation_dir(&visual_studio_dir)
+            .target(Arch::X64);
+        let result = vcvars.get(vars::INCLUDE);
+        if env::consts::ARCH == "x86_64" {
+            assert!(
+                matches!(&result, Err(VcvarsError::HostToolsNotFound(probed_dirs)) if probed_dirs.len() == 2),
+                "{result:?}"
+            );
+        }
+
+        // The host architecture that worked is only cached with `get_cached()`.
+        let mut vcvars = prepare_synthetic("host_arch_cache_file", &[]);
+        vcvars.env_map = None;
+        vcvars.get(vars::INCLUDE).unwrap();
+        let host_arch_file = vcvars
+            .arch_cache_dir(Arch::from_rust_arch(env::consts::ARCH).unwrap())
+            .unwrap()
+            .join("=host_arch.txt");
+        assert!(!host_arch_file.exists());
+        Vcvars::new().get_cached(vars::INCLUDE).unwrap();
+        assert!(host_arch_file.is_file());
+
+        prepare();
+    }
+
+    #[test]
+    #[serial]
+    fn needed() {
+        prepare();
+        assert!(Vcvars::needed());
+
+        env::remove_var("CARGO_CFG_TARGET_ENV");
+        assert!(Vcvars::needed());
+
+        let mut vcvars = prepare_synthetic("needed", &[]);
+        vcvars.env_map = None;
+        env::set_var("CARGO_CFG_TARGET_ENV", "gnu");
+        assert!(!Vcvars::needed());
+        assert!(matches!(
+            vcvars.make_env_map(),
+            Err(VcvarsError::NonMsvcTarget(target_env)) if target_env == "gnu"
+        ));
+
+        prepare();
+    }
+
+    #[test]
+    #[serial]
+    fn raw_output() {
+        prepare();
+
+        let mut vcvars = Vcvars::new();
+        vcvars.get_all().unwrap();
+        assert!(vcvars.raw_output().is_none());
+
+        let mut vcvars = Vcvars::new().keep_raw_output(true);
+        vcvars.get_all().unwrap();
+        let raw_output = vcvars.raw_output().unwrap();
+        assert!(raw_output.contains("_unique_separator_by_rust_crate_that_utilizes_vcvars"));
+    }
+
+    #[test]
+    #[serial]
+    fn atlmfc_synthetic() {
+        let tools_dir = PathBuf::from(env!("OUT_DIR"))
+            .join("synthetic")
+            .join("atlmfc")
+            .join("tools");
+        let tools_dir_string = tools_dir.to_string_lossy().into_owned();
+        let env_map = [
+            ("VCToolsInstallDir", tools_dir_string.as_str()),
+            ("VSCMD_ARG_TGT_ARCH", "x64"),
+        ];
+
+        let mut vcvars = prepare_synthetic("atlmfc", &env_map);
+        assert_eq!(vcvars.atlmfc().unwrap(), None);
+        assert!(matches!(
+            vcvars.require_atlmfc(),
+            Err(VcvarsError::AtlMfcNotInstalled)
+        ));
+
+        let include = tools_dir.join(r"atlmfc\include");
+        let lib = tools_dir.join(r"atlmfc\lib\x64");
+        fs::create_dir_all(&include).unwrap();
+        fs::create_dir_all(&lib).unwrap();
+        assert_eq!(
+            vcvars.require_atlmfc().unwrap(),
+            AtlMfcDirs { include, lib }
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn vscmd() {
+        prepare();
+
+        let mut vcvars = Vcvars::new();
+        let vscmd = vcvars.vscmd().unwrap();
+        assert_eq!(vscmd.target_arch, Arch::from_rust_arch(env::consts::ARCH));
+        assert_eq!(vscmd.app_platform, Some(AppPlatform::Desktop));
+        assert!(vscmd.version.is_some(), "{vscmd:?}");
+    }
+
+    #[test]
+    #[serial]
+    fn vscmd_synthetic() {
+        let mut vcvars = prepare_synthetic("vscmd", &[("VSCMD_ARG_TGT_ARCH", "arm64")]);
+        assert_eq!(
+            vcvars.vscmd().unwrap(),
+            VsCmdArgs {
+                host_arch: None,
+                target_arch: Some(Arch::Arm64),
+                app_platform: None,
+                version: None,
+            }
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn crt_redist_dlls() {
+        prepare();
+
+        let mut vcvars = Vcvars::new();
+        for (kind, dll_name) in [
+            (CrtRedistKind::Release, "vcruntime140.dll"),
+            (CrtRedistKind::Debug, "vcruntime140d.dll"),
+        ] {
+            let dlls = vcvars.crt_redist_dlls(kind).unwrap();
+            assert!(dlls.iter().any(|dll| dll.ends_with(dll_name)), "{dlls:?}");
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn dia_sdk_synthetic() {
+        let visual_studio_dir = PathBuf::from(env!("OUT_DIR"))
+            .join("synthetic")
+            .join("dia_sdk")
+            .join("vs");
+
+        let mut vcvars = prepare_synthetic("dia_sdk", &[("VSCMD_ARG_TGT_ARCH", "x64")]);
+        vcvars.visual_studio_dir = Some(visual_studio_dir.clone());
+        assert_eq!(vcvars.dia_sdk().unwrap(), None);
+
+        let root = visual_studio_dir.join("DIA SDK");
+        fs::create_dir_all(&root).unwrap();
+        assert_eq!(
+            vcvars.dia_sdk().unwrap(),
+            Some(DiaSdk {
+                include: root.join("include"),
+                lib: root.join(r"lib\amd64"),
+                msdia_dll: root.join(r"bin\amd64\msdia140.dll"),
+                root,
+            })
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn clang_cl() {
+        prepare();
+
+        let mut vcvars = Vcvars::new();
+        if let Some(path) = vcvars.clang_cl().unwrap() {
+            let output = Command::new(&path).arg("--version").output().unwrap();
+            assert!(output.status.success(), "{path:?}");
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn bundled_cmake_and_ninja_synthetic() {
+        let visual_studio_dir = PathBuf::from(env!("OUT_DIR"))
+            .join("synthetic")
+            .join("bundled_cmake")
+            .join("vs");
+
+        let mut vcvars = prepare_synthetic("bundled_cmake", &[]);
+        vcvars.visual_studio_dir = Some(visual_studio_dir.clone());
+        assert_eq!(vcvars.bundled_cmake().unwrap(), None);
+        assert_eq!(vcvars.bundled_ninja().unwrap(), None);
+
+        let cmake_dir = visual_studio_dir.join(r"Common7\IDE\CommonExtensions\Microsoft\CMake");
+        let cmake_path = cmake_dir.join(r"CMake\bin\cmake.exe");
+        let ninja_path = cmake_dir.join(r"Ninja\ninja.exe");
+        for path in [&cmake_path, &ninja_path] {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        assert_eq!(vcvars.bundled_cmake().unwrap(), Some(cmake_path));
+        assert_eq!(vcvars.bundled_ninja().unwrap(), Some(ninja_path));
+    }
+
+    #[test]
+    #[serial]
+    fn bundled_vcpkg_synthetic() {
+        let visual_studio_dir = PathBuf::from(env!("OUT_DIR"))
+            .join("synthetic")
+            .join("bundled_vcpkg")
+            .join("vs");
+        let vcpkg_root = visual_studio_dir.join(r"VC\vcpkg");
+        fs::create_dir_all(&vcpkg_root).unwrap();
+
+        let mut vcvars = prepare_synthetic("bundled_vcpkg", &[("PATH", r"C:\a")]);
+        vcvars.visual_studio_dir = Some(visual_studio_dir.clone());
+        assert_eq!(vcvars.bundled_vcpkg().unwrap(), None);
+        assert_eq!(vcvars.bundled_vcpkg_env().unwrap(), None);
+
+        // The absence was cached.
+        fs::write(vcpkg_root.join("vcpkg.exe"), "").unwrap();
+        assert_eq!(vcvars.bundled_vcpkg().unwrap(), None);
+
+        let mut vcvars = prepare_synthetic("bundled_vcpkg", &[("PATH", r"C:\a")]);
+        vcvars.visual_studio_dir = Some(visual_studio_dir);
+        fs::create_dir_all(&vcpkg_root).unwrap();
+        fs::write(vcpkg_root.join("vcpkg.exe"), "").unwrap();
+        assert_eq!(vcvars.bundled_vcpkg().unwrap(), Some(vcpkg_root.clone()));
+
+        let vcpkg_root = vcpkg_root.to_string_lossy().into_owned();
+        assert_eq!(
+            vcvars.bundled_vcpkg_env().unwrap(),
+            Some(vec![
+                ("VCPKG_ROOT".to_owned(), vcpkg_root.clone()),
+                ("PATH".to_owned(), format!(r"{vcpkg_root};C:\a")),
+            ])
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn asan_runtime_synthetic() {
+        let tools_dir = PathBuf::from(env!("OUT_DIR"))
+            .join("synthetic")
+            .join("asan_runtime")
+            .join(r"MSVC\14.38.33130");
+        let tools_dir_string = tools_dir.to_string_lossy().into_owned();
+        let env_map = [
+            ("VCToolsInstallDir", tools_dir_string.as_str()),
+            ("VSCMD_ARG_HOST_ARCH", "x64"),
+            ("VSCMD_ARG_TGT_ARCH", "x64"),
+        ];
+
+        let mut vcvars = prepare_synthetic("asan_runtime", &env_map);
+        assert_eq!(vcvars.asan_runtime().unwrap(), None);
+
+        let lib_dir = tools_dir.join(r"lib\x64");
+        let dll_dir = tools_dir.join(r"bin\Hostx64\x64");
+        let lib = lib_dir.join("clang_rt.asan_dynamic-x86_64.lib");
+        let dll = dll_dir.join("clang_rt.asan_dynamic-x86_64.dll");
+        for path in [&lib, &dll, &lib_dir.join("libcmt.lib")] {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+
+        assert_eq!(
+            vcvars.asan_runtime().unwrap(),
+            Some(AsanRuntime {
+                lib_dir,
+                libs: vec![lib],
+                dll_dir,
+                dlls: vec![dll],
+            })
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn find_header() {
+        prepare();
+
+        let mut vcvars = Vcvars::new();
+        for name in ["Windows.h", "winrt/base.h"] {
+            let path = vcvars.find_header(name).unwrap().unwrap();
+            assert!(path.is_file(), "{path:?}");
+        }
+        assert_eq!(vcvars.find_header("nonexistent-header.h").unwrap(), None);
+    }
+
+    #[test]
+    #[serial]
+    fn find_lib_synthetic() {
+        let lib_dir = PathBuf::from(env!("OUT_DIR"))
+            .join("synthetic")
+            .join("find_lib")
+            .join("lib");
+        let lib = format!(r"C:\vcvars-rs-test\missing;{}", lib_dir.to_string_lossy());
+
+        let mut vcvars = prepare_synthetic("find_lib", &[("LIB", &lib)]);
+        let lib_path = lib_dir.join("D3D12.lib");
+        fs::create_dir_all(&lib_dir).unwrap();
+        fs::write(&lib_path, "").unwrap();
+
+        assert_eq!(vcvars.find_lib("d3d12.lib").unwrap(), Some(lib_path));
+        assert_eq!(vcvars.find_lib("nonexistent.lib").unwrap(), None);
+    }
+
+    #[test]
+    #[serial]
+    fn well_known_vars() {
+        prepare();
+
+        let mut vcvars = Vcvars::new();
+        for var in WellKnownVar::ALL {
+            assert!(vcvars.contains(var).unwrap(), "{var:?}");
+            assert_eq!(
+                vcvars.get(var).unwrap().to_owned(),
+                vcvars.get(var.name()).unwrap()
+            );
+        }
+        assert!(vcvars.get(vars::VC_TOOLS_INSTALL_DIR).is_ok());
+    }
+
+    #[test]
+    #[serial]
+    fn resolve_synthetic() {
+        let mut vcvars = prepare_synthetic("resolve", &[("LIB", r"C:\lib")]);
+        vcvars.visual_studio_dir = Some(PathBuf::from(r"C:\VS"));
+
+        let environment = vcvars.resolve().unwrap();
+        assert_eq!(
+            environment.get_paths("LIB").unwrap(),
+            [PathBuf::from(r"C:\lib")]
         );
+        assert_eq!(environment.visual_studio_dir(), Some(Path::new(r"C:\VS")));
+        assert_eq!(environment.target_arch(), None);
+    }
+
+    #[test]
+    #[serial]
+    fn include_paths_and_lib_paths() {
+        prepare();
+
+        assert!(!crate::include_paths().unwrap().is_empty());
+        assert!(!crate::lib_paths().unwrap().is_empty());
     }
 
     #[test]
@@ -0,0 +1,172 @@
+use std::{
+    path::{Path, PathBuf},
+    ptr,
+};
+
+use crate::vswhere;
+
+/// The registry key under `HKEY_LOCAL_MACHINE` in which Visual Studio 2017 and earlier register their installation directories, with the version like `15.0` as the value name.
+pub(crate) const VS7_KEY: &str = r"SOFTWARE\Microsoft\VisualStudio\SxS\VS7";
+
+/// `HKEY_LOCAL_MACHINE`, which is `0x80000002` sign-extended.
+const HKEY_LOCAL_MACHINE: isize = -0x7FFF_FFFE;
+const KEY_READ: u32 = 0x2_0019;
+const KEY_WOW64_64KEY: u32 = 0x0100;
+const KEY_WOW64_32KEY: u32 = 0x0200;
+const REG_SZ: u32 = 1;
+const ERROR_SUCCESS: i32 = 0;
+const ERROR_MORE_DATA: i32 = 234;
+
+pub(crate) fn vs7_key_names() -> [String; 2] {
+    //! Returns the names of the registry keys read by `newest_vs7_installation()`, in the order they're read, for errors.
+
+    [
+        format!(
+            r"HKEY_LOCAL_MACHINE\{}",
+            VS7_KEY.replace(r"SOFTWARE\", r"SOFTWARE\WOW6432Node\")
+        ),
+        format!(r"HKEY_LOCAL_MACHINE\{VS7_KEY}"),
+    ]
+}
+
+pub(crate) fn newest_vs7_installation() -> Option<(String, PathBuf)> {
+    //! Returns the version and directory of the installation with the highest version registered in the `VS7` key, whose directory exists. Both the 32-bit registry view (`WOW6432Node`), where the Visual Studio Installer writes, and the 64-bit one are read.
+
+    [KEY_WOW64_32KEY, KEY_WOW64_64KEY]
+        .into_iter()
+        .flat_map(|view_flag| string_values(VS7_KEY, view_flag))
+        .filter(|(_, dir)| Path::new(dir).is_dir())
+        .max_by_key(|(version, _)| vswhere::version_parts(version))
+        .map(|(version, dir)| (version, PathBuf::from(dir.trim_end_matches('\\'))))
+}
+
+fn string_values(key_path: &str, view_flag: u32) -> Vec<(String, String)> {
+    //! Returns the names and data of the `REG_SZ` values of the key under `HKEY_LOCAL_MACHINE` in the registry view selected by `view_flag`. Returns an empty `Vec` if the key doesn't exist or can't be read.
+
+    let key_path = key_path.encode_utf16().chain([0]).collect::<Vec<_>>();
+    let mut key = 0;
+
+    // SAFETY: `key_path` is null-terminated, and `key` receives the opened key.
+    let status = unsafe {
+        RegOpenKeyExW(
+            HKEY_LOCAL_MACHINE,
+            key_path.as_ptr(),
+            0,
+            KEY_READ | view_flag,
+            ptr::from_mut(&mut key),
+        )
+    };
+    if status != ERROR_SUCCESS {
+        return Vec::new();
+    }
+
+    let mut values = Vec::new();
+    let mut name = vec![0_u16; 16_384]; // Maximum value name length plus null terminator.
+    let mut data = vec![0_u8; 1024];
+    let mut index = 0;
+
+    loop {
+        let mut name_len = u32::try_from(name.len()).unwrap();
+        let mut data_len = u32::try_from(data.len()).unwrap();
+        let mut value_type = 0;
+
+        // SAFETY: The buffers are valid for the lengths passed, which are updated to the lengths written.
+        let status = unsafe {
+            RegEnumValueW(
+                key,
+                index,
+                name.as_mut_ptr(),
+                ptr::from_mut(&mut name_len),
+                ptr::null_mut(),
+                ptr::from_mut(&mut value_type),
+                data.as_mut_ptr(),
+                ptr::from_mut(&mut data_len),
+            )
+        };
+
+        match status {
+            ERROR_SUCCESS => {}
+            ERROR_MORE_DATA => {
+                data.resize(data_len as usize, 0);
+                continue;
+            }
+            _ => break, // Like `ERROR_NO_MORE_ITEMS`.
+        }
+
+        if value_type == REG_SZ {
+            let data = data[..data_len as usize]
+                .chunks_exact(2)
+                .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+                .take_while(|&code_unit| code_unit != 0)
+                .collect::<Vec<_>>();
+
+            values.push((
+                String::from_utf16_lossy(&name[..name_len as usize]),
+                String::from_utf16_lossy(&data),
+            ));
+        }
+
+        index += 1;
+    }
+
+    // SAFETY: `key` was opened above.
+    unsafe {
+        RegCloseKey(key);
+    }
+
+    values
+}
+
+#[link(name = "advapi32")]
+extern "system" {
+    fn RegOpenKeyExW(
+        key: isize,
+        sub_key: *const u16,
+        options: u32,
+        desired_access: u32,
+        result: *mut isize,
+    ) -> i32;
+    fn RegEnumValueW(
+        key: isize,
+        index: u32,
+        value_name: *mut u16,
+        value_name_len: *mut u32,
+        reserved: *mut u32,
+        value_type: *mut u32,
+        data: *mut u8,
+        data_len: *mut u32,
+    ) -> i32;
+    fn RegCloseKey(key: isize) -> i32;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{newest_vs7_installation, string_values, KEY_WOW64_64KEY};
+
+    #[test]
+    fn string_values_of_existing_key() {
+        let values = string_values(
+            r"SOFTWARE\Microsoft\Windows NT\CurrentVersion",
+            KEY_WOW64_64KEY,
+        );
+        assert!(
+            values
+                .iter()
+                .any(|(name, data)| name == "ProductName" && data.starts_with("Windows")),
+            "{values:?}"
+        );
+
+        assert!(string_values(r"SOFTWARE\vcvars-rs\Nonexistent", KEY_WOW64_64KEY).is_empty());
+    }
+
+    #[test]
+    fn vs7_installation() {
+        // Only checkable where Visual Studio 2017 or earlier registered itself.
+        let Some((version, dir)) = newest_vs7_installation() else {
+            return;
+        };
+
+        assert!(version.parse::<f32>().is_ok(), "{version}");
+        assert!(dir.is_dir(), "{dir:?}");
+    }
+}
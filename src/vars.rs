@@ -0,0 +1,128 @@
+//! Names of well-known variables in the vcvars environment, to avoid typos.
+//!
+//! The constants can be passed wherever a variable name is expected. [`WellKnownVar`] additionally documents what the variables contain.
+
+pub const INCLUDE: &str = "INCLUDE";
+pub const LIB: &str = "LIB";
+pub const LIBPATH: &str = "LIBPATH";
+pub const PATH: &str = "PATH";
+pub const VCINSTALLDIR: &str = "VCINSTALLDIR";
+pub const VC_TOOLS_INSTALL_DIR: &str = "VCToolsInstallDir";
+pub const VC_TOOLS_REDIST_DIR: &str = "VCToolsRedistDir";
+pub const VC_TOOLS_VERSION: &str = "VCToolsVersion";
+pub const VISUAL_STUDIO_VERSION: &str = "VisualStudioVersion";
+pub const VSINSTALLDIR: &str = "VSINSTALLDIR";
+pub const VSCMD_ARG_HOST_ARCH: &str = "VSCMD_ARG_HOST_ARCH";
+pub const VSCMD_ARG_TGT_ARCH: &str = "VSCMD_ARG_TGT_ARCH";
+pub const VSCMD_ARG_APP_PLAT: &str = "VSCMD_ARG_app_plat";
+pub const VSCMD_VER: &str = "VSCMD_VER";
+pub const WINDOWS_SDK_DIR: &str = "WindowsSdkDir";
+pub const WINDOWS_SDK_VERSION: &str = "WindowsSDKVersion";
+pub const WINDOWS_SDK_BIN_PATH: &str = "WindowsSdkBinPath";
+pub const WINDOWS_SDK_VER_BIN_PATH: &str = "WindowsSdkVerBinPath";
+pub const UNIVERSAL_CRT_SDK_DIR: &str = "UniversalCRTSdkDir";
+pub const UCRT_VERSION: &str = "UCRTVersion";
+
+/// A well-known variable in the vcvars environment. Unless noted otherwise, the variables are set by vcvars of Visual Studio 2017 and later.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WellKnownVar {
+    /// Semicolon-separated include directories of MSVC, the Windows SDK and the Universal CRT.
+    Include,
+    /// Semicolon-separated library directories searched by the linker.
+    Lib,
+    /// Semicolon-separated directories searched for metadata files like `.winmd` files referenced by C++/WinRT and C++/CLI code.
+    Libpath,
+    /// Semicolon-separated executable search paths, with vcvars' tool directories prepended.
+    Path,
+    /// The `VC` directory of the Visual Studio installation, with trailing backslash.
+    VcInstallDir,
+    /// The directory of the MSVC toolset version in use, like `...\VC\Tools\MSVC\14.38.33130\`.
+    VcToolsInstallDir,
+    /// The directory of the redistributable files matching the toolset, like `...\VC\Redist\MSVC\14.38.33130\`.
+    VcToolsRedistDir,
+    /// The MSVC toolset version, like `14.38.33130`. Parsed by `Vcvars::vc_tools_version()`.
+    VcToolsVersion,
+    /// The Visual Studio version, like `17.0`. Parsed by `Vcvars::visual_studio_version()`.
+    VisualStudioVersion,
+    /// The Visual Studio installation directory, with trailing backslash.
+    VsInstallDir,
+    /// The host architecture, like `x64`.
+    VscmdArgHostArch,
+    /// The target architecture, like `x64`.
+    VscmdArgTgtArch,
+    /// The application platform, like `Desktop`.
+    VscmdArgAppPlat,
+    /// The version of the developer command scripts, like `17.8.3`.
+    VscmdVer,
+    /// The Windows SDK root directory, like `C:\Program Files (x86)\Windows Kits\10\`. Absent if no Windows SDK is installed.
+    WindowsSdkDir,
+    /// The Windows SDK version, with trailing backslash, like `10.0.22621.0\`. Absent if no Windows SDK is installed.
+    WindowsSdkVersion,
+    /// The `bin` directory of the Windows SDK root directory. Absent if no Windows SDK is installed.
+    WindowsSdkBinPath,
+    /// The `bin` directory of the Windows SDK version in use. Absent with SDKs older than 10.0.15063.0.
+    WindowsSdkVerBinPath,
+    /// The root directory of the SDK containing the Universal CRT. Absent if the Universal CRT isn't installed.
+    UniversalCrtSdkDir,
+    /// The Universal CRT version, like `10.0.22621.0`. Absent if the Universal CRT isn't installed.
+    UcrtVersion,
+}
+
+impl WellKnownVar {
+    pub const ALL: [Self; 20] = [
+        Self::Include,
+        Self::Lib,
+        Self::Libpath,
+        Self::Path,
+        Self::VcInstallDir,
+        Self::VcToolsInstallDir,
+        Self::VcToolsRedistDir,
+        Self::VcToolsVersion,
+        Self::VisualStudioVersion,
+        Self::VsInstallDir,
+        Self::VscmdArgHostArch,
+        Self::VscmdArgTgtArch,
+        Self::VscmdArgAppPlat,
+        Self::VscmdVer,
+        Self::WindowsSdkDir,
+        Self::WindowsSdkVersion,
+        Self::WindowsSdkBinPath,
+        Self::WindowsSdkVerBinPath,
+        Self::UniversalCrtSdkDir,
+        Self::UcrtVersion,
+    ];
+
+    pub fn name(self) -> &'static str {
+        #![must_use]
+        //! Returns the variable name as set by vcvars.
+
+        match self {
+            Self::Include => INCLUDE,
+            Self::Lib => LIB,
+            Self::Libpath => LIBPATH,
+            Self::Path => PATH,
+            Self::VcInstallDir => VCINSTALLDIR,
+            Self::VcToolsInstallDir => VC_TOOLS_INSTALL_DIR,
+            Self::VcToolsRedistDir => VC_TOOLS_REDIST_DIR,
+            Self::VcToolsVersion => VC_TOOLS_VERSION,
+            Self::VisualStudioVersion => VISUAL_STUDIO_VERSION,
+            Self::VsInstallDir => VSINSTALLDIR,
+            Self::VscmdArgHostArch => VSCMD_ARG_HOST_ARCH,
+            Self::VscmdArgTgtArch => VSCMD_ARG_TGT_ARCH,
+            Self::VscmdArgAppPlat => VSCMD_ARG_APP_PLAT,
+            Self::VscmdVer => VSCMD_VER,
+            Self::WindowsSdkDir => WINDOWS_SDK_DIR,
+            Self::WindowsSdkVersion => WINDOWS_SDK_VERSION,
+            Self::WindowsSdkBinPath => WINDOWS_SDK_BIN_PATH,
+            Self::WindowsSdkVerBinPath => WINDOWS_SDK_VER_BIN_PATH,
+            Self::UniversalCrtSdkDir => UNIVERSAL_CRT_SDK_DIR,
+            Self::UcrtVersion => UCRT_VERSION,
+        }
+    }
+}
+
+impl AsRef<str> for WellKnownVar {
+    fn as_ref(&self) -> &str {
+        self.name()
+    }
+}
@@ -0,0 +1,455 @@
+use std::{fmt, str::FromStr};
+
+use crate::VcvarsError;
+
+/// An MSVC toolset version like `14.38.33130`, as found in the `VCToolsVersion` variable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct VcToolsVersion {
+    major: u32,
+    minor: u32,
+    patch: u32,
+}
+
+impl VcToolsVersion {
+    pub fn new(major: u32, minor: u32, patch: u32) -> Self {
+        #![must_use]
+
+        Self {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    pub fn major(&self) -> u32 {
+        #![must_use]
+
+        self.major
+    }
+
+    pub fn minor(&self) -> u32 {
+        #![must_use]
+
+        self.minor
+    }
+
+    pub fn patch(&self) -> u32 {
+        #![must_use]
+
+        self.patch
+    }
+}
+
+impl FromStr for VcToolsVersion {
+    type Err = VcvarsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        //! Parses a version like `14.38.33130`. Surrounding whitespace and a trailing backslash are ignored.
+
+        match parse_numbers(s.trim().trim_end_matches('\\')) {
+            Some([major, minor, patch]) => Ok(Self::new(major, minor, patch)),
+            None => Err(VcvarsError::InvalidVersion(s.to_owned())),
+        }
+    }
+}
+
+impl fmt::Display for VcToolsVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// A Visual Studio version like `17.0`, as found in the `VisualStudioVersion` variable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct VsVersion {
+    major: u32,
+    minor: u32,
+}
+
+impl VsVersion {
+    pub fn new(major: u32, minor: u32) -> Self {
+        #![must_use]
+
+        Self { major, minor }
+    }
+
+    pub fn major(&self) -> u32 {
+        #![must_use]
+
+        self.major
+    }
+
+    pub fn minor(&self) -> u32 {
+        #![must_use]
+
+        self.minor
+    }
+
+    pub fn year(&self) -> Option<u32> {
+        #![must_use]
+        //! Returns the year in the product name, like 2022 for Visual Studio 2022. Returns `None` for major versions unknown to this crate version.
+
+        match self.major {
+            14 => Some(2015),
+            15 => Some(2017),
+            16 => Some(2019),
+            17 => Some(2022),
+            18 => Some(2026),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for VsVersion {
+    type Err = VcvarsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        //! Parses a version like `17.0`. Surrounding whitespace is ignored.
+
+        match parse_numbers(s.trim()) {
+            Some([major, minor]) => Ok(Self::new(major, minor)),
+            None => Err(VcvarsError::InvalidVersion(s.to_owned())),
+        }
+    }
+}
+
+impl fmt::Display for VsVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+/// A Visual Studio installation version like `17.8.34330.188`, as reported by `vswhere.exe` in the `installationVersion` property, or `14.0` for Visual Studio 2015 and earlier.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct VsInstallationVersion {
+    major: u32,
+    minor: u32,
+    build: u32,
+    revision: u32,
+}
+
+impl VsInstallationVersion {
+    pub fn new(major: u32, minor: u32, build: u32, revision: u32) -> Self {
+        #![must_use]
+
+        Self {
+            major,
+            minor,
+            build,
+            revision,
+        }
+    }
+
+    pub fn major(&self) -> u32 {
+        #![must_use]
+
+        self.major
+    }
+
+    pub fn minor(&self) -> u32 {
+        #![must_use]
+
+        self.minor
+    }
+
+    pub fn build(&self) -> u32 {
+        #![must_use]
+
+        self.build
+    }
+
+    pub fn revision(&self) -> u32 {
+        #![must_use]
+
+        self.revision
+    }
+
+    pub fn vs_version(&self) -> VsVersion {
+        #![must_use]
+        //! Returns the major version with minor version `0`, like `17.0`, as in the `VisualStudioVersion` variable.
+
+        VsVersion::new(self.major, 0)
+    }
+}
+
+impl FromStr for VsInstallationVersion {
+    type Err = VcvarsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        //! Parses a version like `17.8.34330.188` or `14.0`. Surrounding whitespace is ignored.
+
+        let trimmed = s.trim();
+
+        if let Some([major, minor, build, revision]) = parse_numbers(trimmed) {
+            Ok(Self::new(major, minor, build, revision))
+        } else if let Some([major, minor]) = parse_numbers(trimmed) {
+            Ok(Self::new(major, minor, 0, 0))
+        } else {
+            Err(VcvarsError::InvalidVersion(s.to_owned()))
+        }
+    }
+}
+
+impl fmt::Display for VsInstallationVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}.{}.{}.{}",
+            self.major, self.minor, self.build, self.revision
+        )
+    }
+}
+
+/// A version of the Visual Studio developer command scripts like `17.8.3`, as found in the `VSCMD_VER` variable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct VsCmdVersion {
+    major: u32,
+    minor: u32,
+    patch: u32,
+}
+
+impl VsCmdVersion {
+    pub fn new(major: u32, minor: u32, patch: u32) -> Self {
+        #![must_use]
+
+        Self {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    pub fn major(&self) -> u32 {
+        #![must_use]
+
+        self.major
+    }
+
+    pub fn minor(&self) -> u32 {
+        #![must_use]
+
+        self.minor
+    }
+
+    pub fn patch(&self) -> u32 {
+        #![must_use]
+
+        self.patch
+    }
+}
+
+impl FromStr for VsCmdVersion {
+    type Err = VcvarsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        //! Parses a version like `17.8.3`. Surrounding whitespace and a prerelease suffix like `-pre.1.0` are ignored.
+
+        let trimmed = s.trim();
+        let without_suffix = trimmed
+            .split_once('-')
+            .map_or(trimmed, |(version, _)| version);
+
+        match parse_numbers(without_suffix) {
+            Some([major, minor, patch]) => Ok(Self::new(major, minor, patch)),
+            None => Err(VcvarsError::InvalidVersion(s.to_owned())),
+        }
+    }
+}
+
+impl fmt::Display for VsCmdVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// A Windows SDK version like `10.0.22621.0`, as found in the `WindowsSDKVersion` variable, or `8.1` for the Windows 8.1 SDK.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SdkVersion {
+    major: u32,
+    minor: u32,
+    build: u32,
+    revision: u32,
+}
+
+impl SdkVersion {
+    pub fn new(major: u32, minor: u32, build: u32, revision: u32) -> Self {
+        #![must_use]
+
+        Self {
+            major,
+            minor,
+            build,
+            revision,
+        }
+    }
+
+    pub fn major(&self) -> u32 {
+        #![must_use]
+
+        self.major
+    }
+
+    pub fn minor(&self) -> u32 {
+        #![must_use]
+
+        self.minor
+    }
+
+    pub fn build(&self) -> u32 {
+        #![must_use]
+
+        self.build
+    }
+
+    pub fn revision(&self) -> u32 {
+        #![must_use]
+
+        self.revision
+    }
+}
+
+impl FromStr for SdkVersion {
+    type Err = VcvarsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        //! Parses a version like `10.0.22621.0` or `8.1`. Surrounding whitespace and a trailing backslash are ignored.
+
+        let trimmed = s.trim().trim_end_matches('\\');
+
+        if let Some([major, minor, build, revision]) = parse_numbers(trimmed) {
+            Ok(Self::new(major, minor, build, revision))
+        } else if let Some([major @ ..=9, minor]) = parse_numbers(trimmed) {
+            Ok(Self::new(major, minor, 0, 0))
+        } else {
+            Err(VcvarsError::InvalidVersion(s.to_owned()))
+        }
+    }
+}
+
+impl fmt::Display for SdkVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        //! Formats SDKs before Windows 10 with two numbers, like `8.1`, as expected by vcvars.
+
+        if self.major < 10 {
+            write!(f, "{}.{}", self.major, self.minor)
+        } else {
+            write!(
+                f,
+                "{}.{}.{}.{}",
+                self.major, self.minor, self.build, self.revision
+            )
+        }
+    }
+}
+
+fn parse_numbers<const N: usize>(s: &str) -> Option<[u32; N]> {
+    //! Parses exactly `N` dot-separated numbers.
+
+    let mut numbers = [0; N];
+    let mut parts = s.split('.');
+
+    for number in &mut numbers {
+        *number = parts.next()?.parse().ok()?;
+    }
+
+    if parts.next().is_some() {
+        return None;
+    }
+
+    Some(numbers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SdkVersion, VcToolsVersion, VsCmdVersion, VsInstallationVersion, VsVersion};
+
+    #[test]
+    fn vc_tools_version() {
+        let version: VcToolsVersion = "14.38.33130\r\n".parse().unwrap();
+        assert_eq!(version, VcToolsVersion::new(14, 38, 33130));
+        assert_eq!(
+            (version.major(), version.minor(), version.patch()),
+            (14, 38, 33130)
+        );
+        assert_eq!(version.to_string(), "14.38.33130");
+
+        assert!(version >= VcToolsVersion::new(14, 30, 0));
+        assert!(version < VcToolsVersion::new(14, 100, 0));
+        assert!(VcToolsVersion::new(14, 9, 0) < VcToolsVersion::new(14, 10, 0));
+
+        for invalid in ["", "14.38", "14.38.x", "14.38.33130.1"] {
+            assert!(invalid.parse::<VcToolsVersion>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn vs_version() {
+        let version: VsVersion = " 16.0\r".parse().unwrap();
+        assert_eq!(version, VsVersion::new(16, 0));
+        assert_eq!(version.to_string(), "16.0");
+        assert_eq!(version.year(), Some(2019));
+        assert_eq!(VsVersion::new(17, 0).year(), Some(2022));
+        assert_eq!(VsVersion::new(99, 0).year(), None);
+
+        assert!(version < VsVersion::new(17, 0));
+        assert!(VsVersion::new(17, 0) >= VsVersion::new(16, 11));
+
+        for invalid in ["", "16", "16.x", "16.0.1"] {
+            assert!(invalid.parse::<VsVersion>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn vs_installation_version() {
+        let version: VsInstallationVersion = "17.8.34330.188\r\n".parse().unwrap();
+        assert_eq!(version, VsInstallationVersion::new(17, 8, 34330, 188));
+        assert_eq!(version.build(), 34330);
+        assert_eq!(version.to_string(), "17.8.34330.188");
+        assert_eq!(version.vs_version(), VsVersion::new(17, 0));
+
+        assert!(version > "17.8.34322.80".parse().unwrap());
+        assert!(version < "17.10.34902.65".parse().unwrap());
+        assert_eq!(
+            "14.0".parse::<VsInstallationVersion>().unwrap(),
+            VsInstallationVersion::new(14, 0, 0, 0)
+        );
+
+        for invalid in ["", "17", "17.8.34330", "17.8.x.188"] {
+            assert!(
+                invalid.parse::<VsInstallationVersion>().is_err(),
+                "{invalid}"
+            );
+        }
+    }
+
+    #[test]
+    fn vs_cmd_version() {
+        let version: VsCmdVersion = "17.9.0-pre.1.0".parse().unwrap();
+        assert_eq!(version, VsCmdVersion::new(17, 9, 0));
+        assert_eq!(version.to_string(), "17.9.0");
+        assert!(version > "17.8.3".parse().unwrap());
+        assert!("17.8".parse::<VsCmdVersion>().is_err());
+    }
+
+    #[test]
+    fn sdk_version() {
+        let version: SdkVersion = "10.0.22621.0\\".parse().unwrap();
+        assert_eq!(version, SdkVersion::new(10, 0, 22621, 0));
+        assert_eq!(version.build(), 22621);
+        assert_eq!(version.to_string(), "10.0.22621.0");
+
+        let legacy_version: SdkVersion = "8.1".parse().unwrap();
+        assert_eq!(legacy_version.to_string(), "8.1");
+        assert!(legacy_version < SdkVersion::new(10, 0, 10240, 0));
+        assert!(version > "10.0.19041.0".parse().unwrap());
+
+        for invalid in ["", "10.0", "10.0.22621", "10.0.x.0"] {
+            assert!(invalid.parse::<SdkVersion>().is_err(), "{invalid}");
+        }
+    }
+}
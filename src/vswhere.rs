@@ -0,0 +1,967 @@
+use std::{
+    env,
+    ffi::OsString,
+    iter::Peekable,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+    ptr, str,
+    str::Chars,
+    sync::{Mutex, PoisonError},
+};
+
+use itertools::Itertools;
+
+use crate::VcvarsError;
+
+/// The oldest `vswhere.exe` version supporting the arguments passed by this crate. Version 2.7.1 introduced `-utf8`, which is passed with all queries. `-find` is older.
+const MIN_VERSION: &str = "2.7.1";
+
+/// The versions of the `vswhere.exe` files checked by `check_version()` in this process, if they could be determined.
+static CHECKED_VERSIONS: Mutex<Vec<(PathBuf, Option<String>)>> = Mutex::new(Vec::new());
+
+/// Options for selecting Visual Studio installations with `vswhere.exe`. See `Vcvars::list_installations()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VswhereOptions {
+    /// The path of `vswhere.exe` to use instead of the one in the Visual Studio Installer directory, like with `Vcvars::vswhere_path()`.
+    pub vswhere_path: Option<PathBuf>,
+    /// Whether to pass `-prerelease`, which includes installations like Visual Studio Preview. Defaults to `true`.
+    pub include_prereleases: bool,
+    /// Whether to pass `-legacy`, which includes Visual Studio 2015 and earlier installations, like with `Vcvars::include_legacy()`. Can't be combined with `products` and `requires`.
+    pub include_legacy: bool,
+    /// The product IDs to pass with `-products`. If empty, which is the default, `*` is passed.
+    pub products: Vec<String>,
+    /// The component IDs to pass with `-requires`, if any.
+    pub requires: Vec<String>,
+    /// Whether to pass `-requiresAny`, so that installations with any instead of all of the components in `requires` are returned.
+    pub requires_any: bool,
+    /// The version range to pass with `-version`, like `[16.0,18.0)`, if any.
+    pub version_range: Option<String>,
+}
+
+impl Default for VswhereOptions {
+    fn default() -> Self {
+        Self {
+            vswhere_path: None,
+            include_prereleases: true,
+            include_legacy: false,
+            products: Vec::new(),
+            requires: Vec::new(),
+            requires_any: false,
+            version_range: None,
+        }
+    }
+}
+
+/// A typed description of the arguments `vswhere.exe` is run with to select the Visual Studio installation vcvars is run from. See `Vcvars::vswhere_query()`. Options not set keep the ones set with the corresponding `Vcvars` methods.
+///
+/// ```ignore
+/// let mut vcvars = Vcvars::new().vswhere_query(
+///     VswhereQuery::new()
+///         .version_range("[16.0,17.0)")
+///         .products(&["Microsoft.VisualStudio.Product.BuildTools"])
+///         .prerelease(false),
+/// );
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct VswhereQuery<'a> {
+    pub(crate) all: Option<bool>,
+    pub(crate) prerelease: Option<bool>,
+    pub(crate) products: Option<&'a [&'a str]>,
+    pub(crate) requires: Option<&'a [&'a str]>,
+    pub(crate) version_range: Option<&'a str>,
+}
+
+impl<'a> VswhereQuery<'a> {
+    pub fn new() -> Self {
+        #![must_use]
+
+        Self::default()
+    }
+
+    pub fn latest(mut self) -> Self {
+        #![must_use]
+        //! Passes `-latest`, so only the installation with the highest version is returned. This is the default, unless `Vcvars::not_vswhere_latest_but()` was used, whose arguments this discards.
+
+        self.all = Some(false);
+
+        self
+    }
+
+    pub fn all(mut self) -> Self {
+        #![must_use]
+        //! Passes `-all` instead of `-latest`, so all installations are returned, including incomplete ones. With `Vcvars`, the first one is then used, unless `Vcvars::fail_on_ambiguous_installation()` is set.
+
+        self.all = Some(true);
+
+        self
+    }
+
+    pub fn version_range(mut self, range: &'a str) -> Self {
+        #![must_use]
+        //! Passes `-version <range>`, like with `Vcvars::version_range()`.
+
+        self.version_range = Some(range);
+
+        self
+    }
+
+    pub fn products(mut self, product_ids: &'a [&'a str]) -> Self {
+        #![must_use]
+        //! Passes `-products <product_ids>` instead of `-products *`, like with `Vcvars::products()`.
+
+        self.products = Some(product_ids);
+
+        self
+    }
+
+    pub fn requires(mut self, component_ids: &'a [&'a str]) -> Self {
+        #![must_use]
+        //! Passes `-requires <component_ids>`, like with `Vcvars::requires()`.
+
+        self.requires = Some(component_ids);
+
+        self
+    }
+
+    pub fn prerelease(mut self, enabled: bool) -> Self {
+        #![must_use]
+        //! Sets whether to pass `-prerelease`, like with `Vcvars::include_prereleases()`.
+
+        self.prerelease = Some(enabled);
+
+        self
+    }
+}
+
+/// Information about a Visual Studio installation, as output by `vswhere.exe`. See `Vcvars::installation()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct InstallationInfo {
+    /// From `installationPath`, like `C:\Program Files\Microsoft Visual Studio\2022\Community`.
+    pub installation_path: PathBuf,
+    /// From `installationVersion`, like `17.8.34330.188`.
+    pub installation_version: String,
+    /// From `displayName`, like `Visual Studio Community 2022`.
+    pub display_name: String,
+    /// From `productId`, like `Microsoft.VisualStudio.Product.Community`.
+    pub product_id: String,
+    /// From `channelId`, like `VisualStudio.17.Release` or `VisualStudio.17.Preview`.
+    pub channel_id: String,
+    /// From `instanceId`, like `1a2b3c4d`.
+    pub instance_id: String,
+    /// From `isPrerelease`.
+    pub is_prerelease: bool,
+    /// From `catalog.productLineVersion`, like `2022`.
+    pub product_line_version: String,
+    /// From `isComplete`. `false` for installations whose setup is pending or failed, which are only returned by `Vcvars::list_installations()`.
+    pub is_complete: bool,
+    /// Whether this is a Visual Studio 2015 or earlier installation found due to `-legacy`. For these, `vswhere.exe` only outputs the installation path and version and the instance ID, so the other fields are empty or `false`.
+    pub is_legacy: bool,
+}
+
+pub(crate) fn vswhere_path(override_path: Option<&Path>) -> Result<PathBuf, VcvarsError> {
+    //! Returns the path of `vswhere.exe`, which must exist. `override_path` is used instead of searching, if given. Otherwise, the Visual Studio Installer directories of `installer_vswhere_paths_from()` are tried first, then the `PATH` directories and the Chocolatey shim directory, for standalone copies installed with package managers.
+
+    if let Some(path) = override_path {
+        if !path.is_file() {
+            return Err(VcvarsError::FileNotFound(
+                path.to_string_lossy().into_owned(),
+            ));
+        }
+
+        return Ok(path.to_owned());
+    }
+
+    let mut probed_paths = Vec::new();
+
+    // Note: Microsoft says about the `vswhere` path: "This is a fixed location that will be maintained." (https://github.com/Microsoft/vswhere/wiki/Installing)
+
+    for vswhere_path in installer_vswhere_paths_from(|name| env::var_os(name)) {
+        if vswhere_path.is_file() {
+            return Ok(vswhere_path);
+        }
+
+        probed_paths.push(vswhere_path.to_string_lossy().into_owned());
+    }
+
+    // Search standalone copies.
+    let mut dirs: Vec<PathBuf> = env::var_os("PATH")
+        .map(|path| env::split_paths(&path).collect())
+        .unwrap_or_default();
+    if let Some(chocolatey_dir) = env::var_os("ChocolateyInstall")
+        .map(PathBuf::from)
+        .or_else(|| Some(PathBuf::from(env::var_os("ProgramData")?).join("chocolatey")))
+    {
+        dirs.push(chocolatey_dir.join("bin"));
+    }
+
+    let extensions = env::var("PATHEXT").unwrap_or_else(|_| ".COM;.EXE;.BAT;.CMD".to_owned());
+
+    for dir in dirs.iter().filter(|dir| dir.is_absolute()) {
+        for extension in extensions
+            .split(';')
+            .filter(|extension| !extension.is_empty())
+        {
+            let vswhere_path = dir.join(format!("vswhere{extension}"));
+            if vswhere_path.is_file() {
+                return Ok(vswhere_path);
+            }
+        }
+
+        probed_paths.push(format!("{}.*", dir.join("vswhere").to_string_lossy()));
+    }
+
+    Err(VcvarsError::VswhereNotFound(probed_paths))
+}
+
+fn installer_vswhere_paths_from(var: impl Fn(&str) -> Option<OsString>) -> Vec<PathBuf> {
+    //! Returns the candidate paths of `vswhere.exe` in the Visual Studio Installer directory, derived from the environment variables read with `var`. The machine-wide directory in the candidates of `program_files_x86_dirs_from()` comes first, then the per-user directory in `LOCALAPPDATA`, used by per-user installations.
+
+    program_files_x86_dirs_from(&var)
+        .into_iter()
+        .map(|program_files_x86_dir| {
+            program_files_x86_dir.join(r"Microsoft Visual Studio\Installer\vswhere.exe")
+        })
+        .chain(var("LOCALAPPDATA").map(|local_app_data_dir| {
+            PathBuf::from(local_app_data_dir).join(r"Microsoft\VisualStudio\Installer\vswhere.exe")
+        }))
+        .collect()
+}
+
+pub(crate) fn program_files_x86_dirs() -> Vec<PathBuf> {
+    //! Returns the candidates for the 32-bit program files directory, like `C:\Program Files (x86)`, in which the Visual Studio Installer and older Visual Studio versions are installed. See `program_files_x86_dirs_from()`.
+
+    program_files_x86_dirs_from(|name| env::var_os(name))
+}
+
+fn program_files_x86_dirs_from(var: impl Fn(&str) -> Option<OsString>) -> Vec<PathBuf> {
+    //! Returns the candidates for the 32-bit program files directory, derived from the environment variables read with `var`, since `PROGRAMFILES(X86)` is missing in some environments, like stripped service environments. In order, these are `PROGRAMFILES(X86)`, `ProgramW6432` with ` (x86)` appended, `ProgramFiles`, with ` (x86)` appended unless already present, and, on 32-bit Windows, as is, and finally `C:\Program Files (x86)`. Duplicates are removed case-insensitively.
+
+    const X86_SUFFIX: &str = " (x86)";
+
+    let with_x86_suffix = |dir: OsString| {
+        let mut dir = dir.to_string_lossy().trim_end_matches('\\').to_owned();
+        if !dir.to_lowercase().ends_with(X86_SUFFIX) {
+            dir.push_str(X86_SUFFIX);
+        }
+        PathBuf::from(dir)
+    };
+
+    let mut dirs = Vec::new();
+    dirs.extend(var("PROGRAMFILES(X86)").map(PathBuf::from));
+    dirs.extend(var("ProgramW6432").map(with_x86_suffix));
+    if let Some(program_files_dir) = var("ProgramFiles") {
+        dirs.push(with_x86_suffix(program_files_dir.clone()));
+
+        // Only 64-bit Windows sets `ProgramW6432`.
+        if var("ProgramW6432").is_none() {
+            dirs.push(PathBuf::from(program_files_dir));
+        }
+    }
+    dirs.push(PathBuf::from(r"C:\Program Files (x86)"));
+
+    dirs.into_iter()
+        .unique_by(|dir| dir.to_string_lossy().trim_end_matches('\\').to_lowercase())
+        .collect()
+}
+
+pub(crate) fn check_version(vswhere_path: &Path) -> Result<(), VcvarsError> {
+    //! Fails with `VcvarsError::VswhereTooOld` if `vswhere.exe` is older than `MIN_VERSION`, since old versions print their usage text for unknown arguments instead of failing clearly. The version is determined from the header printed with `-help`, only once per process and path. If it can't be determined, the check passes.
+
+    let mut checked_versions = CHECKED_VERSIONS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+
+    let version = if let Some((_, version)) = checked_versions
+        .iter()
+        .find(|(path, _)| path == vswhere_path)
+    {
+        version.clone()
+    } else {
+        let output = match Command::new(vswhere_path).arg("-help").output() {
+            Ok(output) => output,
+            Err(err) => {
+                return Err(VcvarsError::CouldntRun(
+                    vswhere_path.to_string_lossy().into_owned(),
+                    err,
+                ));
+            }
+        };
+
+        let version = version_from_header(&String::from_utf8_lossy(&output.stdout));
+        checked_versions.push((vswhere_path.to_owned(), version.clone()));
+
+        version
+    };
+
+    if let Some(version) =
+        version.filter(|version| version_parts(version) < version_parts(MIN_VERSION))
+    {
+        return Err(VcvarsError::VswhereTooOld {
+            found: version,
+            required: MIN_VERSION.to_owned(),
+        });
+    }
+
+    Ok(())
+}
+
+fn version_from_header(stdout: &str) -> Option<String> {
+    //! Extracts the version from the first line `vswhere.exe` prints without `-nologo`, like `Visual Studio Locator version 2.8.4+ff1bd2e9e5 [query version 3.8.2133.33218]`, whose wording may be localized.
+
+    let version = stdout
+        .lines()
+        .next()?
+        .split_whitespace()
+        .find(|word| word.starts_with(|char: char| char.is_ascii_digit()))?
+        .split('+')
+        .next()?;
+
+    version
+        .split('.')
+        .all(|part| part.parse::<u32>().is_ok())
+        .then(|| version.to_owned())
+}
+
+pub(crate) fn query_installations(
+    vswhere_path: &Path,
+    mut command: Command,
+) -> Result<Vec<InstallationInfo>, VcvarsError> {
+    //! Runs the `vswhere.exe` command with `-format json` and parses the output with `installations_from_output()`.
+
+    command.args(["-format", "json", "-utf8"]);
+    let args = command
+        .get_args()
+        .map(|arg| arg.to_string_lossy())
+        .join(" ");
+
+    let output = match command.output() {
+        Ok(output) => output,
+        Err(err) => {
+            return Err(VcvarsError::CouldntRun(
+                vswhere_path.to_string_lossy().into_owned(),
+                err,
+            ));
+        }
+    };
+
+    installations_from_output(&args, &output)
+}
+
+fn installations_from_output(
+    args: &str,
+    output: &Output,
+) -> Result<Vec<InstallationInfo>, VcvarsError> {
+    //! Parses the output of `vswhere.exe` run with `args`. If no installation was found, which `vswhere.exe` may indicate by empty output, returns `VcvarsError::NoVisualStudioFound`.
+
+    check_output(args, output)?;
+
+    let stdout = decode_output(&output.stdout)?;
+
+    let installations = if stdout.trim().is_empty() {
+        Vec::new()
+    } else {
+        parse_installations(&stdout)?
+    };
+
+    if installations.is_empty() {
+        return Err(VcvarsError::NoVisualStudioFound(
+            args.to_owned(),
+            decode_output_lossy(&output.stderr).trim().to_owned(),
+        ));
+    }
+
+    Ok(installations)
+}
+
+pub(crate) fn check_output(args: &str, output: &Output) -> Result<(), VcvarsError> {
+    //! Returns `VcvarsError::VswhereFailed` if `vswhere.exe` run with `args` exited with a non-zero exit code, or if its stdout output is empty, but its stderr output isn't, like when it printed its usage text due to an invalid argument.
+
+    let stderr = decode_output_lossy(&output.stderr).trim().to_owned();
+
+    if !output.status.success() || (output.stdout.trim_ascii().is_empty() && !stderr.is_empty()) {
+        return Err(VcvarsError::VswhereFailed {
+            exit_code: output.status.code(),
+            stderr,
+            args: args.to_owned(),
+        });
+    }
+
+    Ok(())
+}
+
+pub(crate) fn decode_output(bytes: &[u8]) -> Result<String, VcvarsError> {
+    //! Decodes output of `vswhere.exe` as UTF-8, which it should output due to `-utf8`, or else in the console's OEM code page, in case `vswhere.exe` ignored the switch. If both fail, returns `VcvarsError::UndecodableVswhereOutput`.
+
+    // SAFETY: The function has no preconditions.
+    let oem_code_page = unsafe { GetOEMCP() };
+
+    decode_with_code_page(bytes, oem_code_page)
+}
+
+fn decode_output_lossy(bytes: &[u8]) -> String {
+    //! Like `decode_output()`, but replaces invalid UTF-8 sequences, if both encodings fail. For stderr output, which is only used in errors.
+
+    decode_output(bytes).unwrap_or_else(|_| String::from_utf8_lossy(bytes).into_owned())
+}
+
+fn decode_with_code_page(bytes: &[u8], code_page: u32) -> Result<String, VcvarsError> {
+    //! Decodes `bytes` as UTF-8, or else in the Windows code page `code_page`, strictly.
+
+    const MB_ERR_INVALID_CHARS: u32 = 0x8;
+
+    if let Ok(string) = str::from_utf8(bytes) {
+        return Ok(string.to_owned());
+    }
+
+    let undecodable = || VcvarsError::UndecodableVswhereOutput(bytes.to_owned());
+    let Ok(bytes_len) = i32::try_from(bytes.len()) else {
+        return Err(undecodable());
+    };
+
+    // SAFETY: The input pointer and length denote `bytes`. With a null output pointer and length `0`, the required output length is returned.
+    let wide_len = unsafe {
+        MultiByteToWideChar(
+            code_page,
+            MB_ERR_INVALID_CHARS,
+            bytes.as_ptr(),
+            bytes_len,
+            ptr::null_mut(),
+            0,
+        )
+    };
+    let Ok(wide_len_usize) = usize::try_from(wide_len) else {
+        return Err(undecodable());
+    };
+    if wide_len_usize == 0 {
+        return Err(undecodable());
+    }
+
+    let mut wide = vec![0_u16; wide_len_usize];
+    // SAFETY: The pointers and lengths denote `bytes` and `wide`.
+    let written_len = unsafe {
+        MultiByteToWideChar(
+            code_page,
+            MB_ERR_INVALID_CHARS,
+            bytes.as_ptr(),
+            bytes_len,
+            wide.as_mut_ptr(),
+            wide_len,
+        )
+    };
+    if written_len != wide_len {
+        return Err(undecodable());
+    }
+
+    String::from_utf16(&wide).map_err(|_| undecodable())
+}
+
+#[link(name = "kernel32")]
+extern "system" {
+    fn GetOEMCP() -> u32;
+    fn MultiByteToWideChar(
+        code_page: u32,
+        flags: u32,
+        multi_byte_str: *const u8,
+        multi_byte_len: i32,
+        wide_char_str: *mut u16,
+        wide_char_len: i32,
+    ) -> i32;
+}
+
+pub(crate) fn version_parts(version: &str) -> Vec<u32> {
+    //! Splits a version like `17.8.34330.188` into its numbers for comparison. Invalid parts are treated as `0`.
+
+    version
+        .split('.')
+        .map(|part| part.parse().unwrap_or(0))
+        .collect()
+}
+
+pub(crate) fn parse_installations(json: &str) -> Result<Vec<InstallationInfo>, VcvarsError> {
+    //! Parses the output of `vswhere.exe -format json`, an array of installation objects.
+
+    let invalid = || VcvarsError::InvalidVswhereOutput(json.to_owned());
+
+    let Some(Json::Array(installations)) = Json::parse(json) else {
+        return Err(invalid());
+    };
+
+    installations
+        .iter()
+        .map(|installation| {
+            let string = |key: &str| Some(installation.get(key)?.as_str()?.to_owned());
+
+            // Legacy installations lack most fields.
+            let is_legacy = installation.get("productId").is_none();
+            let optional_string = |key: &str| {
+                if is_legacy {
+                    Some(String::new())
+                } else {
+                    string(key)
+                }
+            };
+
+            Some(InstallationInfo {
+                installation_path: PathBuf::from(string("installationPath")?),
+                installation_version: string("installationVersion")?,
+                display_name: optional_string("displayName")?,
+                product_id: optional_string("productId")?,
+                channel_id: optional_string("channelId")?,
+                instance_id: string("instanceId")?,
+                is_prerelease: !is_legacy && installation.get("isPrerelease")?.as_bool()?,
+                product_line_version: if is_legacy {
+                    String::new()
+                } else {
+                    installation
+                        .get("catalog")?
+                        .get("productLineVersion")?
+                        .as_str()?
+                        .to_owned()
+                },
+                is_complete: installation
+                    .get("isComplete")
+                    .map_or(Some(true), Json::as_bool)?,
+                is_legacy,
+            })
+        })
+        .collect::<Option<_>>()
+        .ok_or_else(invalid)
+}
+
+/// A JSON value, only as far as needed for the `vswhere.exe` output.
+#[derive(Debug)]
+enum Json {
+    Null,
+    Bool(bool),
+    Number,
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn parse(text: &str) -> Option<Self> {
+        let mut chars = text.chars().peekable();
+
+        let value = parse_value(&mut chars)?;
+        skip_whitespace(&mut chars);
+
+        chars.next().is_none().then_some(value)
+    }
+
+    fn get(&self, key: &str) -> Option<&Self> {
+        match self {
+            Self::Object(members) => members
+                .iter()
+                .find(|(member_key, _)| member_key == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(string) => Some(string),
+            _ => None,
+        }
+    }
+
+    fn as_bool(&self) -> Option<bool> {
+        match self {
+            Self::Bool(bool) => Some(*bool),
+            _ => None,
+        }
+    }
+}
+
+fn skip_whitespace(chars: &mut Peekable<Chars<'_>>) {
+    while chars.next_if(char::is_ascii_whitespace).is_some() {}
+}
+
+fn parse_value(chars: &mut Peekable<Chars<'_>>) -> Option<Json> {
+    skip_whitespace(chars);
+
+    match *chars.peek()? {
+        '{' => {
+            chars.next();
+            let mut members = Vec::new();
+
+            skip_whitespace(chars);
+            if chars.next_if_eq(&'}').is_some() {
+                return Some(Json::Object(members));
+            }
+
+            loop {
+                skip_whitespace(chars);
+                let key = parse_string(chars)?;
+                skip_whitespace(chars);
+                chars.next_if_eq(&':')?;
+                members.push((key, parse_value(chars)?));
+
+                skip_whitespace(chars);
+                match chars.next()? {
+                    ',' => {}
+                    '}' => return Some(Json::Object(members)),
+                    _ => return None,
+                }
+            }
+        }
+        '[' => {
+            chars.next();
+            let mut elements = Vec::new();
+
+            skip_whitespace(chars);
+            if chars.next_if_eq(&']').is_some() {
+                return Some(Json::Array(elements));
+            }
+
+            loop {
+                elements.push(parse_value(chars)?);
+
+                skip_whitespace(chars);
+                match chars.next()? {
+                    ',' => {}
+                    ']' => return Some(Json::Array(elements)),
+                    _ => return None,
+                }
+            }
+        }
+        '"' => parse_string(chars).map(Json::String),
+        't' => parse_literal(chars, "true", Json::Bool(true)),
+        'f' => parse_literal(chars, "false", Json::Bool(false)),
+        'n' => parse_literal(chars, "null", Json::Null),
+        '-' | '0'..='9' => {
+            let mut number = String::new();
+            while let Some(char) =
+                chars.next_if(|char| char.is_ascii_digit() || "+-.eE".contains(*char))
+            {
+                number.push(char);
+            }
+
+            number.parse::<f64>().is_ok().then_some(Json::Number)
+        }
+        _ => None,
+    }
+}
+
+fn parse_literal(chars: &mut Peekable<Chars<'_>>, literal: &str, value: Json) -> Option<Json> {
+    for expected_char in literal.chars() {
+        chars.next_if_eq(&expected_char)?;
+    }
+
+    Some(value)
+}
+
+fn parse_string(chars: &mut Peekable<Chars<'_>>) -> Option<String> {
+    chars.next_if_eq(&'"')?;
+    let mut string = String::new();
+
+    loop {
+        match chars.next()? {
+            '"' => return Some(string),
+            '\\' => string.push(match chars.next()? {
+                '"' => '"',
+                '\\' => '\\',
+                '/' => '/',
+                'b' => '\u{8}',
+                'f' => '\u{c}',
+                'n' => '\n',
+                'r' => '\r',
+                't' => '\t',
+                'u' => {
+                    let code_unit = parse_code_unit(chars)?;
+
+                    if (0xD800..0xDC00).contains(&code_unit) {
+                        // A surrogate pair.
+                        chars.next_if_eq(&'\\')?;
+                        chars.next_if_eq(&'u')?;
+                        let low_code_unit = parse_code_unit(chars)?;
+                        if !(0xDC00..0xE000).contains(&low_code_unit) {
+                            return None;
+                        }
+
+                        char::from_u32(
+                            0x10000 + ((code_unit - 0xD800) << 10) + (low_code_unit - 0xDC00),
+                        )?
+                    } else {
+                        char::from_u32(code_unit)?
+                    }
+                }
+                _ => return None,
+            }),
+            char if char < ' ' => return None,
+            char => string.push(char),
+        }
+    }
+}
+
+fn parse_code_unit(chars: &mut Peekable<Chars<'_>>) -> Option<u32> {
+    let mut code_unit = 0;
+
+    for _ in 0..4 {
+        code_unit = code_unit * 16 + chars.next()?.to_digit(16)?;
+    }
+
+    Some(code_unit)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env,
+        ffi::OsString,
+        fs,
+        os::windows::process::ExitStatusExt,
+        path::PathBuf,
+        process::{ExitStatus, Output},
+    };
+
+    use serial_test::serial;
+
+    use super::{
+        decode_with_code_page, installations_from_output, installer_vswhere_paths_from,
+        parse_installations, program_files_x86_dirs_from, version_from_header, vswhere_path,
+        InstallationInfo,
+    };
+    use crate::VcvarsError;
+
+    #[test]
+    fn installations() {
+        let json = r#"[
+  {
+    "instanceId": "1a2b3c4d",
+    "installationPath": "C:\\Program Files\\Microsoft Visual Studio\\2022\\Preview",
+    "installationVersion": "17.9.34310.174",
+    "isPrerelease": true,
+    "isComplete": false,
+    "displayName": "Visual Studio Community 2022 \u00ABPreview\u00BB \ud83d\ude80",
+    "productId": "Microsoft.VisualStudio.Product.Community",
+    "channelId": "VisualStudio.17.Preview",
+    "state": 4294967295,
+    "launchParams": {"arguments": null, "fileName": "Common7\/IDE\/devenv.exe"},
+    "catalog": {"productLineVersion": "2022", "productSemanticVersion": "17.9.0-pre.1.0+34310.174"},
+    "properties": {}
+  }
+]
+"#;
+
+        assert_eq!(
+            parse_installations(json).unwrap(),
+            [InstallationInfo {
+                installation_path: PathBuf::from(
+                    r"C:\Program Files\Microsoft Visual Studio\2022\Preview"
+                ),
+                installation_version: "17.9.34310.174".to_owned(),
+                display_name: "Visual Studio Community 2022 «Preview» 🚀".to_owned(),
+                product_id: "Microsoft.VisualStudio.Product.Community".to_owned(),
+                channel_id: "VisualStudio.17.Preview".to_owned(),
+                instance_id: "1a2b3c4d".to_owned(),
+                is_prerelease: true,
+                product_line_version: "2022".to_owned(),
+                is_complete: false,
+                is_legacy: false,
+            }]
+        );
+
+        let legacy_json = r#"[{"instanceId": "VisualStudio.14.0", "installationPath": "C:\\Program Files (x86)\\Microsoft Visual Studio 14.0\\", "installationVersion": "14.0"}]"#;
+        let legacy_installation = &parse_installations(legacy_json).unwrap()[0];
+        assert!(legacy_installation.is_legacy);
+        assert_eq!(legacy_installation.installation_version, "14.0");
+        assert!(legacy_installation.display_name.is_empty());
+
+        assert!(parse_installations(" []\r\n").unwrap().is_empty());
+
+        for invalid in ["", "[", "[{}]", "{}", "[] x", r#"["\x"]"#, "[1.2.3]"] {
+            assert!(
+                matches!(
+                    parse_installations(invalid),
+                    Err(VcvarsError::InvalidVswhereOutput(raw)) if raw == invalid
+                ),
+                "{invalid}"
+            );
+        }
+    }
+
+    #[test]
+    fn no_installations() {
+        let args = "-prerelease -products * -latest -format json -utf8";
+
+        for (stdout, stderr) in [(&b" \r\n"[..], &b""[..]), (b"[]", b"Warning\r\n")] {
+            let output = Output {
+                status: ExitStatus::from_raw(0),
+                stdout: stdout.to_vec(),
+                stderr: stderr.to_vec(),
+            };
+
+            assert!(matches!(
+                installations_from_output(args, &output),
+                Err(VcvarsError::NoVisualStudioFound(error_args, error_stderr))
+                    if error_args == args && error_stderr.as_bytes() == stderr.trim_ascii()
+            ));
+        }
+    }
+
+    #[test]
+    fn failure() {
+        let args = "-version [16.0,18.0";
+
+        for (exit_code, stdout) in [(2, &b""[..]), (0, b"\r\n")] {
+            let output = Output {
+                status: ExitStatus::from_raw(exit_code),
+                stdout: stdout.to_vec(),
+                stderr: b"Error 0x57: Version \"[16.0,18.0\" is not a valid version range\r\n"
+                    .to_vec(),
+            };
+
+            assert!(matches!(
+                installations_from_output(args, &output),
+                Err(VcvarsError::VswhereFailed { exit_code: Some(code), stderr, args: error_args })
+                    if code == i32::try_from(exit_code).unwrap() && stderr.starts_with("Error 0x57") && error_args == args
+            ));
+        }
+    }
+
+    #[test]
+    fn version() {
+        assert_eq!(
+            version_from_header("Visual Studio Locator version 2.8.4+ff1bd2e9e5 [query version 3.8.2133.33218]\r\nCopyright (C) Microsoft Corporation. All rights reserved.\r\n").as_deref(),
+            Some("2.8.4")
+        );
+        assert_eq!(
+            version_from_header(
+                "Visual Studio Locator Version 2.5.2+gebb9f26a3d [Abfrageversion 1.16.1247.518]"
+            )
+            .as_deref(),
+            Some("2.5.2")
+        );
+        assert_eq!(version_from_header("Usage: vswhere.exe [options]"), None);
+        assert_eq!(version_from_header(""), None);
+    }
+
+    #[test]
+    fn decoding() {
+        const CP_UTF8: u32 = 65001;
+        const OEM_UNITED_STATES: u32 = 437;
+        const SHIFT_JIS: u32 = 932;
+
+        let utf8_path = r"C:\Users\José\日本\VS";
+        assert_eq!(
+            decode_with_code_page(utf8_path.as_bytes(), OEM_UNITED_STATES).unwrap(),
+            utf8_path
+        );
+
+        assert_eq!(
+            decode_with_code_page(b"C:\\Users\\Jos\x82", OEM_UNITED_STATES).unwrap(),
+            r"C:\Users\José"
+        );
+        assert_eq!(
+            decode_with_code_page(b"C:\\\x93\xfa\x96\x7b", SHIFT_JIS).unwrap(),
+            r"C:\日本"
+        );
+
+        let invalid_bytes = b"C:\\\xff\xfe";
+        let err = decode_with_code_page(invalid_bytes, CP_UTF8).unwrap_err();
+        assert!(
+            matches!(&err, VcvarsError::UndecodableVswhereOutput(bytes) if bytes == invalid_bytes),
+            "{err}"
+        );
+        assert!(err.to_string().ends_with("43 3a 5c ff fe"), "{err}");
+    }
+
+    #[test]
+    fn program_files_x86_dirs() {
+        let dirs = |vars: &[(&str, &str)]| {
+            program_files_x86_dirs_from(|name| {
+                vars.iter()
+                    .find(|(key, _)| *key == name)
+                    .map(|(_, value)| OsString::from(value))
+            })
+        };
+        let paths = |paths: &[&str]| paths.iter().map(PathBuf::from).collect::<Vec<_>>();
+
+        // 64-bit process on 64-bit Windows.
+        assert_eq!(
+            dirs(&[
+                ("PROGRAMFILES(X86)", r"C:\Program Files (x86)"),
+                ("ProgramW6432", r"C:\Program Files"),
+                ("ProgramFiles", r"C:\Program Files"),
+            ]),
+            paths(&[r"C:\Program Files (x86)"])
+        );
+
+        // Stripped environment.
+        assert_eq!(dirs(&[]), paths(&[r"C:\Program Files (x86)"]));
+
+        // `PROGRAMFILES(X86)` missing, with Windows on another drive.
+        assert_eq!(
+            dirs(&[
+                ("ProgramW6432", r"D:\Program Files"),
+                ("ProgramFiles", r"D:\Program Files"),
+            ]),
+            paths(&[r"D:\Program Files (x86)", r"C:\Program Files (x86)"])
+        );
+
+        // 32-bit process on 64-bit Windows, whose `ProgramFiles` is the 32-bit directory.
+        assert_eq!(
+            dirs(&[
+                ("ProgramW6432", r"D:\Program Files"),
+                ("ProgramFiles", r"D:\Program Files (x86)"),
+            ]),
+            paths(&[r"D:\Program Files (x86)", r"C:\Program Files (x86)"])
+        );
+
+        // 32-bit Windows.
+        assert_eq!(
+            dirs(&[("ProgramFiles", r"D:\Program Files\")]),
+            paths(&[
+                r"D:\Program Files (x86)",
+                r"D:\Program Files\",
+                r"C:\Program Files (x86)",
+            ])
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn per_user_installer_dir() {
+        assert_eq!(
+            installer_vswhere_paths_from(|name| match name {
+                "PROGRAMFILES(X86)" => Some(OsString::from(r"C:\Program Files (x86)")),
+                "LOCALAPPDATA" => Some(OsString::from(r"C:\Users\user\AppData\Local")),
+                _ => None,
+            }),
+            [
+                PathBuf::from(
+                    r"C:\Program Files (x86)\Microsoft Visual Studio\Installer\vswhere.exe"
+                ),
+                PathBuf::from(
+                    r"C:\Users\user\AppData\Local\Microsoft\VisualStudio\Installer\vswhere.exe"
+                ),
+            ]
+        );
+
+        let local_app_data_dir = PathBuf::from(env!("OUT_DIR")).join("per_user_installer_dir");
+        let stub_path = local_app_data_dir.join(r"Microsoft\VisualStudio\Installer\vswhere.exe");
+        fs::create_dir_all(stub_path.parent().unwrap()).unwrap();
+        fs::write(&stub_path, "").unwrap();
+
+        let previous_value = env::var_os("LOCALAPPDATA");
+        env::set_var("LOCALAPPDATA", &local_app_data_dir);
+        let path = vswhere_path(None);
+        match previous_value {
+            Some(value) => env::set_var("LOCALAPPDATA", value),
+            None => env::remove_var("LOCALAPPDATA"),
+        }
+
+        // The per-user stub is only used without a machine-wide `vswhere.exe`.
+        let machine_wide_exists = installer_vswhere_paths_from(|name| {
+            (name != "LOCALAPPDATA")
+                .then(|| env::var_os(name))
+                .flatten()
+        })
+        .iter()
+        .any(|path| path.is_file());
+        assert_eq!(path.unwrap() == stub_path, !machine_wide_exists);
+    }
+}